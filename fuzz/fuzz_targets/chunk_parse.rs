@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngcheck::png::{Chunk, ChunkType};
+
+/// Every chunk type with a parser, plus an unknown one.
+const CHUNK_TYPES: [&[u8; 4]; 19] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"pHYs", b"sRGB", b"gAMA", b"bKGD", b"sBIT",
+    b"iTXt", b"tEXt", b"zTXt", b"iCCP", b"hIST", b"cHRM", b"acTL", b"fcTL", b"prVt",
+];
+
+// The first byte picks the chunk type, the rest is its data
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    let chunk_type = CHUNK_TYPES[selector as usize % CHUNK_TYPES.len()];
+    let chunk = Chunk::from_data(ChunkType::from(*chunk_type), data.to_vec());
+
+    let _ = chunk.parse().to_string();
+    let _ = chunk.data_fields();
+});
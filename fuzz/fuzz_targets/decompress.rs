@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngcheck::png::compression::{decompress, decompress_raw, zlib_stream_len};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress(data, 1 << 20);
+    let _ = decompress_raw(data, 1 << 20);
+    let _ = zlib_stream_len(data, 1 << 20);
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngcheck::parse_bytes_with_limits;
+use pngcheck::png::Limits;
+
+fuzz_target!(|data: &[u8]| {
+    let limits = Limits {
+        max_decompressed_size: 1 << 20,
+        max_text_size: 1 << 16,
+        ..Limits::default()
+    };
+    let Ok(png) = parse_bytes_with_limits(data, limits) else {
+        return;
+    };
+
+    for chunk in &png.chunks {
+        let _ = chunk.parse_with_limits(&png.limits).to_string();
+        let _ = chunk.data_fields();
+    }
+    let _ = png.get_raw_buffer();
+    let _ = png.get_buffer();
+    let _ = png.get_scanlines();
+    let _ = png.frame_controls();
+});
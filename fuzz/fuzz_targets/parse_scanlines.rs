@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngcheck::png::scanline::parse_scanlines;
+use pngcheck::png::{Chunk, ChunkType, ParsedChunk, PLTE};
+
+// The first 13 bytes are the IHDR data, the rest is decompressed image data
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 13 {
+        return;
+    }
+    let (header, data) = data.split_at(13);
+    let ParsedChunk::IHDR(ihdr) =
+        Chunk::from_data(ChunkType::from(*b"IHDR"), header.to_vec()).parse()
+    else {
+        return;
+    };
+    // Scanlines are allocated up front, so keep images small
+    if ihdr.width as u64 * ihdr.height as u64 > 1 << 16 {
+        return;
+    }
+
+    let plte = PLTE {
+        entries: (0..=255).map(|i| (i, i, i)).collect(),
+        transparency: None,
+    };
+    let _ = parse_scanlines(&ihdr, Some(&plte), data);
+});
@@ -1,91 +1,114 @@
 use std::collections::HashMap;
 
-use crate::png::Pixel;
+use crate::png::PixelBuffer;
 use crate::png::Png;
 
+/// Value streams built from the channels of color images. Grayscale images have none.
 pub fn analyze(png: &Png) -> Result<HashMap<String, Vec<u8>>, Box<dyn std::error::Error>> {
-    let pixels = png.get_pixels()?.to_vec();
+    // Indexed images are expanded to RGBA, so they are analyzed like truecolor with alpha
+    let buffer = png.get_buffer()?.to_8bit();
+    Ok(analyze_buffer(&buffer))
+}
 
-    match png.color_type() {
-        crate::png::ColorType::Grayscale => {
-            println!("Grayscale color type not supported");
-        }
-        crate::png::ColorType::Truecolor => {
-            return Ok(analyze_truecolor(&pixels));
-        }
-        crate::png::ColorType::GrayscaleAlpha => {
-            println!("GrayscaleAlpha color type not supported");
-        }
-        crate::png::ColorType::TruecolorAlpha => {
-            return Ok(analyze_truecolor_alpha(&pixels));
-        }
-        crate::png::ColorType::Indexed => {
-            panic!("Indexed color type not supported");
-        }
+/// Turns one of the value streams of `analyze` back into an image with one value per pixel, so
+/// patterns in channel combinations become visible. `rgb` gives a color image.
+pub fn visualize(png: &Png, key: &str) -> Result<PixelBuffer, Box<dyn std::error::Error>> {
+    let buffer = png.get_buffer()?.to_8bit();
+    let values = analyze_buffer(&buffer);
+
+    if values.is_empty() {
+        return Err("Visualizations are only available for color images".into());
     }
+    let Some(data) = values.get(key) else {
+        let mut keys: Vec<_> = values.keys().map(String::as_str).collect();
+        keys.sort();
+        return Err(format!(
+            "Unknown visualization {}, expected one of: {}",
+            key,
+            keys.join(", ")
+        )
+        .into());
+    };
+
+    let pixels = buffer.width as usize * buffer.height as usize;
+    let channels = data.len().checked_div(pixels).unwrap_or(1);
+    Ok(PixelBuffer::from_data(
+        buffer.width,
+        buffer.height,
+        channels as u8,
+        8,
+        data.clone(),
+    ))
+}
 
-    Ok(HashMap::new())
+fn analyze_buffer(buffer: &PixelBuffer) -> HashMap<String, Vec<u8>> {
+    match buffer.channels {
+        3 => analyze_truecolor(buffer),
+        4 => analyze_truecolor_alpha(buffer),
+        _ => HashMap::new(),
+    }
 }
 
-fn analyze_truecolor(pixels: &Vec<Pixel>) -> HashMap<String, Vec<u8>> {
+fn analyze_truecolor(buffer: &PixelBuffer) -> HashMap<String, Vec<u8>> {
     let mut rgb_values: HashMap<String, Vec<u8>> = HashMap::new();
 
-    pixels.iter().for_each(|pixel| match pixel {
-        Pixel::Truecolor(r, g, b) | Pixel::TruecolorAlpha(r, g, b, _) => {
-            let key = format!("r");
+    buffer
+        .data()
+        .chunks_exact(buffer.channels as usize)
+        .for_each(|pixel| {
+            let (r, g, b) = (&pixel[0], &pixel[1], &pixel[2]);
+
+            let key = String::from("r");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(*r);
 
-            let key = format!("g");
+            let key = String::from("g");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(*g);
 
-            let key = format!("b");
+            let key = String::from("b");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(*b);
 
-            let key = format!("r+g");
+            let key = String::from("r+g");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(r.wrapping_add(*g));
 
-            let key = format!("r+b");
+            let key = String::from("r+b");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(r.wrapping_add(*b));
 
-            let key = format!("g+b");
+            let key = String::from("g+b");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(g.wrapping_add(*b));
 
-            let key = format!("r+g+b");
+            let key = String::from("r+g+b");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(r.wrapping_add(*g).wrapping_add(*b));
 
-            let key = format!("rgb");
+            let key = String::from("rgb");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(*r);
             count.push(*g);
             count.push(*b);
-        }
-        _ => {}
-    });
+        });
 
     rgb_values
 }
 
-fn analyze_truecolor_alpha(pixels: &Vec<Pixel>) -> HashMap<String, Vec<u8>> {
-    let mut rgb_values: HashMap<String, Vec<u8>> = analyze_truecolor(pixels);
+fn analyze_truecolor_alpha(buffer: &PixelBuffer) -> HashMap<String, Vec<u8>> {
+    let mut rgb_values: HashMap<String, Vec<u8>> = analyze_truecolor(buffer);
 
-    pixels.iter().for_each(|pixel| match pixel {
-        Pixel::TruecolorAlpha(r, g, b, a) => {
-            let key = format!("a");
+    buffer.data().chunks_exact(4).for_each(|pixel| {
+        if let [r, g, b, a] = pixel {
+            let key = String::from("a");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(*a);
 
-            let key = format!("r+g+b+a");
+            let key = String::from("r+g+b+a");
             let count = rgb_values.entry(key).or_insert(vec![]);
             count.push(r.wrapping_add(*g).wrapping_add(*b).wrapping_add(*a));
         }
-        _ => {}
     });
 
     rgb_values
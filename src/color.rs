@@ -0,0 +1,284 @@
+use crate::png::{Chrm, Png};
+
+type Matrix = [[f64; 3]; 3];
+
+/// D65, the sRGB white point.
+const SRGB_WHITE: (f64, f64) = (0.3127, 0.3290);
+
+const XYZ_TO_SRGB: Matrix = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+const BRADFORD: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transfer {
+    Srgb,
+    /// Exponent that turns stored samples into linear light
+    Gamma(f64),
+}
+
+impl Transfer {
+    fn decode(&self, value: f64) -> f64 {
+        match *self {
+            Transfer::Srgb if value <= 0.04045 => value / 12.92,
+            Transfer::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            Transfer::Gamma(exponent) => value.powf(exponent),
+        }
+    }
+}
+
+/// How the samples stored in a PNG map to sRGB.
+///
+/// An `sRGB` chunk takes precedence over everything else. Otherwise `gAMA` and `cHRM` are used;
+/// ICC profiles are not interpreted, which the PNG specification allows as long as decoders fall
+/// back to `gAMA` and `cHRM`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorSpace {
+    transfer: Transfer,
+    /// Linear RGB to linear sRGB, `None` when the primaries are sRGB's
+    matrix: Option<Matrix>,
+    /// The chunks the conversion is based on
+    pub source: String,
+}
+
+impl ColorSpace {
+    pub fn srgb() -> Self {
+        Self {
+            transfer: Transfer::Srgb,
+            matrix: None,
+            source: String::from("sRGB (assumed)"),
+        }
+    }
+
+    pub fn from_png(png: &Png) -> Self {
+        if png.srgb().is_some() {
+            return Self {
+                source: String::from("sRGB chunk"),
+                ..Self::srgb()
+            };
+        }
+
+        let mut sources = vec![];
+        if let Some(iccp) = png.iccp() {
+            sources.push(format!(
+                "iCCP profile \"{}\" (not interpreted)",
+                iccp.profile_name
+            ));
+        }
+
+        let transfer = match png.gama() {
+            Some(gama) if gama > 0 => {
+                sources.push(format!("gAMA {:.5}", gama as f64 / 100000.0));
+                Transfer::Gamma(100000.0 / gama as f64)
+            }
+            _ => Transfer::Srgb,
+        };
+
+        let matrix = png
+            .chrm()
+            .and_then(|chrm| chromaticity_matrix(&chrm))
+            .filter(|matrix| !is_identity(matrix));
+        if png.chrm().is_some() {
+            sources.push(String::from("cHRM"));
+        }
+
+        Self {
+            transfer,
+            matrix,
+            source: if sources.is_empty() {
+                String::from("sRGB (assumed)")
+            } else {
+                sources.join(", ")
+            },
+        }
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        self.transfer == Transfer::Srgb && self.matrix.is_none()
+    }
+
+    /// Converts normalized RGB samples to normalized sRGB samples.
+    pub fn to_srgb(&self, rgb: [f64; 3]) -> [f64; 3] {
+        if self.is_srgb() {
+            return rgb;
+        }
+
+        let linear = rgb.map(|value| self.transfer.decode(value));
+        let linear = match &self.matrix {
+            Some(matrix) => multiply(matrix, linear),
+            None => linear,
+        };
+
+        linear.map(|value| encode_srgb(value.clamp(0.0, 1.0)))
+    }
+}
+
+fn encode_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Builds the linear RGB to linear sRGB matrix for the primaries and white point in `cHRM`,
+/// adapting the white point to D65. Returns `None` for degenerate chromaticities.
+fn chromaticity_matrix(chrm: &Chrm) -> Option<Matrix> {
+    let xy = |x: u32, y: u32| (x as f64 / 100000.0, y as f64 / 100000.0);
+    let white = xy(chrm.white_point_x, chrm.white_point_y);
+    let primaries = [
+        xy(chrm.red_x, chrm.red_y),
+        xy(chrm.green_x, chrm.green_y),
+        xy(chrm.blue_x, chrm.blue_y),
+    ];
+
+    let rgb_to_xyz = rgb_to_xyz(primaries, white)?;
+    let adaptation = bradford(white, SRGB_WHITE)?;
+
+    Some(multiply_matrices(
+        &XYZ_TO_SRGB,
+        &multiply_matrices(&adaptation, &rgb_to_xyz),
+    ))
+}
+
+fn xyz((x, y): (f64, f64)) -> Option<[f64; 3]> {
+    if y <= 0.0 {
+        return None;
+    }
+
+    Some([x / y, 1.0, (1.0 - x - y) / y])
+}
+
+fn rgb_to_xyz(primaries: [(f64, f64); 3], white: (f64, f64)) -> Option<Matrix> {
+    let columns = [xyz(primaries[0])?, xyz(primaries[1])?, xyz(primaries[2])?];
+    let mut matrix = [[0.0; 3]; 3];
+    for (i, row) in matrix.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = columns[j][i];
+        }
+    }
+
+    // Scale each primary so that full intensity on all three gives the white point
+    let scale = multiply(&invert(&matrix)?, xyz(white)?);
+    for row in matrix.iter_mut() {
+        for (value, scale) in row.iter_mut().zip(scale) {
+            *value *= scale;
+        }
+    }
+
+    Some(matrix)
+}
+
+fn bradford(source: (f64, f64), destination: (f64, f64)) -> Option<Matrix> {
+    let source = multiply(&BRADFORD, xyz(source)?);
+    let destination = multiply(&BRADFORD, xyz(destination)?);
+
+    let mut scale = [[0.0; 3]; 3];
+    for i in 0..3 {
+        scale[i][i] = destination[i] / source[i];
+    }
+
+    Some(multiply_matrices(
+        &invert(&BRADFORD)?,
+        &multiply_matrices(&scale, &BRADFORD),
+    ))
+}
+
+fn multiply(matrix: &Matrix, vector: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+fn multiply_matrices(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor =
+        |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let determinant = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+        + m[0][2] * cofactor(1, 2, 0, 1);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+
+    let adjugate = [
+        [
+            cofactor(1, 2, 1, 2),
+            -cofactor(0, 2, 1, 2),
+            cofactor(0, 1, 1, 2),
+        ],
+        [
+            -cofactor(1, 2, 0, 2),
+            cofactor(0, 2, 0, 2),
+            -cofactor(0, 1, 0, 2),
+        ],
+        [
+            cofactor(1, 2, 0, 1),
+            -cofactor(0, 2, 0, 1),
+            cofactor(0, 1, 0, 1),
+        ],
+    ];
+
+    Some(adjugate.map(|row| row.map(|value| value / determinant)))
+}
+
+fn is_identity(matrix: &Matrix) -> bool {
+    (0..3).all(|i| (0..3).all(|j| (matrix[i][j] - if i == j { 1.0 } else { 0.0 }).abs() < 1e-3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_chromaticities_are_identity() {
+        let chrm = Chrm {
+            white_point_x: 31270,
+            white_point_y: 32900,
+            red_x: 64000,
+            red_y: 33000,
+            green_x: 30000,
+            green_y: 60000,
+            blue_x: 15000,
+            blue_y: 6000,
+        };
+
+        assert!(is_identity(&chromaticity_matrix(&chrm).unwrap()));
+    }
+
+    #[test]
+    fn test_linear_gamma() {
+        let color_space = ColorSpace {
+            transfer: Transfer::Gamma(1.0),
+            matrix: None,
+            source: String::from("gAMA 1.00000"),
+        };
+
+        let [r, g, b] = color_space.to_srgb([0.5, 0.0, 1.0]);
+
+        assert!((r - 0.7354).abs() < 1e-3);
+        assert_eq!(g, 0.0);
+        assert!((b - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_invert() {
+        let inverse = invert(&BRADFORD).unwrap();
+
+        assert!(is_identity(&multiply_matrices(&BRADFORD, &inverse)));
+        assert_eq!(invert(&[[1.0, 2.0, 3.0]; 3]), None);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::png::{Chunk, ChunkField, ChunkType, PixelBuffer, Png};
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+
+/// How a chunk of one file relates to the other. Chunks are paired by type and occurrence, so
+/// the second tEXt chunk on the left is compared with the second tEXt chunk on the right.
+#[derive(Debug, PartialEq)]
+pub enum ChunkDiff {
+    Same {
+        left: usize,
+        right: usize,
+    },
+    Changed {
+        left: usize,
+        right: usize,
+        fields: Vec<FieldDiff>,
+    },
+    Removed {
+        left: usize,
+    },
+    Added {
+        right: usize,
+    },
+}
+
+/// A changed field, or with `None` on one side a field only the other chunk has.
+#[derive(Debug, PartialEq)]
+pub struct FieldDiff {
+    pub name: String,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+pub fn diff_chunks(left: &[Chunk], right: &[Chunk]) -> Vec<ChunkDiff> {
+    let occurrences = |chunks: &[Chunk]| {
+        let mut seen: HashMap<ChunkType, usize> = HashMap::new();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let occurrence = seen.entry(chunk.chunk_type).or_default();
+                *occurrence += 1;
+                ((chunk.chunk_type, *occurrence), i)
+            })
+            .collect::<Vec<_>>()
+    };
+    let right_occurrences = occurrences(right);
+    let right_index: HashMap<_, _> = right_occurrences.iter().copied().collect();
+
+    let mut matched = vec![false; right.len()];
+    let mut diffs: Vec<ChunkDiff> = occurrences(left)
+        .into_iter()
+        .map(|(key, l)| match right_index.get(&key) {
+            Some(&r) => {
+                matched[r] = true;
+                let fields = diff_fields(&left[l], &right[r]);
+                if fields.is_empty() {
+                    ChunkDiff::Same { left: l, right: r }
+                } else {
+                    ChunkDiff::Changed {
+                        left: l,
+                        right: r,
+                        fields,
+                    }
+                }
+            }
+            None => ChunkDiff::Removed { left: l },
+        })
+        .collect();
+
+    diffs.extend(
+        (0..right.len())
+            .filter(|&r| !matched[r])
+            .map(|right| ChunkDiff::Added { right }),
+    );
+
+    diffs
+}
+
+/// Compares the length, CRC and named fields of two chunks of the same type. Chunks without a
+/// field layout get a single `data` entry when their data differs. Fields are paired by name
+/// and occurrence, and those only one of the chunks has, such as an empty keyword, are reported
+/// as added or removed.
+fn diff_fields(left: &Chunk, right: &Chunk) -> Vec<FieldDiff> {
+    let mut diffs = vec![];
+    let mut push = |name: &str, left: Option<String>, right: Option<String>| {
+        if left != right {
+            diffs.push(FieldDiff {
+                name: name.to_string(),
+                left,
+                right,
+            })
+        }
+    };
+
+    push(
+        "length",
+        Some(left.length.to_string()),
+        Some(right.length.to_string()),
+    );
+
+    let left_data = left.data.as_deref().unwrap_or_default();
+    let right_data = right.data.as_deref().unwrap_or_default();
+    let left_fields = left.data_fields();
+    let right_fields = right.data_fields();
+    if left_fields.is_empty() || right_fields.is_empty() {
+        if left_data != right_data {
+            push(
+                "data",
+                Some(format!("{} bytes", left_data.len())),
+                Some(format!("{} bytes", right_data.len())),
+            );
+        }
+    } else {
+        let occurrences = |fields: &[ChunkField], data: &[u8]| {
+            let mut seen: HashMap<&str, usize> = HashMap::new();
+            fields
+                .iter()
+                .map(|field| {
+                    let occurrence = seen.entry(field.name).or_default();
+                    *occurrence += 1;
+                    let value = field_value(&data[field.range.clone()]);
+                    ((field.name, *occurrence), value)
+                })
+                .collect::<Vec<_>>()
+        };
+        let left_values = occurrences(&left_fields, left_data);
+        let right_values = occurrences(&right_fields, right_data);
+        let left_index: HashMap<_, _> = left_values.iter().cloned().collect();
+        let right_index: HashMap<_, _> = right_values.iter().cloned().collect();
+
+        for (key, value) in left_values {
+            push(key.0, Some(value), right_index.get(&key).cloned());
+        }
+        for (key, value) in right_values {
+            if !left_index.contains_key(&key) {
+                push(key.0, None, Some(value));
+            }
+        }
+    }
+
+    push(
+        "crc",
+        Some(format!("{:08x}", left.crc)),
+        Some(format!("{:08x}", right.crc)),
+    );
+
+    diffs
+}
+
+/// Shows short fields as numbers, printable ones as text and anything else as hex.
+fn field_value(bytes: &[u8]) -> String {
+    let text = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+    match bytes.len() {
+        1 => bytes[0].to_string(),
+        2 => u16::from_be_bytes([bytes[0], bytes[1]]).to_string(),
+        4 => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]).to_string(),
+        _ if text.iter().all(|b| b.is_ascii_graphic() || *b == b' ') => {
+            format!("{:?}", String::from_utf8_lossy(text))
+        }
+        len if len > 32 => format!("{} bytes: {:02x?}...", len, &bytes[..32]),
+        _ => format!("{:02x?}", bytes),
+    }
+}
+
+/// Sample-wise comparison of two decoded images of the same size.
+#[derive(Debug)]
+pub struct PixelDiff {
+    pub width: u32,
+    pub height: u32,
+    pub changed_pixels: u64,
+    /// Per channel: the number of differing samples and the largest difference
+    pub channels: Vec<ChannelDiff>,
+    changed: Vec<bool>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChannelDiff {
+    pub changed_samples: u64,
+    pub max_delta: u16,
+}
+
+impl PixelDiff {
+    pub fn is_changed(&self, x: u32, y: u32) -> bool {
+        self.changed[y as usize * self.width as usize + x as usize]
+    }
+}
+
+/// Compares decoded pixels. Images with different channels or bit depths are compared as 8-bit
+/// RGBA.
+pub fn diff_pixels(left: &Png, right: &Png) -> Result<PixelDiff, Box<dyn std::error::Error>> {
+    let mut left = left.get_buffer()?;
+    let mut right = right.get_buffer()?;
+
+    if (left.width, left.height) != (right.width, right.height) {
+        return Err(format!(
+            "Image sizes differ: {}x{} and {}x{}",
+            left.width, left.height, right.width, right.height
+        )
+        .into());
+    }
+
+    if (left.channels, left.bit_depth) != (right.channels, right.bit_depth) {
+        let rgba = |buffer: &PixelBuffer| {
+            PixelBuffer::from_data(
+                buffer.width,
+                buffer.height,
+                4,
+                8,
+                buffer.to_rgba8().into_raw(),
+            )
+        };
+        left = rgba(&left);
+        right = rgba(&right);
+    }
+
+    let mut diff = PixelDiff {
+        width: left.width,
+        height: left.height,
+        changed_pixels: 0,
+        channels: vec![ChannelDiff::default(); left.channels as usize],
+        changed: vec![false; left.width as usize * left.height as usize],
+    };
+
+    for y in 0..left.height {
+        for x in 0..left.width {
+            let mut changed = false;
+            for (c, channel) in diff.channels.iter_mut().enumerate() {
+                let delta = left
+                    .sample(x, y, c as u8)
+                    .abs_diff(right.sample(x, y, c as u8));
+                if delta > 0 {
+                    changed = true;
+                    channel.changed_samples += 1;
+                    channel.max_delta = channel.max_delta.max(delta);
+                }
+            }
+            if changed {
+                diff.changed_pixels += 1;
+                diff.changed[y as usize * left.width as usize + x as usize] = true;
+            }
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Draws `base` dimmed to gray with the changed pixels in red.
+pub fn difference_image(base: &PixelBuffer, diff: &PixelDiff) -> RgbaImage {
+    let base = base.to_rgba8();
+    RgbaImage::from_fn(diff.width, diff.height, |x, y| {
+        if diff.is_changed(x, y) {
+            return Rgba([255, 0, 0, 255]);
+        }
+        let [r, g, b, _] = base.get_pixel(x, y).0;
+        let gray = ((r as u32 + g as u32 + b as u32) / 9) as u8;
+        Rgba([gray, gray, gray, 255])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::chunk;
+
+    #[test]
+    fn test_diff_chunks() {
+        let left = vec![
+            chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk(b"tEXt", b"Comment\0a"),
+            chunk(b"tIME", &[7, 232, 1, 1, 0, 0, 0]),
+        ];
+        let right = vec![
+            chunk(b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk(b"tEXt", b"Comment\0a"),
+            chunk(b"zTXt", b"Comment\0\0"),
+        ];
+
+        let diffs = diff_chunks(&left, &right);
+
+        assert!(matches!(&diffs[0], ChunkDiff::Changed { fields, .. }
+            if fields[0] == FieldDiff {
+                name: String::from("width"),
+                left: Some(String::from("1")),
+                right: Some(String::from("2")),
+            } && fields[1].name == "crc"));
+        assert_eq!(diffs[1], ChunkDiff::Same { left: 1, right: 1 });
+        assert_eq!(diffs[2], ChunkDiff::Removed { left: 2 });
+        assert_eq!(diffs[3], ChunkDiff::Added { right: 2 });
+    }
+
+    #[test]
+    fn test_diff_fields_different_layouts() {
+        let left = chunk(b"tEXt", b"Comment\0text");
+        let right = chunk(b"tEXt", b"\0text\0more");
+
+        let fields = diff_fields(&left, &right);
+
+        assert_eq!(
+            fields[1],
+            FieldDiff {
+                name: String::from("keyword"),
+                left: Some(String::from("\"Comment\"")),
+                right: None,
+            }
+        );
+        assert_eq!(fields[2].name, "text");
+        assert!(fields[2].left.is_some() && fields[2].right.is_some());
+        assert_eq!(fields[3].name, "crc");
+    }
+
+    #[test]
+    fn test_field_value() {
+        assert_eq!(field_value(&[0, 0, 1, 0]), "256");
+        assert_eq!(field_value(b"Comment\0"), "\"Comment\"");
+        assert_eq!(field_value(&[1, 2, 3]), "[01, 02, 03]");
+    }
+}
@@ -0,0 +1,274 @@
+use crate::png::{Chunk, ChunkType, ColorType, PixelBuffer, Png};
+use std::error::Error;
+
+/// Which bits of which samples carry a hidden stream. Bits are taken pixel by pixel in row
+/// order, and within a pixel channel by channel, then bit plane by bit plane, in the order
+/// given. This is the layout `search` reads its LSB layers with, so anything embedded with a
+/// layout can be found again with the same one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsbLayout {
+    pub channels: Vec<u8>,
+    /// Bit planes, 0 being the least significant
+    pub bit_planes: Vec<u8>,
+    /// Whether the first bit of each byte is its most significant one
+    pub msb_first: bool,
+}
+
+impl LsbLayout {
+    /// Bit 0 of every channel, most significant bit first.
+    pub fn all_channels(channels: u8) -> Self {
+        Self {
+            channels: (0..channels).collect(),
+            bit_planes: vec![0],
+            msb_first: true,
+        }
+    }
+
+    /// Number of whole bytes the layout holds in `buffer`.
+    pub fn capacity(&self, buffer: &PixelBuffer) -> usize {
+        buffer.width as usize * buffer.height as usize * self.channels.len() * self.bit_planes.len()
+            / 8
+    }
+
+    fn validate(&self, buffer: &PixelBuffer) -> Result<(), Box<dyn Error>> {
+        if let Some(channel) = self.channels.iter().find(|&&c| c >= buffer.channels) {
+            return Err(format!(
+                "Channel {} out of range, the image has {}",
+                channel, buffer.channels
+            )
+            .into());
+        }
+        if let Some(plane) = self.bit_planes.iter().find(|&&p| p >= buffer.bit_depth) {
+            return Err(format!(
+                "Bit plane {} out of range for {}-bit samples",
+                plane, buffer.bit_depth
+            )
+            .into());
+        }
+        if self.channels.is_empty() || self.bit_planes.is_empty() {
+            return Err("No channels or bit planes to embed in".into());
+        }
+        Ok(())
+    }
+
+    /// Positions of every bit in the stream, as pixel coordinates, channel and bit plane.
+    fn positions(&self, buffer: &PixelBuffer) -> impl Iterator<Item = (u32, u32, u8, u8)> + '_ {
+        let (width, height) = (buffer.width, buffer.height);
+        (0..height).flat_map(move |y| {
+            (0..width).flat_map(move |x| {
+                self.channels
+                    .iter()
+                    .flat_map(move |&c| self.bit_planes.iter().map(move |&plane| (x, y, c, plane)))
+            })
+        })
+    }
+}
+
+/// Reads the bits selected by `layout` and packs them into bytes, dropping a final partial byte.
+pub fn extract_lsb(buffer: &PixelBuffer, layout: &LsbLayout) -> Vec<u8> {
+    let bits = layout
+        .positions(buffer)
+        .map(|(x, y, c, plane)| (buffer.sample(x, y, c) >> plane) as u8 & 1);
+    pack_bits(bits, layout.msb_first)
+}
+
+/// Writes `payload` into the bits selected by `layout`, leaving the bits after it unchanged.
+pub fn embed_lsb(
+    buffer: &mut PixelBuffer,
+    layout: &LsbLayout,
+    payload: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    layout.validate(buffer)?;
+    let capacity = layout.capacity(buffer);
+    if payload.len() > capacity {
+        return Err(format!(
+            "Payload of {} bytes does not fit, the layout holds {}",
+            payload.len(),
+            capacity
+        )
+        .into());
+    }
+
+    let bits = payload.iter().flat_map(|&byte| {
+        (0..8).map(move |i| {
+            let shift = if layout.msb_first { 7 - i } else { i };
+            (byte >> shift) as u16 & 1
+        })
+    });
+    let positions: Vec<_> = layout.positions(buffer).take(payload.len() * 8).collect();
+    for ((x, y, c, plane), bit) in positions.into_iter().zip(bits) {
+        let sample = buffer.sample(x, y, c) & !(1 << plane) | bit << plane;
+        buffer.set_sample(x, y, c, sample);
+    }
+    Ok(())
+}
+
+pub(crate) fn pack_bits(bits: impl Iterator<Item = u8>, msb_first: bool) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut byte = 0;
+    let mut count = 0;
+    for bit in bits {
+        byte = if msb_first {
+            (byte << 1) | bit
+        } else {
+            byte | bit << count
+        };
+        count += 1;
+        if count == 8 {
+            bytes.push(byte);
+            byte = 0;
+            count = 0;
+        }
+    }
+    bytes
+}
+
+/// Where `embed` hides the payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Technique {
+    /// In sample bits of the image
+    Lsb(LsbLayout),
+    /// As the data of a new ancillary chunk, placed before IEND
+    Chunk(ChunkType),
+    /// After IEND
+    Trailing,
+    /// In the last IDAT chunk after the end of the zlib stream, where decoders stop reading
+    AfterZlib,
+}
+
+/// Hides `payload` in a copy of `png`. The result is a valid PNG that displays the same, apart
+/// from the changed sample bits of LSB embedding, which also stores the image non-interlaced.
+pub fn embed(png: &Png, payload: &[u8], technique: &Technique) -> Result<Png, Box<dyn Error>> {
+    if png.is_cgbi() {
+        return Err("Convert CgBI files with uncrush before embedding".into());
+    }
+
+    let mut output = png.clone();
+    match technique {
+        Technique::Lsb(layout) => {
+            // Only there do the stored samples match the 8-bit samples `search` reads
+            let ihdr = png.ihdr().ok_or("IHDR chunk not found")?;
+            if ihdr.bit_depth != 8 || matches!(ihdr.color_type, ColorType::Indexed) {
+                return Err("LSB embedding needs an 8-bit grayscale or truecolor image".into());
+            }
+            let mut buffer = png.get_raw_buffer()?;
+            embed_lsb(&mut buffer, layout, payload)?;
+            output = png.with_image_data(&buffer)?;
+        }
+        Technique::Chunk(chunk_type) => {
+            if !chunk_type.is_valid() || chunk_type.is_critical() {
+                return Err(format!("{} is not a valid ancillary chunk type", chunk_type).into());
+            }
+            let iend = output
+                .chunks
+                .iter()
+                .rposition(|chunk| chunk.chunk_type == "IEND")
+                .unwrap_or(output.chunks.len());
+            output
+                .chunks
+                .insert(iend, Chunk::from_data(*chunk_type, payload.to_vec()));
+        }
+        Technique::Trailing => {
+            output
+                .extra_bytes
+                .get_or_insert_with(Vec::new)
+                .extend(payload);
+        }
+        Technique::AfterZlib => {
+            let idat = output
+                .chunks
+                .iter()
+                .rposition(|chunk| chunk.chunk_type == "IDAT")
+                .ok_or("IDAT chunk not found")?;
+            let mut data = output.chunks[idat].data.clone().unwrap_or_default();
+            data.extend(payload);
+            output.chunks[idat] = Chunk::from_data(ChunkType::from(*b"IDAT"), data);
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, idat, ihdr, reparse};
+    use crate::search::{search, MatchKind, SearchOptions, FLAG_PATTERN};
+
+    /// A 16x16 RGB gradient.
+    fn cover() -> Png {
+        let mut data = vec![];
+        for y in 0..16u8 {
+            data.push(0);
+            for x in 0..16u8 {
+                data.extend([x * 16, y * 16, x ^ y]);
+            }
+        }
+        Png::new(
+            vec![ihdr(16, 16, 8, 2), idat(&data), chunk(b"IEND", &[])],
+            None,
+        )
+    }
+
+    #[test]
+    fn test_embed_lsb_roundtrip() {
+        let mut buffer = cover().get_raw_buffer().unwrap();
+        let layout = LsbLayout {
+            channels: vec![2, 0],
+            bit_planes: vec![1, 0],
+            msb_first: false,
+        };
+
+        embed_lsb(&mut buffer, &layout, b"hidden").unwrap();
+
+        assert_eq!(layout.capacity(&buffer), 128);
+        assert_eq!(&extract_lsb(&buffer, &layout)[..6], b"hidden");
+        assert!(embed_lsb(&mut buffer, &layout, &[0; 129]).is_err());
+        assert!(embed_lsb(&mut buffer, &LsbLayout::all_channels(4), b"x").is_err());
+    }
+
+    #[test]
+    fn test_pack_bits() {
+        let bits = [0, 1, 0, 0, 0, 0, 0, 1, 1];
+
+        assert_eq!(pack_bits(bits.into_iter(), true), vec![0b0100_0001]);
+        assert_eq!(pack_bits(bits.into_iter(), false), vec![0b1000_0010]);
+    }
+
+    #[test]
+    fn test_embedded_payload_is_found() {
+        let techniques = [
+            (Technique::Lsb(LsbLayout::all_channels(3)), "LSB rgb"),
+            (
+                Technique::Chunk(ChunkType::from(*b"stEg")),
+                "chunk 2 (stEg)",
+            ),
+            (Technique::Trailing, "extra bytes"),
+            (Technique::AfterZlib, "IDAT after zlib stream"),
+        ];
+
+        for (technique, layer) in techniques {
+            let embedded = embed(&cover(), b"CTF{embedded}", &technique).unwrap();
+            let parsed = reparse(&embedded);
+
+            assert!(parsed.chunks.iter().all(|chunk| chunk.validate_checksum()));
+            assert!(parsed.get_raw_buffer().is_ok());
+            let found = search(&parsed, &SearchOptions::default())
+                .unwrap()
+                .into_iter()
+                .any(|m| {
+                    m.layer == layer
+                        && m.kind == MatchKind::Pattern(FLAG_PATTERN.to_string())
+                        && m.preview == "CTF{embedded}"
+                });
+            assert!(found, "{:?} not found in {}", technique, layer);
+        }
+    }
+
+    #[test]
+    fn test_embed_rejects_critical_chunks() {
+        let technique = Technique::Chunk(ChunkType::from(*b"IDAT"));
+
+        assert!(embed(&cover(), b"payload", &technique).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::png::{PixelBuffer, Png};
+use std::error::Error;
+
+/// Shannon entropy of chunk payloads, trailing bytes and unfiltered scanlines, in bits per byte.
+/// Compressed or encrypted data sits close to 8.
+#[derive(Debug)]
+pub struct EntropyReport {
+    /// Index, type, length and entropy of every chunk's data
+    pub chunks: Vec<(usize, String, usize, f64)>,
+    pub extra_bytes: Option<f64>,
+    pub scanlines: Vec<ScanlineEntropy>,
+    /// Why the scanlines could not be decoded, in which case `scanlines` is empty
+    pub scanline_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanlineEntropy {
+    /// 0 for non-interlaced images, otherwise the Adam7 pass (1-7)
+    pub pass: usize,
+    pub y: u32,
+    pub entropy: f64,
+}
+
+/// Reports the chunks and trailing bytes even when the image data cannot be decoded, as broken
+/// files are the ones most worth looking at.
+pub fn entropy_report(png: &Png) -> EntropyReport {
+    let chunks = png
+        .chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let data = chunk.data.as_deref().unwrap_or_default();
+            (
+                i,
+                chunk.chunk_type.to_string(),
+                data.len(),
+                shannon_entropy(data),
+            )
+        })
+        .collect();
+
+    let (scanlines, scanline_error) = match scanline_entropy(png) {
+        Ok(scanlines) => (scanlines, None),
+        Err(e) => (vec![], Some(e.to_string())),
+    };
+
+    EntropyReport {
+        chunks,
+        extra_bytes: png.extra_bytes.as_deref().map(shannon_entropy),
+        scanlines,
+        scanline_error,
+    }
+}
+
+fn scanline_entropy(png: &Png) -> Result<Vec<ScanlineEntropy>, Box<dyn Error>> {
+    let mut scanlines = vec![];
+    let mut rows = png.rows()?;
+    while let Some(row) = rows.next_row()? {
+        scanlines.push(ScanlineEntropy {
+            pass: row.pass,
+            y: row.y,
+            entropy: shannon_entropy(row.data),
+        });
+    }
+    Ok(scanlines)
+}
+
+/// Bits of entropy per byte, from 0 for constant data to 8.
+pub fn shannon_entropy(bytes: &[u8]) -> f64 {
+    let mut histogram = [0u32; 256];
+    for &byte in bytes {
+        histogram[byte as usize] += 1;
+    }
+    entropy(&histogram, bytes.len())
+}
+
+fn entropy(histogram: &[u32], total: usize) -> f64 {
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            p * (1.0 / p).log2()
+        })
+        .fold(0.0, |sum, value| sum + value)
+}
+
+/// Entropy of square pixel blocks sliding over the image.
+#[derive(Debug)]
+pub struct EntropyMap {
+    /// Number of block positions across and down
+    pub width: u32,
+    pub height: u32,
+    /// Distance between block positions in pixels
+    pub step: u32,
+    /// Entropy of each block, normalized to 0-1 by the sample bit depth, row by row
+    pub values: Vec<f64>,
+}
+
+impl EntropyMap {
+    pub fn get(&self, x: u32, y: u32) -> f64 {
+        self.values[y as usize * self.width as usize + x as usize]
+    }
+
+    pub fn max(&self) -> f64 {
+        self.values.iter().copied().fold(0.0, f64::max)
+    }
+}
+
+/// Computes the entropy of the samples in every `size` x `size` block, moving by `step` pixels.
+/// Blocks include every channel of `buffer`, so pass a single channel or bit plane to map it on
+/// its own.
+pub fn entropy_map(buffer: &PixelBuffer, size: u32, step: u32) -> EntropyMap {
+    let size = size.clamp(1, buffer.width.min(buffer.height).max(1));
+    let step = step.max(1);
+    let positions = |length: u32| (length.saturating_sub(size) / step + 1).min(length);
+    let (width, height) = (positions(buffer.width), positions(buffer.height));
+
+    let mut histogram = vec![0u32; 1 << buffer.bit_depth];
+    let mut values = Vec::with_capacity(width as usize * height as usize);
+    for by in 0..height {
+        for bx in 0..width {
+            histogram.fill(0);
+            let mut total = 0;
+            for y in by * step..(by * step + size).min(buffer.height) {
+                for x in bx * step..(bx * step + size).min(buffer.width) {
+                    for c in 0..buffer.channels {
+                        histogram[buffer.sample(x, y, c) as usize] += 1;
+                        total += 1;
+                    }
+                }
+            }
+            values.push(entropy(&histogram, total) / buffer.bit_depth as f64);
+        }
+    }
+
+    EntropyMap {
+        width,
+        height,
+        step,
+        values,
+    }
+}
+
+/// Draws the map with a black-red-yellow-white color scale, each block position covering `step`
+/// pixels, so the heatmap lines up with the image.
+pub fn heatmap(map: &EntropyMap) -> PixelBuffer {
+    let step = map.step.max(1);
+    let mut buffer = PixelBuffer::new(map.width * step, map.height * step, 3, 8);
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            let color = heat_color(map.get(x / step, y / step));
+            for (c, value) in color.into_iter().enumerate() {
+                buffer.set_sample(x, y, c as u8, value as u16);
+            }
+        }
+    }
+    buffer
+}
+
+fn heat_color(value: f64) -> [u8; 3] {
+    let scaled = value.clamp(0.0, 1.0) * 3.0;
+    let channel = |offset: f64| ((scaled - offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, ihdr};
+
+    #[test]
+    fn test_shannon_entropy() {
+        assert_eq!(shannon_entropy(&[]), 0.0);
+        assert_eq!(shannon_entropy(&[7; 100]), 0.0);
+        assert_eq!(shannon_entropy(&[0, 1, 2, 3]), 2.0);
+        assert_eq!(shannon_entropy(&(0..=255).collect::<Vec<u8>>()), 8.0);
+    }
+
+    #[test]
+    fn test_entropy_report_broken_image_data() {
+        let png = Png::new(
+            vec![ihdr(1, 1, 8, 0), chunk(b"IDAT", &[1, 2, 3, 4])],
+            Some(vec![0; 4]),
+        );
+
+        let report = entropy_report(&png);
+
+        assert_eq!(report.chunks.len(), 2);
+        assert_eq!(report.chunks[1].3, 2.0);
+        assert_eq!(report.extra_bytes, Some(0.0));
+        assert!(report.scanlines.is_empty());
+        assert!(report.scanline_error.is_some());
+    }
+
+    #[test]
+    fn test_entropy_map() {
+        // Left half constant, right half alternating between two values
+        let data = (0..8 * 4)
+            .map(|i| if i % 8 < 4 { 9 } else { (i % 2) as u8 * 255 })
+            .collect();
+        let buffer = PixelBuffer::from_data(8, 4, 1, 8, data);
+
+        let map = entropy_map(&buffer, 4, 2);
+
+        assert_eq!((map.width, map.height), (3, 1));
+        assert_eq!(map.values, vec![0.0, 1.5 / 8.0, 1.0 / 8.0]);
+        assert_eq!(heatmap(&map).width, 6);
+    }
+
+    #[test]
+    fn test_heat_color() {
+        assert_eq!(heat_color(0.0), [0, 0, 0]);
+        assert_eq!(heat_color(0.5), [255, 128, 0]);
+        assert_eq!(heat_color(1.0), [255, 255, 255]);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::analyze::visualize;
+use crate::palette::randomize_palette;
+use crate::png::{limit_error, Limits, Png};
+use crate::view::create_dynamic_image;
+use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat};
+use std::error::Error;
+use std::io::Cursor;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Ppm,
+    Bmp,
+}
+
+impl Format {
+    /// Picks the format from a file extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Format::Png),
+            "ppm" => Some(Format::Ppm),
+            "bmp" => Some(Format::Bmp),
+            _ => None,
+        }
+    }
+}
+
+/// The part of the file to export.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Source {
+    #[default]
+    Image,
+    /// An Adam7 pass (1-7) as its reduced image
+    Pass(usize),
+    /// An APNG frame, counting from 0, as stored
+    Frame(usize),
+    /// One of the value streams of `analyze`, such as `r+g+b`
+    Visualization(String),
+    /// Every palette entry or sample value replaced by a random color
+    RandomPalette,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub source: Source,
+    /// Channel name (`r`, `g`, `b`, `a`, `gray`) or index
+    pub channel: Option<String>,
+    pub bit_plane: Option<u8>,
+    /// Keep samples as stored instead of expanding palettes
+    pub raw: bool,
+    /// Integer upscaling factor, nearest neighbour
+    pub scale: u32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            source: Source::Image,
+            channel: None,
+            bit_plane: None,
+            raw: false,
+            scale: 1,
+        }
+    }
+}
+
+/// Builds the image selected by `options`.
+pub fn export_image(png: &Png, options: &ExportOptions) -> Result<DynamicImage, Box<dyn Error>> {
+    let mut buffer = match &options.source {
+        Source::Image if options.raw => png.get_raw_buffer()?,
+        Source::Image => png.get_buffer()?,
+        Source::Pass(pass) if options.raw => png.get_raw_pass(*pass)?,
+        Source::Pass(pass) => png.get_pass(*pass)?,
+        Source::Frame(index) if options.raw => png.frame(*index)?.get_raw_buffer()?,
+        Source::Frame(index) => png.frame(*index)?.get_buffer()?,
+        Source::Visualization(key) => visualize(png, key)?,
+        Source::RandomPalette => randomize_palette(png)?,
+    };
+
+    if let Some(name) = &options.channel {
+        let channel = channel_index(name, buffer.channels).ok_or_else(|| {
+            format!(
+                "Channel {} not found in an image with {} channels",
+                name, buffer.channels
+            )
+        })?;
+        buffer = buffer.extract_channel(channel);
+    }
+
+    if let Some(plane) = options.bit_plane {
+        if plane >= buffer.bit_depth {
+            return Err(format!(
+                "Bit plane {} out of range for {}-bit samples",
+                plane, buffer.bit_depth
+            )
+            .into());
+        }
+        buffer = buffer.bit_plane(plane);
+    }
+
+    scale(create_dynamic_image(&buffer), options.scale, &png.limits)
+}
+
+/// Upscales by `factor`, as long as the result stays within the dimension and pixel limits.
+fn scale(
+    image: DynamicImage,
+    factor: u32,
+    limits: &Limits,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    if factor <= 1 {
+        return Ok(image);
+    }
+
+    let too_large = || {
+        limit_error(format!(
+            "Scaling {}x{} by {} exceeds the image size limits",
+            image.width(),
+            image.height(),
+            factor
+        ))
+    };
+    let width = image.width().checked_mul(factor).ok_or_else(too_large)?;
+    let height = image.height().checked_mul(factor).ok_or_else(too_large)?;
+    if width > limits.max_width
+        || height > limits.max_height
+        || width as u64 * height as u64 > limits.max_pixels
+    {
+        return Err(too_large().into());
+    }
+
+    Ok(image.resize_exact(width, height, FilterType::Nearest))
+}
+
+/// Resolves a channel name or index for a buffer with `channels` channels.
+pub fn channel_index(name: &str, channels: u8) -> Option<u8> {
+    let index = match name.to_ascii_lowercase().as_str() {
+        "r" | "red" | "gray" | "grey" | "y" => 0,
+        "g" | "green" => 1,
+        "b" | "blue" => 2,
+        "a" | "alpha" if channels.is_multiple_of(2) => channels - 1,
+        name => name.parse().ok()?,
+    };
+
+    (index < channels).then_some(index)
+}
+
+/// Encodes the image in memory, so it can be written to files or stdout alike.
+pub fn encode(image: &DynamicImage, format: Format) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Cursor::new(vec![]);
+    match format {
+        Format::Png => image.write_to(&mut bytes, ImageOutputFormat::Png)?,
+        // PPM has no alpha channel and BMP no 16-bit samples
+        Format::Ppm => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(
+            &mut bytes,
+            ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+        )?,
+        Format::Bmp if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_to(&mut bytes, ImageOutputFormat::Bmp)?,
+        Format::Bmp => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut bytes, ImageOutputFormat::Bmp)?
+        }
+    }
+
+    Ok(bytes.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::PixelBuffer;
+
+    #[test]
+    fn test_channel_index() {
+        assert_eq!(channel_index("a", 4), Some(3));
+        assert_eq!(channel_index("alpha", 2), Some(1));
+        assert_eq!(channel_index("a", 3), None);
+        assert_eq!(channel_index("B", 3), Some(2));
+        assert_eq!(channel_index("1", 2), Some(1));
+        assert_eq!(channel_index("g", 1), None);
+    }
+
+    #[test]
+    fn test_encode_ppm() {
+        let buffer = PixelBuffer::from_data(1, 1, 4, 8, vec![1, 2, 3, 4]);
+
+        assert_eq!(
+            encode(&create_dynamic_image(&buffer), Format::Ppm).unwrap(),
+            b"P6\n1 1 255\n\x01\x02\x03"
+        );
+    }
+
+    #[test]
+    fn test_scale_limits() {
+        let image = create_dynamic_image(&PixelBuffer::new(2, 2, 1, 8));
+        let limits = Limits {
+            max_pixels: 16,
+            ..Limits::default()
+        };
+
+        assert_eq!(scale(image.clone(), 2, &limits).unwrap().width(), 4);
+        assert!(scale(image.clone(), 3, &limits).is_err());
+        assert!(scale(image, u32::MAX, &Limits::unlimited()).is_err());
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("out.PNG"), Some(Format::Png));
+        assert_eq!(Format::from_path("out.ppm"), Some(Format::Ppm));
+        assert_eq!(Format::from_path("out"), None);
+    }
+}
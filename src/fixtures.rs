@@ -0,0 +1,65 @@
+//! Chunk and image builders shared by the unit tests.
+
+use crate::parse_bytes;
+use crate::png::compression::compress;
+use crate::png::writer::write_png;
+use crate::png::{
+    Chunk, ChunkType, ColorType, CompressionMethod, FilterMethod, InterlaceMethod, Png, IHDR,
+};
+
+pub fn chunk(chunk_type: &[u8; 4], data: &[u8]) -> Chunk {
+    Chunk::from_data(ChunkType::from(*chunk_type), data.to_vec())
+}
+
+/// The IHDR chunk of a non-interlaced image.
+pub fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Chunk {
+    let mut data = vec![];
+    data.extend(width.to_be_bytes());
+    data.extend(height.to_be_bytes());
+    data.extend([bit_depth, color_type, 0, 0, 0]);
+    chunk(b"IHDR", &data)
+}
+
+/// The parsed IHDR of a grayscale image.
+pub fn gray_ihdr(
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    interlace_method: InterlaceMethod,
+) -> IHDR {
+    IHDR {
+        width,
+        height,
+        bit_depth,
+        color_type: ColorType::Grayscale,
+        compression_method: CompressionMethod::Deflate,
+        filter_method: FilterMethod::Adaptive,
+        interlace_method,
+    }
+}
+
+/// An IDAT chunk holding `scanlines`, filter type bytes included.
+pub fn idat(scanlines: &[u8]) -> Chunk {
+    chunk(b"IDAT", &compress(scanlines).unwrap())
+}
+
+/// The CgBI chunk Xcode writes first in iOS-optimized files.
+pub fn cgbi() -> Chunk {
+    chunk(b"CgBI", &[0x50, 0x00, 0x20, 0x06])
+}
+
+/// IHDR and IDAT of a 2x2 8-bit grayscale image with samples 1 to 4.
+pub fn gray_2x2() -> Vec<Chunk> {
+    vec![ihdr(2, 2, 8, 0), idat(&[0, 1, 2, 0, 3, 4])]
+}
+
+pub fn to_bytes(png: &Png) -> Vec<u8> {
+    let mut bytes = vec![];
+    write_png(png, &mut bytes).unwrap();
+    bytes
+}
+
+/// Writes `png` out and reads it back, so that its chunks have offsets.
+pub fn reparse(png: &Png) -> Png {
+    parse_bytes(&to_bytes(png)).unwrap()
+}
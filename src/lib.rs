@@ -1,46 +1,95 @@
-use crate::easy_br::EasyRead;
+use crate::reader::ChunkReader;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::str;
 
-mod easy_br;
+pub mod analyze;
+pub mod color;
+pub mod diff;
+pub mod embed;
+pub mod entropy;
+pub mod export;
+#[cfg(test)]
+mod fixtures;
+pub mod optimize;
+pub mod palette;
 pub mod png;
+#[cfg(test)]
+mod proptests;
+pub mod reader;
+pub mod sanitize;
+pub mod search;
+pub mod steganalysis;
 pub mod view;
-pub mod analyze;
 
 pub fn parse_file(file: File) -> Result<png::Png, Box<dyn std::error::Error>> {
-    let mut buf = BufReader::new(file);
+    parse_file_with_limits(file, png::Limits::default())
+}
 
-    let signature = buf.read_bytes(8)?;
-    if signature != png::HEADER {
-        return Err("Invalid PNG signature".into());
-    }
+pub fn parse_file_with_limits(
+    file: File,
+    limits: png::Limits,
+) -> Result<png::Png, Box<dyn std::error::Error>> {
+    parse_reader_with_limits(BufReader::new(file), limits)
+}
 
-    let mut chunks = vec![];
+/// Parses a PNG held in memory.
+pub fn parse_bytes(data: &[u8]) -> Result<png::Png, Box<dyn std::error::Error>> {
+    parse_reader(data)
+}
 
-    loop {
-        let mut data = None;
-        let length = buf.read_u32_be()?;
-        let chunk_type = buf.read_bytes(4)?;
-        let chunk_type = str::from_utf8(&chunk_type)?;
-        if length != 0 {
-            data = Some(buf.read_bytes(length as usize)?);
-        }
-        let crc = buf.read_u32_be()?;
+pub fn parse_bytes_with_limits(
+    data: &[u8],
+    limits: png::Limits,
+) -> Result<png::Png, Box<dyn std::error::Error>> {
+    parse_reader_with_limits(data, limits)
+}
 
-        chunks.push(png::Chunk::new(length, String::from(chunk_type), data, crc));
+/// Parses a PNG from any reader, such as stdin. The reader is read in small pieces, so wrap
+/// unbuffered readers in a `BufReader`.
+pub fn parse_reader<R: Read>(reader: R) -> Result<png::Png, Box<dyn std::error::Error>> {
+    parse_reader_with_limits(reader, png::Limits::default())
+}
 
-        if chunk_type == "IEND" {
-            break;
-        }
+pub fn parse_reader_with_limits<R: Read>(
+    reader: R,
+    limits: png::Limits,
+) -> Result<png::Png, Box<dyn std::error::Error>> {
+    let mut reader = ChunkReader::with_limits(reader, limits.clone());
+    let chunks = reader.by_ref().collect::<Result<Vec<_>, _>>()?;
+    let extra_bytes = reader.read_trailing_bytes()?;
+
+    if !extra_bytes.is_empty() {
+        return Ok(png::Png::new(chunks, Some(extra_bytes)).with_limits(limits));
     }
 
-    let mut extra_bytes: Vec<u8> = vec![];
-    buf.read_to_end(&mut extra_bytes)?;
+    Ok(png::Png::new(chunks, None).with_limits(limits))
+}
 
-    if !extra_bytes.is_empty() {
-        return Ok(png::Png::new(chunks, Some(extra_bytes)));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, to_bytes};
+
+    fn minimal_png() -> Vec<u8> {
+        to_bytes(&png::Png::new(vec![chunk(b"IEND", &[])], None))
     }
 
-    Ok(png::Png::new(chunks, None))
+    #[test]
+    fn test_parse_bytes() {
+        let mut data = minimal_png();
+        data.extend(b"extra");
+
+        let png = parse_bytes(&data).unwrap();
+
+        assert_eq!(png.chunks.len(), 1);
+        assert_eq!(png.extra_bytes, Some(b"extra".to_vec()));
+    }
+
+    #[test]
+    fn test_parse_reader() {
+        let png = parse_reader(std::io::Cursor::new(minimal_png())).unwrap();
+
+        assert_eq!(png.chunks[0].chunk_type, "IEND");
+        assert_eq!(png.extra_bytes, None);
+    }
 }
@@ -1,10 +1,28 @@
-use clap::Parser;
-use pngcheck::parse_file;
-use pngcheck::png::{Chunk, Png};
-use pngcheck::view::view_image;
+use base64::Engine;
+use clap::{Parser, Subcommand, ValueEnum};
+use image::DynamicImage;
+use pngcheck::color::ColorSpace;
+use pngcheck::diff::{diff_chunks, diff_pixels, difference_image, ChunkDiff};
+use pngcheck::embed::{embed, LsbLayout, Technique};
+use pngcheck::entropy::{entropy_map, entropy_report, heatmap, EntropyReport};
+use pngcheck::export::{channel_index, encode, export_image, ExportOptions, Format, Source};
+use pngcheck::optimize::{optimize, FilterStrategy, OptimizeOptions, OptimizeReport};
+use pngcheck::palette::{analyze_palette, PaletteReport};
+use pngcheck::png::compression::Strategy;
+use pngcheck::png::filter::Filter;
+use pngcheck::png::writer::write_png;
+use pngcheck::png::{Chunk, Limits, Png, Report, ValidateOptions};
+use pngcheck::sanitize::{sanitize, verify, SanitizeOptions, SanitizeReport};
+use pngcheck::search::{search, MatchKind, SearchOptions};
+use pngcheck::steganalysis::{steganalysis, SteganalysisReport};
+use pngcheck::view::{view_image, view_png, Background, RenderOptions};
+use pngcheck::{parse_file_with_limits, parse_reader_with_limits};
+use regex::bytes::Regex;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::str::from_utf8;
-use std::collections::HashMap;
 
 mod pretty_assert_printing;
 mod tui;
@@ -12,27 +30,346 @@ mod tui;
 //PNG check
 #[derive(Parser)]
 #[clap(author, about, version, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Args,
+    #[command(flatten)]
+    limits: LimitArgs,
+}
+
+#[derive(Subcommand)]
 enum Args {
     ///Check a PNG file
     Check {
-        ///The PNG file to check
+        ///The PNG file to check, or - for stdin
         file: String,
+        ///Skip decoding the image data
+        #[arg(long)]
+        no_decode: bool,
+        ///Print the findings as JSON
+        #[arg(long)]
+        json: bool,
     },
     ///Analyze a PNG file for hidden data
     Analyze {
-        ///The PNG file to analyze
+        ///The PNG file to analyze, or - for stdin
         file: String,
+        ///Print the results as JSON
+        #[arg(long)]
+        json: bool,
     },
     ///View a PNG file
     View {
-        ///The PNG file to view
+        ///The PNG file to view, or - for stdin
         file: String,
+        ///Convert colors to sRGB using the gAMA, cHRM and sRGB chunks
+        #[arg(long)]
+        color_manage: bool,
+        ///What transparent pixels are drawn over
+        #[arg(long, value_enum, default_value_t = BackgroundArg::None)]
+        background: BackgroundArg,
     },
     //Use a UI to view PNG information
     Ui {
-        ///The PNG file to view
+        ///The PNG file to view, or - for stdin
+        file: String,
+    },
+    ///Write the decoded image, or a part or visualization of it, to a PNG, PPM or BMP file
+    Export {
+        ///The PNG file to export from, or - for stdin
+        file: String,
+        ///The file to write, or - for stdout
+        output: String,
+        ///Output format, taken from the output file extension by default
+        #[arg(long, value_enum)]
+        format: Option<FormatArg>,
+        ///Export a single channel: r, g, b, a, gray or an index
+        #[arg(long)]
+        channel: Option<String>,
+        ///Export a single bit plane, 0 being the least significant bit
+        #[arg(long)]
+        bit_plane: Option<u8>,
+        ///Export an Adam7 pass (1-7) as its reduced image
+        #[arg(long, conflicts_with_all = ["frame", "visualize"])]
+        pass: Option<usize>,
+        ///Export an APNG frame, counting from 0, as stored
+        #[arg(long, conflicts_with = "visualize")]
+        frame: Option<usize>,
+        ///Export one of the analyze value streams as an image, such as r+g+b
+        #[arg(long, conflicts_with = "random_palette")]
+        visualize: Option<String>,
+        ///Replace every palette entry, or sample value, with a random color
+        #[arg(long, conflicts_with_all = ["pass", "frame"])]
+        random_palette: bool,
+        ///Keep samples as stored, palette indices instead of colors
+        #[arg(long)]
+        raw: bool,
+        ///Integer upscaling factor
+        #[arg(long, default_value_t = 1)]
+        scale: u32,
+    },
+    ///Report palette usage, duplicate and unused entries and PLTE/tRNS/hIST problems
+    Palette {
+        ///The PNG file to inspect, or - for stdin
+        file: String,
+    },
+    ///Compare two PNG files chunk by chunk and pixel by pixel
+    Diff {
+        ///The original PNG file
+        left: String,
+        ///The PNG file to compare against it
+        right: String,
+        ///Write an image highlighting the changed pixels in red
+        #[arg(long)]
+        image: Option<String>,
+    },
+    ///Show the entropy of chunks, scanlines and pixel blocks, as text and a heatmap
+    Entropy {
+        ///The PNG file to inspect, or - for stdin
         file: String,
+        ///Size of the square pixel blocks
+        #[arg(long, default_value_t = 16)]
+        block: u32,
+        ///Distance between blocks, half the block size by default
+        #[arg(long)]
+        step: Option<u32>,
+        ///Map a single channel: r, g, b, a, gray or an index
+        #[arg(long)]
+        channel: Option<String>,
+        ///Map a single bit plane, 0 being the least significant bit
+        #[arg(long)]
+        bit_plane: Option<u8>,
+        ///Write the heatmap to a PNG, PPM or BMP file instead of showing it
+        #[arg(long)]
+        image: Option<String>,
     },
+    ///Search the file, its chunks, decompressed data and LSB streams for strings, flags, encoded
+    ///blobs and embedded files
+    Search {
+        ///The PNG file to search, or - for stdin
+        file: String,
+        ///Shortest run of printable characters to report as a string
+        #[arg(long, default_value_t = 8)]
+        min_length: usize,
+        ///A regular expression to look for, in addition to the CTF{...} flag format
+        #[arg(long)]
+        pattern: Vec<String>,
+        ///Only report patterns, encoded blobs and magic numbers, not every string
+        #[arg(long)]
+        no_strings: bool,
+    },
+    ///Remove metadata, private chunks and trailing data, keeping only allowlisted chunks
+    Sanitize {
+        ///The PNG file to sanitize, or - for stdin
+        file: String,
+        ///The PNG file to write, or - for stdout
+        #[arg(required_unless_present = "dry_run")]
+        output: Option<String>,
+        ///Keep chunks of this type too, on top of those needed to display the image
+        #[arg(long)]
+        allow: Vec<String>,
+        ///Convert images with an ICC profile to sRGB instead of only removing the profile
+        #[arg(long)]
+        iccp_to_srgb: bool,
+        ///Report what would be removed without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    ///Losslessly shrink the image data by trying filters, zlib settings and smaller color types
+    Optimize {
+        ///The PNG file to optimize, or - for stdin
+        file: String,
+        ///The PNG file to write, or - for stdout
+        output: String,
+        ///Filter strategies to try, all by default
+        #[arg(long, value_enum)]
+        filter: Vec<FilterArg>,
+        ///zlib compression levels to try (0-10)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=10))]
+        level: Vec<u8>,
+        ///Deflate strategies to try
+        #[arg(long, value_enum)]
+        strategy: Vec<StrategyArg>,
+        ///Keep the color type and bit depth
+        #[arg(long)]
+        no_reduce: bool,
+    },
+    ///Hide a payload in a PNG, to produce samples for analyze and search
+    Embed {
+        ///The cover PNG file, or - for stdin
+        file: String,
+        ///The PNG file to write, or - for stdout
+        output: String,
+        ///The file to hide
+        #[arg(long, required_unless_present = "message", conflicts_with = "message")]
+        payload: Option<String>,
+        ///Text to hide instead of a file
+        #[arg(long)]
+        message: Option<String>,
+        ///Where to hide the payload
+        #[arg(long, value_enum, default_value_t = MethodArg::Lsb)]
+        method: MethodArg,
+        ///Channels to embed in with lsb, in order: r, g, b, a, gray or an index. All by default
+        #[arg(long)]
+        channel: Vec<String>,
+        ///Bit planes to embed in with lsb, in order, 0 being the least significant
+        #[arg(long, default_values_t = [0])]
+        bit_plane: Vec<u8>,
+        ///Store the least significant bit of each payload byte first with lsb
+        #[arg(long)]
+        lsb_first: bool,
+        ///Chunk type to embed in with chunk
+        #[arg(long, default_value = "stEg")]
+        chunk_type: String,
+    },
+    ///Convert an Apple CgBI (iOS-optimized) PNG to a standard PNG
+    Uncrush {
+        ///The CgBI PNG file to convert, or - for stdin
+        file: String,
+        ///The PNG file to write, or - for stdout
+        output: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BackgroundArg {
+    ///Keep transparency
+    None,
+    ///The bKGD color, or a checkerboard if there is none
+    File,
+    Checkerboard,
+}
+
+impl From<BackgroundArg> for Background {
+    fn from(background: BackgroundArg) -> Self {
+        match background {
+            BackgroundArg::None => Background::None,
+            BackgroundArg::File => Background::File,
+            BackgroundArg::Checkerboard => Background::Checkerboard,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormatArg {
+    Png,
+    Ppm,
+    Bmp,
+}
+
+impl From<FormatArg> for Format {
+    fn from(format: FormatArg) -> Self {
+        match format {
+            FormatArg::Png => Format::Png,
+            FormatArg::Ppm => Format::Ppm,
+            FormatArg::Bmp => Format::Bmp,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MethodArg {
+    ///Sample bits chosen with --channel, --bit-plane and --lsb-first
+    Lsb,
+    ///A new ancillary chunk of type --chunk-type
+    Chunk,
+    ///Data after IEND
+    Trailing,
+    ///The last IDAT chunk, after the end of the zlib stream
+    AfterZlib,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FilterArg {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    ///Per row, the filter with the smallest sum of absolute differences
+    MinSum,
+    ///Per row, the filter that compresses smallest
+    BruteForce,
+}
+
+impl From<FilterArg> for FilterStrategy {
+    fn from(filter: FilterArg) -> Self {
+        match filter {
+            FilterArg::None => FilterStrategy::Fixed(Filter::None),
+            FilterArg::Sub => FilterStrategy::Fixed(Filter::Sub),
+            FilterArg::Up => FilterStrategy::Fixed(Filter::Up),
+            FilterArg::Average => FilterStrategy::Fixed(Filter::Average),
+            FilterArg::Paeth => FilterStrategy::Fixed(Filter::Paeth),
+            FilterArg::MinSum => FilterStrategy::MinSum,
+            FilterArg::BruteForce => FilterStrategy::BruteForce,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StrategyArg {
+    Default,
+    Filtered,
+    HuffmanOnly,
+    Rle,
+}
+
+impl From<StrategyArg> for Strategy {
+    fn from(strategy: StrategyArg) -> Self {
+        match strategy {
+            StrategyArg::Default => Strategy::Default,
+            StrategyArg::Filtered => Strategy::Filtered,
+            StrategyArg::HuffmanOnly => Strategy::HuffmanOnly,
+            StrategyArg::Rle => Strategy::Rle,
+        }
+    }
+}
+
+///Resource limits applied to untrusted files
+#[derive(clap::Args)]
+struct LimitArgs {
+    ///Largest accepted chunk length in bytes
+    #[arg(long, global = true, default_value_t = Limits::default().max_chunk_length)]
+    max_chunk_length: u32,
+    ///Largest accepted decompressed image data size in bytes
+    #[arg(long, global = true, default_value_t = Limits::default().max_decompressed_size)]
+    max_decompressed_size: usize,
+    ///Largest accepted decompressed size of all zTXt, iTXt and iCCP chunks in bytes
+    #[arg(long, global = true, default_value_t = Limits::default().max_text_size)]
+    max_text_size: usize,
+    ///Largest accepted size of the data after IEND in bytes
+    #[arg(long, global = true, default_value_t = Limits::default().max_trailing_size)]
+    max_trailing_size: usize,
+    ///Largest accepted image width
+    #[arg(long, global = true, default_value_t = Limits::default().max_width)]
+    max_width: u32,
+    ///Largest accepted image height
+    #[arg(long, global = true, default_value_t = Limits::default().max_height)]
+    max_height: u32,
+    ///Largest accepted number of pixels
+    #[arg(long, global = true, default_value_t = Limits::default().max_pixels)]
+    max_pixels: u64,
+    ///Disable all resource limits
+    #[arg(long, global = true)]
+    no_limits: bool,
+}
+
+impl From<&LimitArgs> for Limits {
+    fn from(args: &LimitArgs) -> Self {
+        if args.no_limits {
+            return Limits::unlimited();
+        }
+
+        Limits {
+            max_chunk_length: args.max_chunk_length,
+            max_decompressed_size: args.max_decompressed_size,
+            max_text_size: args.max_text_size,
+            max_trailing_size: args.max_trailing_size,
+            max_width: args.max_width,
+            max_height: args.max_height,
+            max_pixels: args.max_pixels,
+        }
+    }
 }
 
 fn print_banner() {
@@ -46,59 +383,638 @@ fn print_banner() {
     println!();
 }
 
-fn print_chunks(chunks: &Vec<Chunk>) {
-    for chunk in chunks {
+fn print_chunks(png: &Png) {
+    for (chunk, parsed) in png.chunks.iter().zip(png.parsed_chunks()) {
         println!("=============== {} ===============", chunk.chunk_type);
-        println!("{}", chunk);
+        println!("{}", chunk.describe(&parsed));
+    }
+}
+
+fn chunk_location(i: usize, chunk: &Chunk) -> String {
+    match chunk.offset {
+        Some(offset) => format!("chunk {} ({}) at {:#x}", i, chunk.chunk_type, offset),
+        None => format!("chunk {} ({})", i, chunk.chunk_type),
+    }
+}
+
+fn print_findings(report: &Report) {
+    for finding in &report.findings {
+        println!("{}", finding);
     }
 }
 
 fn print_analyze_result(result: &HashMap<String, Vec<u8>>) {
     for (key, value) in result {
-        let value = from_utf8(&value);
+        let value = from_utf8(value);
         match value {
             Ok(value) => println!("{}\t\t{}", key, value),
             Err(_) => eprintln!("{}\t\t{:?}", key, value),
         }
+    }
+}
+
+fn print_steganalysis(report: &SteganalysisReport) {
+    println!("=============== Steganalysis ===============");
+    let estimate = |rate: Option<f64>| {
+        rate.map_or(String::from("n/a"), |rate| format!("{:.1}%", rate * 100.0))
     };
+    for channel in &report.channels {
+        let chi_square = &channel.chi_square;
+        println!("{}", channel.channel);
+        println!(
+            "  Chi-square:\t{:.4} (statistic {:.1}, {} degrees of freedom)",
+            chi_square.probability, chi_square.statistic, chi_square.degrees_of_freedom
+        );
+        println!(
+            "  Curve:\t{} ({:.0}% of rows before dropping below 0.5)",
+            sparkline(&chi_square.curve),
+            chi_square.embedded_fraction * 100.0
+        );
+        println!("  RS:\t\t{}", estimate(channel.rs));
+        println!("  Sample pairs:\t{}", estimate(channel.sample_pair));
+    }
+    println!(
+        "Estimated embedding rate: {}",
+        estimate(report.estimated_rate)
+    );
 }
 
-fn read_file(file: &str) -> Result<Png, Box<dyn Error>> {
-    let file = std::fs::File::open(file).expect("Failed to open file");
+/// Draws a 0-1 curve with block characters, sampled at 20 points.
+fn sparkline(values: &[f64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let points = values.len().min(20);
+    (0..points)
+        .map(|i| {
+            let value = values[(i + 1) * values.len() / points - 1];
+            BLOCKS[((value * 7.0).round() as usize).min(7)]
+        })
+        .collect()
+}
+
+fn print_entropy_report(report: &EntropyReport) {
+    println!("=============== Chunks ===============");
+    for (i, chunk_type, length, entropy) in &report.chunks {
+        println!(
+            "{:>4} {}\t{:>10} bytes\t{:.3} {}",
+            i,
+            chunk_type,
+            length,
+            entropy,
+            entropy_bar(*entropy)
+        );
+    }
+    if let Some(entropy) = report.extra_bytes {
+        println!("Extra bytes\t\t\t{:.3} {}", entropy, entropy_bar(entropy));
+    }
 
-    parse_file(file)
+    println!("=============== Scanlines ===============");
+    if let Some(error) = &report.scanline_error {
+        println!("Failed to decode scanlines: {}", error);
+    }
+    for rows in report.scanlines.chunk_by(|a, b| a.pass == b.pass) {
+        let label = match rows[0].pass {
+            0 => String::from("Rows"),
+            pass => format!("Pass {}", pass),
+        };
+        let values: Vec<f64> = rows.iter().map(|row| row.entropy / 8.0).collect();
+        println!("{}\t{}", label, sparkline(&values));
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
+/// A bar of up to 16 characters for an entropy of 0-8 bits per byte.
+fn entropy_bar(entropy: f64) -> String {
+    "█".repeat((entropy * 2.0).round() as usize)
+}
 
-    match args {
-        Args::Check { file } => {
-            print_banner();
-            let data = read_file(&file)?;
-            print_chunks(&data.chunks);
-            println!("====================================");
-            println!("Extra bytes: {:?}", data.extra_bytes);
+/// Goes to stderr, so that sanitized output can be written to stdout.
+fn print_sanitize_report(data: &Png, report: &SanitizeReport) {
+    if report.uncrushed {
+        eprintln!("Converted from Apple CgBI");
+    }
+    if let Some(source) = &report.converted_to_srgb {
+        eprintln!("Converted colors to sRGB from {}", source);
+    }
+    for removal in &report.removed {
+        // Conversions rewrite IDAT, so indices only match the input when there were none
+        let converted = report.uncrushed || report.converted_to_srgb.is_some();
+        let chunk = data.chunks.get(removal.index).filter(|_| !converted);
+        let location = match chunk {
+            Some(chunk) => chunk_location(removal.index, chunk),
+            None => format!("chunk {} ({})", removal.index, removal.chunk_type),
+        };
+        eprintln!(
+            "Removed {}, {} bytes: {}",
+            location, removal.length, removal.reason
+        );
+    }
+    if let Some(extra_bytes) = report.extra_bytes {
+        eprintln!("Removed {} extra bytes after IEND", extra_bytes);
+    }
+    if report.is_clean() {
+        eprintln!("Nothing to remove");
+    }
+}
+
+/// Goes to stderr, so that optimized output can be written to stdout.
+fn print_optimize_report(report: &OptimizeReport) {
+    match report.chosen {
+        Some((filter, level, strategy)) => {
+            eprintln!("Format: {} -> {}", report.format.0, report.format.1);
+            eprintln!(
+                "Filter: {}, zlib level {}, {:?} strategy",
+                filter, level, strategy
+            );
+            eprintln!("Merged {} IDAT chunks into 1", report.idat_chunks);
         }
-        Args::Analyze { file } => {
-            print_banner();
-            let data = read_file(&file)?;
+        None => eprintln!("No smaller encoding found, keeping the original"),
+    }
+    let saved = report.input_size - report.output_size;
+    eprintln!(
+        "{} -> {} bytes ({:.1}% smaller)",
+        report.input_size,
+        report.output_size,
+        saved as f64 * 100.0 / report.input_size.max(1) as f64
+    );
+}
+
+fn print_chunk_diff(left: &Png, right: &Png) {
+    println!("=============== Chunks ===============");
+    for diff in diff_chunks(&left.chunks, &right.chunks) {
+        match diff {
+            ChunkDiff::Same { left: l, .. } => println!("  {}", chunk_location(l, &left.chunks[l])),
+            ChunkDiff::Removed { left: l } => println!("- {}", chunk_location(l, &left.chunks[l])),
+            ChunkDiff::Added { right: r } => println!("+ {}", chunk_location(r, &right.chunks[r])),
+            ChunkDiff::Changed {
+                left: l,
+                right: r,
+                fields,
+            } => {
+                let (left_chunk, right_chunk) = (&left.chunks[l], &right.chunks[r]);
+                println!(
+                    "~ {} -> {}",
+                    chunk_location(l, left_chunk),
+                    chunk_location(r, right_chunk)
+                );
+                for field in fields {
+                    match (field.left, field.right) {
+                        (Some(l), Some(r)) => println!("    {}: {} -> {}", field.name, l, r),
+                        (Some(l), None) => println!("  - {}: {}", field.name, l),
+                        (None, Some(r)) => println!("  + {}: {}", field.name, r),
+                        (None, None) => {}
+                    }
+                }
+
+                let left_data = left_chunk.data.as_deref().unwrap_or_default();
+                let right_data = right_chunk.data.as_deref().unwrap_or_default();
+                if let Some(start) = first_difference(left_data, right_data) {
+                    // Show a window of the raw data around the first difference
+                    let start = start.saturating_sub(8);
+                    pretty_assert_printing::soft_assert(
+                        &format!("{} data from byte {}", left_chunk.chunk_type, start),
+                        data_window(right_data, start),
+                        data_window(left_data, start),
+                    );
+                }
+            }
+        }
+    }
+
+    if left.extra_bytes != right.extra_bytes {
+        println!(
+            "~ Extra bytes: {} -> {}",
+            left.extra_bytes.as_ref().map_or(0, Vec::len),
+            right.extra_bytes.as_ref().map_or(0, Vec::len)
+        );
+    }
+}
+
+fn data_window(data: &[u8], start: usize) -> &[u8] {
+    &data[start.min(data.len())..(start + 32).min(data.len())]
+}
+
+fn first_difference(left: &[u8], right: &[u8]) -> Option<usize> {
+    let common = left.iter().zip(right).position(|(l, r)| l != r);
+    common.or((left.len() != right.len()).then(|| left.len().min(right.len())))
+}
+
+fn print_pixel_diff(left: &Png, right: &Png, image: Option<&str>) -> Result<(), Box<dyn Error>> {
+    println!("=============== Pixels ===============");
+    let diff = match diff_pixels(left, right) {
+        Ok(diff) => diff,
+        Err(e) => {
+            println!("Pixels not compared: {}", e);
+            return Ok(());
+        }
+    };
+
+    let total = diff.width as u64 * diff.height as u64;
+    println!(
+        "Changed pixels: {} of {} ({:.2}%)",
+        diff.changed_pixels,
+        total,
+        diff.changed_pixels as f64 * 100.0 / total.max(1) as f64
+    );
+    let names: &[&str] = match diff.channels.len() {
+        1 => &["Gray"],
+        2 => &["Gray", "Alpha"],
+        3 => &["Red", "Green", "Blue"],
+        _ => &["Red", "Green", "Blue", "Alpha"],
+    };
+    for (name, channel) in names.iter().zip(&diff.channels) {
+        println!(
+            "{}: {} samples differ, max delta {}",
+            name, channel.changed_samples, channel.max_delta
+        );
+    }
+
+    if let Some(path) = image {
+        let format = Format::from_path(path).unwrap_or(Format::Png);
+        let image = DynamicImage::ImageRgba8(difference_image(&left.get_buffer()?, &diff));
+        let mut writer = create_output(path)?;
+        writer.write_all(&encode(&image, format)?)?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+fn print_palette_report(report: &PaletteReport) {
+    println!("=============== Palette ===============");
+    println!("Index  Color    Alpha  Pixels    hIST");
+    for (i, (r, g, b)) in report.entries.iter().enumerate() {
+        println!(
+            "{:>5}  #{:02x}{:02x}{:02x}  {:>5}  {:>8}  {:>6}",
+            i,
+            r,
+            g,
+            b,
+            report.alpha[i],
+            report
+                .usage
+                .as_ref()
+                .map_or(String::from("-"), |usage| usage[i].to_string()),
+            report
+                .hist
+                .as_ref()
+                .and_then(|hist| hist.get(i))
+                .map_or(String::from("-"), u16::to_string)
+        );
+    }
+
+    if report.usage.is_none() {
+        println!("Not an indexed image, PLTE is only a suggested palette");
+    }
+    let unused = report.unused();
+    if !unused.is_empty() {
+        println!("Unused entries: {:?}", unused);
+    }
+    if report.out_of_range > 0 {
+        println!(
+            "Pixels with indices past the end of the palette: {}",
+            report.out_of_range
+        );
+    }
+    for group in &report.duplicates {
+        println!("Duplicate colors: {:?}", group);
+    }
+    for (a, b) in &report.near_duplicates {
+        println!(
+            "Colors differing only in the least significant bit: {} and {}",
+            a, b
+        );
+    }
+    for issue in &report.issues {
+        println!("Issue: {}", issue);
+    }
+}
+
+fn read_file(file: &str, limits: &LimitArgs) -> Result<Png, Box<dyn Error>> {
+    if file == "-" {
+        return parse_reader_with_limits(std::io::stdin().lock(), limits.into());
+    }
+
+    let file = File::open(file).map_err(|e| format!("Failed to open {}: {}", file, e))?;
+
+    parse_file_with_limits(file, limits.into())
+}
+
+fn create_output(file: &str) -> Result<Box<dyn Write>, Box<dyn Error>> {
+    if file == "-" {
+        return Ok(Box::new(BufWriter::new(std::io::stdout().lock())));
+    }
+
+    let file = File::create(file).map_err(|e| format!("Failed to create {}: {}", file, e))?;
+
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let limits = &cli.limits;
+
+    match cli.command {
+        Args::Check {
+            file,
+            no_decode,
+            json,
+        } => {
+            if !json {
+                print_banner();
+            }
+            let data = read_file(&file, limits)?;
+            let report = data.validate(&ValidateOptions { decode: !no_decode });
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_chunks(&data);
+                println!("====================================");
+                print_findings(&report);
+                println!("Extra bytes: {:?}", data.extra_bytes);
+            }
+        }
+        Args::Analyze { file, json } => {
+            if !json {
+                print_banner();
+            }
+            let data = read_file(&file, limits)?;
             let hidden_data = pngcheck::analyze::analyze(&data)?;
-            print_analyze_result(&hidden_data);
+            if hidden_data.is_empty() {
+                eprintln!("Value streams are only available for color images");
+            }
+            let report = steganalysis(&data)?;
+            if json {
+                // Streams are mostly binary, so they are base64 encoded
+                let streams: HashMap<_, _> = hidden_data
+                    .iter()
+                    .map(|(key, value)| {
+                        (key, base64::engine::general_purpose::STANDARD.encode(value))
+                    })
+                    .collect();
+                let output = serde_json::json!({
+                    "streams": streams,
+                    "steganalysis": report,
+                });
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            } else {
+                print_analyze_result(&hidden_data);
+                print_steganalysis(&report);
+            }
         }
-        Args::View { file } => {
-            let data = read_file(&file)?;
-            match data.ihdr() {
-                Some(ihdr) => {
-                    view_image(&data.get_scanlines()?, &ihdr);
-                }
-                None => eprintln!("IHDR chunk not found"),
+        Args::View {
+            file,
+            color_manage,
+            background,
+        } => {
+            let data = read_file(&file, limits)?;
+            if color_manage {
+                eprintln!("Color space: {}", ColorSpace::from_png(&data).source);
             }
+            let options = RenderOptions {
+                color_manage,
+                background: background.into(),
+            };
+            view_png(&data, &options)?;
         }
         Args::Ui { file } => {
-            let data = read_file(&file)?;
+            let data = read_file(&file, limits)?;
             tui::tui(&data)?;
         }
+        Args::Export {
+            file,
+            output,
+            format,
+            channel,
+            bit_plane,
+            pass,
+            frame,
+            visualize,
+            random_palette,
+            raw,
+            scale,
+        } => {
+            let format = match format {
+                Some(format) => format.into(),
+                None => Format::from_path(&output)
+                    .ok_or("Unknown output format, use --format png, ppm or bmp")?,
+            };
+            let source = match (pass, frame, visualize) {
+                (Some(pass), _, _) => Source::Pass(pass),
+                (_, Some(frame), _) => Source::Frame(frame),
+                (_, _, Some(key)) => Source::Visualization(key),
+                _ if random_palette => Source::RandomPalette,
+                _ => Source::Image,
+            };
+            let options = ExportOptions {
+                source,
+                channel,
+                bit_plane,
+                raw,
+                scale,
+            };
+
+            let data = read_file(&file, limits)?;
+            let image = export_image(&data, &options)?;
+            let mut writer = create_output(&output)?;
+            writer.write_all(&encode(&image, format)?)?;
+            writer.flush()?;
+        }
+        Args::Palette { file } => {
+            let data = read_file(&file, limits)?;
+            match analyze_palette(&data)? {
+                Some(report) => print_palette_report(&report),
+                None => println!("No PLTE chunk"),
+            }
+        }
+        Args::Diff { left, right, image } => {
+            let left = read_file(&left, limits)?;
+            let right = read_file(&right, limits)?;
+            print_chunk_diff(&left, &right);
+            print_pixel_diff(&left, &right, image.as_deref())?;
+        }
+        Args::Entropy {
+            file,
+            block,
+            step,
+            channel,
+            bit_plane,
+            image,
+        } => {
+            let data = read_file(&file, limits)?;
+            print_entropy_report(&entropy_report(&data));
+
+            let mut buffer = data.get_raw_buffer()?;
+            if let Some(name) = channel {
+                let channel = channel_index(&name, buffer.channels)
+                    .ok_or_else(|| format!("Channel {} not found", name))?;
+                buffer = buffer.extract_channel(channel);
+            }
+            if let Some(plane) = bit_plane {
+                if plane >= buffer.bit_depth {
+                    return Err(format!("Bit plane {} out of range", plane).into());
+                }
+                buffer = buffer.bit_plane(plane);
+            }
+            let map = entropy_map(&buffer, block, step.unwrap_or(block / 2));
+            let heatmap = heatmap(&map);
+            match image {
+                Some(path) => {
+                    let format = Format::from_path(&path)
+                        .ok_or("Unknown image format, use png, ppm or bmp")?;
+                    let mut writer = create_output(&path)?;
+                    writer.write_all(&encode(&heatmap.to_dynamic_image(), format)?)?;
+                    writer.flush()?;
+                }
+                None => view_image(&heatmap),
+            }
+            println!("Highest block entropy: {:.3}", map.max());
+        }
+        Args::Search {
+            file,
+            min_length,
+            pattern,
+            no_strings,
+        } => {
+            let mut options = SearchOptions {
+                min_length,
+                ..SearchOptions::default()
+            };
+            for pattern in pattern {
+                options.patterns.push(Regex::new(&pattern)?);
+            }
+
+            let data = read_file(&file, limits)?;
+            let mut layer = String::new();
+            for found in search(&data, &options)? {
+                if no_strings && found.kind == MatchKind::String {
+                    continue;
+                }
+                if found.layer != layer {
+                    println!("=============== {} ===============", found.layer);
+                    layer = found.layer;
+                }
+                println!(
+                    "{:#10x}  {:<16} {}",
+                    found.offset,
+                    found.kind.to_string(),
+                    found.preview
+                );
+            }
+        }
+        Args::Sanitize {
+            file,
+            output,
+            allow,
+            iccp_to_srgb,
+            dry_run,
+        } => {
+            let mut options = SanitizeOptions {
+                iccp_to_srgb,
+                ..SanitizeOptions::default()
+            };
+            options.allow.extend(allow);
+
+            let data = read_file(&file, limits)?;
+            let (sanitized, report) = sanitize(&data, &options)?;
+            print_sanitize_report(&data, &report);
+            let bytes = verify(&data, &sanitized, &options)?;
+            if let Some(output) = output.filter(|_| !dry_run) {
+                let mut writer = create_output(&output)?;
+                writer.write_all(&bytes)?;
+                writer.flush()?;
+                eprintln!("Wrote {} bytes, verified to parse cleanly", bytes.len());
+            }
+        }
+        Args::Optimize {
+            file,
+            output,
+            filter,
+            level,
+            strategy,
+            no_reduce,
+        } => {
+            let mut options = OptimizeOptions {
+                reduce: !no_reduce,
+                ..OptimizeOptions::default()
+            };
+            if !filter.is_empty() {
+                options.filters = filter.into_iter().map(FilterStrategy::from).collect();
+            }
+            if !level.is_empty() {
+                options.levels = level;
+            }
+            if !strategy.is_empty() {
+                options.strategies = strategy.into_iter().map(Strategy::from).collect();
+            }
+
+            let data = read_file(&file, limits)?;
+            let (optimized, report) = optimize(&data, &options)?;
+            print_optimize_report(&report);
+            let mut writer = create_output(&output)?;
+            write_png(&optimized, &mut writer)?;
+            writer.flush()?;
+        }
+        Args::Embed {
+            file,
+            output,
+            payload,
+            message,
+            method,
+            channel,
+            bit_plane,
+            lsb_first,
+            chunk_type,
+        } => {
+            let data = read_file(&file, limits)?;
+            let payload = match payload {
+                Some(path) => {
+                    std::fs::read(&path).map_err(|e| format!("Failed to open {}: {}", path, e))?
+                }
+                None => message.unwrap_or_default().into_bytes(),
+            };
+
+            let technique = match method {
+                MethodArg::Lsb => {
+                    let channels = data.color_type().ok_or("IHDR chunk not found")?.channels();
+                    let mut layout = LsbLayout::all_channels(channels);
+                    if !channel.is_empty() {
+                        layout.channels = channel
+                            .iter()
+                            .map(|name| {
+                                channel_index(name, channels)
+                                    .ok_or_else(|| format!("Unknown channel {}", name))
+                            })
+                            .collect::<Result<_, _>>()?;
+                    }
+                    layout.bit_planes = bit_plane;
+                    layout.msb_first = !lsb_first;
+                    Technique::Lsb(layout)
+                }
+                MethodArg::Chunk => Technique::Chunk(chunk_type.parse()?),
+                MethodArg::Trailing => Technique::Trailing,
+                MethodArg::AfterZlib => Technique::AfterZlib,
+            };
+
+            let embedded = embed(&data, &payload, &technique)?;
+            let mut writer = create_output(&output)?;
+            write_png(&embedded, &mut writer)?;
+            writer.flush()?;
+            eprintln!("Embedded {} bytes", payload.len());
+        }
+        Args::Uncrush { file, output } => {
+            let data = read_file(&file, limits)?;
+            if !data.is_cgbi() {
+                return Err("Not an Apple CgBI PNG file".into());
+            }
+            let mut writer = create_output(&output)?;
+            write_png(&data.uncrush()?, &mut writer)?;
+            writer.flush()?;
+        }
     };
 
     Ok(())
@@ -0,0 +1,676 @@
+use crate::png::compression::{compress_with, Strategy};
+use crate::png::filter::{choose_filter, filter, Filter};
+use crate::png::writer::{pack_row, write_png};
+use crate::png::{Chunk, ChunkType, ColorType, PixelBuffer, Png};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// How a filter is picked for each row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// The same filter for every row
+    Fixed(Filter),
+    /// The filter whose output has the smallest sum of absolute values, as signed bytes
+    MinSum,
+    /// The filter whose output compresses smallest, row by row
+    BruteForce,
+}
+
+impl fmt::Display for FilterStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterStrategy::Fixed(filter) => write!(f, "{:?}", filter),
+            FilterStrategy::MinSum => write!(f, "minimum sum"),
+            FilterStrategy::BruteForce => write!(f, "brute force"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OptimizeOptions {
+    pub filters: Vec<FilterStrategy>,
+    /// zlib levels, 0-10
+    pub levels: Vec<u8>,
+    pub strategies: Vec<Strategy>,
+    /// Try lossless bit depth and color type reductions
+    pub reduce: bool,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        let mut filters: Vec<_> = Filter::ALL.into_iter().map(FilterStrategy::Fixed).collect();
+        filters.extend([FilterStrategy::MinSum, FilterStrategy::BruteForce]);
+        Self {
+            filters,
+            levels: vec![9],
+            strategies: vec![Strategy::Default, Strategy::Filtered, Strategy::Rle],
+            reduce: true,
+        }
+    }
+}
+
+/// Filter strategy, zlib level and deflate strategy of an encoding.
+pub type Settings = (FilterStrategy, u8, Strategy);
+
+#[derive(Debug)]
+pub struct OptimizeReport {
+    pub input_size: usize,
+    pub output_size: usize,
+    pub idat_chunks: usize,
+    /// Color type and bit depth before and after
+    pub format: (String, String),
+    /// The settings of the smallest encoding, `None` when nothing beat the input
+    pub chosen: Option<Settings>,
+}
+
+/// The image in one possible representation: samples plus the IHDR, PLTE and tRNS that go with
+/// them.
+#[derive(Debug, Clone, PartialEq)]
+struct Candidate {
+    buffer: PixelBuffer,
+    color_type: u8,
+    plte: Option<Vec<u8>>,
+    trns: Option<Vec<u8>>,
+}
+
+impl Candidate {
+    fn describe(&self) -> String {
+        format!(
+            "{:?}, {} bits",
+            ColorType::from(self.color_type),
+            self.buffer.bit_depth
+        )
+    }
+}
+
+/// Re-encodes the image data losslessly, trying every combination of representation, filter
+/// strategy and compression setting, and keeps the smallest file. The output is not
+/// interlaced and has a single IDAT chunk; every other chunk is kept.
+pub fn optimize(
+    png: &Png,
+    options: &OptimizeOptions,
+) -> Result<(Png, OptimizeReport), Box<dyn Error>> {
+    if png.is_cgbi() {
+        return Err("Convert CgBI files with uncrush before optimizing".into());
+    }
+    let ihdr_chunk = png
+        .chunks
+        .iter()
+        .find(|chunk| chunk.chunk_type.as_str() == "IHDR")
+        .ok_or("IHDR chunk not found")?;
+    let chunk_data = |chunk_type: &str| {
+        png.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type.as_str() == chunk_type)
+            .map(|chunk| chunk.data.clone().unwrap_or_default())
+    };
+
+    let original = Candidate {
+        buffer: png.get_raw_buffer()?,
+        color_type: ihdr_chunk.data.as_ref().map_or(0, |data| data[9]),
+        plte: chunk_data("PLTE"),
+        trns: chunk_data("tRNS"),
+    };
+
+    let mut candidates = vec![original.clone()];
+    // Chunks describing samples in the original format, and animation frames sharing it, rule
+    // out reductions
+    let reducible = !png.chunks.iter().any(|chunk| {
+        matches!(
+            chunk.chunk_type.as_str(),
+            "sBIT" | "bKGD" | "hIST" | "acTL" | "fdAT"
+        )
+    });
+    if options.reduce && reducible {
+        candidates.extend(reductions(&original));
+        candidates.dedup();
+        // A reduction that changes the pixels is skipped rather than failing the whole run
+        let expected = canonical_pixels(png)?;
+        candidates
+            .retain(|candidate| *candidate == original || is_lossless(png, candidate, &expected));
+    }
+
+    let mut input = vec![];
+    write_png(png, &mut input)?;
+    let idat_chunks = png
+        .chunks
+        .iter()
+        .filter(|chunk| chunk.chunk_type.as_str() == "IDAT")
+        .count();
+
+    let mut best: Option<(Vec<u8>, &Candidate, Settings)> = None;
+    for candidate in &candidates {
+        let rows = packed_rows(&candidate.buffer);
+        let bytes_per_pixel =
+            (candidate.buffer.channels as usize * candidate.buffer.bit_depth as usize).div_ceil(8);
+
+        for &filter_strategy in &options.filters {
+            let filtered = filter_rows(&rows, bytes_per_pixel, filter_strategy);
+            for &level in &options.levels {
+                for &strategy in &options.strategies {
+                    let idat = compress_with(&filtered, level, strategy);
+                    if best
+                        .as_ref()
+                        .is_none_or(|(smallest, ..)| idat.len() < smallest.len())
+                    {
+                        best = Some((idat, candidate, (filter_strategy, level, strategy)));
+                    }
+                }
+            }
+        }
+    }
+
+    let (idat, candidate, settings) = best.ok_or("No filter or compression settings given")?;
+    let output = assemble(png, candidate, idat);
+    let mut bytes = vec![];
+    write_png(&output, &mut bytes)?;
+
+    if bytes.len() >= input.len() {
+        return Ok((
+            png.clone(),
+            OptimizeReport {
+                input_size: input.len(),
+                output_size: input.len(),
+                idat_chunks,
+                format: (original.describe(), original.describe()),
+                chosen: None,
+            },
+        ));
+    }
+
+    if canonical_pixels(&output)? != canonical_pixels(png)? {
+        return Err("Optimized image differs from the original".into());
+    }
+
+    Ok((
+        output,
+        OptimizeReport {
+            input_size: input.len(),
+            output_size: bytes.len(),
+            idat_chunks,
+            format: (original.describe(), candidate.describe()),
+            chosen: Some(settings),
+        },
+    ))
+}
+
+/// Whether the file built from `candidate` decodes to the `expected` canonical pixels.
+fn is_lossless(png: &Png, candidate: &Candidate, expected: &[[u16; 4]]) -> bool {
+    let rows = packed_rows(&candidate.buffer);
+    let filtered = filter_rows(&rows, 1, FilterStrategy::Fixed(Filter::None));
+    let output = assemble(
+        png,
+        candidate,
+        compress_with(&filtered, 0, Strategy::Default),
+    );
+    canonical_pixels(&output).is_ok_and(|pixels| pixels == expected)
+}
+
+/// Builds the output file: the new IHDR, PLTE and tRNS, in place of the old ones or else before
+/// the image data, a single IDAT where the first one was, and every other chunk unchanged.
+fn assemble(png: &Png, candidate: &Candidate, idat: Vec<u8>) -> Png {
+    // A suggested palette carried over from truecolor is not allowed in grayscale
+    let mut plte = candidate
+        .plte
+        .clone()
+        .filter(|_| !matches!(candidate.color_type, 0 | 4));
+    let mut trns = candidate.trns.clone();
+    let mut idat = Some(idat);
+    let mut chunks = vec![];
+    let new_chunk =
+        |chunk_type: &[u8; 4], data: Vec<u8>| Chunk::from_data(ChunkType::from(*chunk_type), data);
+    for chunk in &png.chunks {
+        match chunk.chunk_type.as_str() {
+            "IHDR" => {
+                let mut data = chunk.data.clone().unwrap_or_default();
+                data[8] = candidate.buffer.bit_depth;
+                data[9] = candidate.color_type;
+                data[12] = 0;
+                chunks.push(new_chunk(b"IHDR", data));
+            }
+            "PLTE" => chunks.extend(plte.take().map(|data| new_chunk(b"PLTE", data))),
+            "tRNS" | "IDAT" => {
+                chunks.extend(plte.take().map(|data| new_chunk(b"PLTE", data)));
+                chunks.extend(trns.take().map(|data| new_chunk(b"tRNS", data)));
+                if chunk.chunk_type == "IDAT" {
+                    chunks.extend(idat.take().map(|data| new_chunk(b"IDAT", data)));
+                }
+            }
+            _ => chunks.push(chunk.clone()),
+        }
+    }
+
+    Png::new(chunks, png.extra_bytes.clone()).with_limits(png.limits.clone())
+}
+
+fn packed_rows(buffer: &PixelBuffer) -> Vec<Vec<u8>> {
+    buffer
+        .rows()
+        .map(|row| pack_row(row, buffer.bit_depth))
+        .collect()
+}
+
+/// Filters every row, prefixing its filter type byte.
+fn filter_rows(rows: &[Vec<u8>], bytes_per_pixel: usize, strategy: FilterStrategy) -> Vec<u8> {
+    let mut data = vec![];
+    let mut previous = vec![0; rows.first().map_or(0, Vec::len)];
+    for row in rows {
+        let (filter_type, filtered) = match strategy {
+            FilterStrategy::Fixed(filter_type) => (
+                filter_type,
+                filter(filter_type, &previous, row, bytes_per_pixel),
+            ),
+            FilterStrategy::MinSum => choose_filter(&previous, row, bytes_per_pixel),
+            FilterStrategy::BruteForce => Filter::ALL
+                .into_iter()
+                .map(|f| (f, filter(f, &previous, row, bytes_per_pixel)))
+                .min_by_key(|(_, filtered)| compress_with(filtered, 6, Strategy::Default).len())
+                .unwrap(),
+        };
+        data.push(filter_type as u8);
+        data.extend(filtered);
+        previous.clone_from(row);
+    }
+    data
+}
+
+/// Applies the lossless reductions that fit the image, returning the intermediate
+/// representations worth trying.
+fn reductions(original: &Candidate) -> Vec<Candidate> {
+    let mut current = original.clone();
+    for reduce in [reduce_16_to_8, drop_alpha, to_grayscale] {
+        if let Some(reduced) = reduce(&current) {
+            current = reduced;
+        }
+    }
+
+    let mut candidates = vec![current.clone()];
+    if current.color_type == 3 {
+        candidates.extend(reduce_palette_depth(&current));
+    } else {
+        candidates.extend(reduce_gray_depth(&current));
+        candidates.extend(to_palette(&current));
+    }
+    candidates
+}
+
+fn map_samples(
+    candidate: &Candidate,
+    channels: u8,
+    bit_depth: u8,
+    color_type: u8,
+    f: impl Fn(&[u16]) -> Vec<u16>,
+) -> Candidate {
+    let buffer = &candidate.buffer;
+    let mut output = PixelBuffer::new(buffer.width, buffer.height, channels, bit_depth);
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            let pixel: Vec<u16> = (0..buffer.channels)
+                .map(|c| buffer.sample(x, y, c))
+                .collect();
+            for (c, value) in f(&pixel).into_iter().enumerate() {
+                output.set_sample(x, y, c as u8, value);
+            }
+        }
+    }
+    Candidate {
+        buffer: output,
+        color_type,
+        plte: candidate.plte.clone(),
+        trns: candidate.trns.clone(),
+    }
+}
+
+fn samples(candidate: &Candidate) -> impl Iterator<Item = Vec<u16>> + '_ {
+    let buffer = &candidate.buffer;
+    (0..buffer.height).flat_map(move |y| {
+        (0..buffer.width).map(move |x| {
+            (0..buffer.channels)
+                .map(|c| buffer.sample(x, y, c))
+                .collect()
+        })
+    })
+}
+
+fn trns_values(trns: &[u8]) -> Vec<u16> {
+    trns.chunks_exact(2)
+        .map(|v| u16::from_be_bytes([v[0], v[1]]))
+        .collect()
+}
+
+fn trns_bytes(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+/// 16 to 8 bits when every sample is a multiple of 257, so the low byte repeats the high byte.
+fn reduce_16_to_8(candidate: &Candidate) -> Option<Candidate> {
+    let buffer = &candidate.buffer;
+    if buffer.bit_depth != 16 {
+        return None;
+    }
+    let trns = candidate.trns.as_deref().map(trns_values);
+    if !buffer.data().chunks_exact(2).all(|v| v[0] == v[1])
+        || !trns.iter().flatten().all(|v| v % 257 == 0)
+    {
+        return None;
+    }
+
+    let mut reduced = map_samples(
+        candidate,
+        buffer.channels,
+        8,
+        candidate.color_type,
+        |pixel| pixel.iter().map(|v| v / 257).collect(),
+    );
+    reduced.trns = trns.map(|trns| trns_bytes(&trns.iter().map(|v| v / 257).collect::<Vec<_>>()));
+    Some(reduced)
+}
+
+/// Removes an alpha channel that is opaque everywhere.
+fn drop_alpha(candidate: &Candidate) -> Option<Candidate> {
+    let buffer = &candidate.buffer;
+    if !matches!(candidate.color_type, 4 | 6) {
+        return None;
+    }
+    let max = ((1u32 << buffer.bit_depth) - 1) as u16;
+    let alpha = buffer.channels - 1;
+    if !buffer.channel(alpha).iter().all(|v| v == max) {
+        return None;
+    }
+
+    Some(map_samples(
+        candidate,
+        alpha,
+        buffer.bit_depth,
+        candidate.color_type - 4,
+        |pixel| pixel[..alpha as usize].to_vec(),
+    ))
+}
+
+/// Truecolor to grayscale when every pixel, and the tRNS color, is gray.
+fn to_grayscale(candidate: &Candidate) -> Option<Candidate> {
+    if !matches!(candidate.color_type, 2 | 6) {
+        return None;
+    }
+    let trns = candidate.trns.as_deref().map(trns_values);
+    let gray = |pixel: &[u16]| pixel[0] == pixel[1] && pixel[1] == pixel[2];
+    if !samples(candidate).all(|pixel| gray(&pixel)) || trns.as_deref().is_some_and(|t| !gray(t)) {
+        return None;
+    }
+
+    let channels = candidate.buffer.channels - 2;
+    let mut reduced = map_samples(
+        candidate,
+        channels,
+        candidate.buffer.bit_depth,
+        candidate.color_type - 2,
+        |pixel| {
+            let mut gray = vec![pixel[0]];
+            gray.extend(pixel.get(3));
+            gray
+        },
+    );
+    reduced.trns = trns.map(|trns| trns_bytes(&trns[..1]));
+    Some(reduced)
+}
+
+/// 8-bit grayscale without alpha at the lowest bit depth that holds every value exactly, as 0,
+/// 85, 170 and 255 fit in 2 bits.
+fn reduce_gray_depth(candidate: &Candidate) -> Option<Candidate> {
+    let buffer = &candidate.buffer;
+    if candidate.color_type != 0 || buffer.bit_depth != 8 || candidate.trns.is_some() {
+        return None;
+    }
+
+    let bit_depth = [1u8, 2, 4].into_iter().find(|&depth| {
+        let scale = 255 / ((1u16 << depth) - 1);
+        buffer
+            .data()
+            .iter()
+            .all(|&v| (v as u16).is_multiple_of(scale))
+    })?;
+    let scale = 255 / ((1u16 << bit_depth) - 1);
+    Some(map_samples(candidate, 1, bit_depth, 0, |pixel| {
+        vec![pixel[0] / scale]
+    }))
+}
+
+/// Pixels of 8-bit grayscale or truecolor images, with or without alpha, as RGBA.
+fn rgba(pixel: &[u16], color_type: u8) -> [u8; 4] {
+    let pixel: Vec<u8> = pixel.iter().map(|&v| v as u8).collect();
+    match color_type {
+        0 => [pixel[0], pixel[0], pixel[0], 255],
+        4 => [pixel[0], pixel[0], pixel[0], pixel[1]],
+        2 => [pixel[0], pixel[1], pixel[2], 255],
+        _ => [pixel[0], pixel[1], pixel[2], pixel[3]],
+    }
+}
+
+/// Images with at most 256 colors to a palette, translucent entries first so that tRNS stays
+/// short.
+fn to_palette(candidate: &Candidate) -> Option<Candidate> {
+    let buffer = &candidate.buffer;
+    if buffer.bit_depth != 8 || candidate.trns.is_some() {
+        return None;
+    }
+
+    let mut colors: Vec<[u8; 4]> = vec![];
+    let mut seen = HashMap::new();
+    for pixel in samples(candidate) {
+        let color = rgba(&pixel, candidate.color_type);
+        if seen.insert(color, 0).is_none() {
+            if colors.len() == 256 {
+                return None;
+            }
+            colors.push(color);
+        }
+    }
+    colors.sort_by_key(|color| color[3] == 255);
+    for (i, color) in colors.iter().enumerate() {
+        seen.insert(*color, i as u8);
+    }
+
+    let bit_depth = match colors.len() {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    };
+    let mut reduced = map_samples(candidate, 1, bit_depth, 3, |pixel| {
+        vec![seen[&rgba(pixel, candidate.color_type)] as u16]
+    });
+    reduced.plte = Some(
+        colors
+            .iter()
+            .flat_map(|color| color[..3].to_vec())
+            .collect(),
+    );
+    let alpha: Vec<u8> = colors
+        .iter()
+        .map(|color| color[3])
+        .take_while(|&a| a != 255)
+        .collect();
+    reduced.trns = (!alpha.is_empty()).then_some(alpha);
+    Some(reduced)
+}
+
+/// Indexed images at the lowest bit depth that holds every index.
+fn reduce_palette_depth(candidate: &Candidate) -> Option<Candidate> {
+    let buffer = &candidate.buffer;
+    let max = buffer.data().iter().copied().max().unwrap_or(0);
+    let bit_depth = [1u8, 2, 4, 8]
+        .into_iter()
+        .find(|&depth| (max as u16) < 1 << depth)?;
+    if bit_depth >= buffer.bit_depth {
+        return None;
+    }
+
+    Some(map_samples(candidate, 1, bit_depth, 3, |pixel| {
+        pixel.to_vec()
+    }))
+}
+
+/// Every pixel as 16-bit RGBA, with tRNS applied, to compare images across representations.
+pub(crate) fn canonical_pixels(png: &Png) -> Result<Vec<[u16; 4]>, Box<dyn Error>> {
+    let ihdr = png.ihdr().ok_or("IHDR chunk not found")?;
+    let raw = png.get_raw_buffer()?;
+    let buffer = png.get_buffer()?;
+    let max = (1u32 << buffer.bit_depth) - 1;
+    let scale = |v: u16| (v as u32 * 65535 / max) as u16;
+    let key = match ihdr.color_type {
+        ColorType::Indexed => None,
+        _ => png
+            .chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type.as_str() == "tRNS")
+            .and_then(|chunk| chunk.data.as_deref().map(trns_values)),
+    };
+
+    let mut pixels = vec![];
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            let sample = |c: u8| scale(buffer.sample(x, y, c));
+            let mut pixel = match buffer.channels {
+                1 => [sample(0), sample(0), sample(0), 65535],
+                2 => [sample(0), sample(0), sample(0), sample(1)],
+                3 => [sample(0), sample(1), sample(2), 65535],
+                _ => [sample(0), sample(1), sample(2), sample(3)],
+            };
+            let stored: Vec<u16> = (0..raw.channels).map(|c| raw.sample(x, y, c)).collect();
+            if key.as_deref() == Some(&stored[..]) {
+                pixel[3] = 0;
+            }
+            pixels.push(pixel);
+        }
+    }
+    Ok(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, ihdr};
+    use crate::png::compression::compress;
+
+    /// A 16-bit RGBA image split over two IDAT chunks, gray and opaque with 3 levels.
+    fn wasteful_png() -> Png {
+        let mut data = vec![];
+        for y in 0..16u8 {
+            data.push(0);
+            for x in 0..16u8 {
+                let level = [0u8, 128, 255][((x + y) % 3) as usize];
+                data.extend([level, level, level, level, level, level, 255, 255]);
+            }
+        }
+        let compressed = compress(&data).unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        Png::new(
+            vec![
+                ihdr(16, 16, 16, 6),
+                chunk(b"tEXt", b"Comment\0kept"),
+                chunk(b"IDAT", first),
+                chunk(b"IDAT", second),
+                chunk(b"IEND", &[]),
+            ],
+            None,
+        )
+    }
+
+    #[test]
+    fn test_optimize() {
+        let png = wasteful_png();
+
+        let (optimized, report) = optimize(&png, &OptimizeOptions::default()).unwrap();
+
+        assert!(report.output_size < report.input_size);
+        assert_eq!(report.format.1, "Indexed, 2 bits");
+        let types: Vec<_> = optimized
+            .chunks
+            .iter()
+            .map(|chunk| chunk.chunk_type.to_string())
+            .collect();
+        assert_eq!(types, vec!["IHDR", "tEXt", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(
+            canonical_pixels(&optimized).unwrap(),
+            canonical_pixels(&png).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_optimize_without_reduction() {
+        let options = OptimizeOptions {
+            reduce: false,
+            ..OptimizeOptions::default()
+        };
+
+        let (optimized, report) = optimize(&wasteful_png(), &options).unwrap();
+
+        assert_eq!(report.format.0, report.format.1);
+        assert_eq!(
+            optimized.get_raw_buffer().unwrap(),
+            wasteful_png().get_raw_buffer().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_optimize_drops_suggested_palette_for_grayscale() {
+        let data: Vec<u8> = (0..8u8)
+            .flat_map(|y| {
+                let row = (0..8u8).flat_map(move |x| [x * 30, y].repeat(3));
+                std::iter::once(0).chain(row)
+            })
+            .collect();
+        let png = Png::new(
+            vec![
+                ihdr(8, 8, 16, 2),
+                chunk(b"PLTE", &[0, 0, 0, 255, 255, 255]),
+                chunk(b"IDAT", &compress(&data).unwrap()),
+                chunk(b"IEND", &[]),
+            ],
+            None,
+        );
+
+        let (optimized, report) = optimize(&png, &OptimizeOptions::default()).unwrap();
+
+        assert!(report.format.1.starts_with("Grayscale"));
+        assert!(!optimized
+            .chunks
+            .iter()
+            .any(|chunk| chunk.chunk_type == "PLTE"));
+    }
+
+    #[test]
+    fn test_reduce_gray_depth() {
+        let candidate = Candidate {
+            buffer: PixelBuffer::from_data(4, 1, 1, 8, vec![0, 85, 170, 255]),
+            color_type: 0,
+            plte: None,
+            trns: None,
+        };
+
+        let reduced = reduce_gray_depth(&candidate).unwrap();
+
+        assert_eq!(reduced.buffer.bit_depth, 2);
+        assert_eq!(reduced.buffer.data(), [0, 1, 2, 3]);
+
+        // The same levels in color stay truecolor
+        let rgb = Candidate {
+            buffer: PixelBuffer::from_data(4, 1, 3, 8, [0, 85, 170, 255].repeat(3)),
+            color_type: 2,
+            ..candidate
+        };
+        assert!(reduce_gray_depth(&rgb).is_none());
+    }
+
+    #[test]
+    fn test_filter_rows_min_sum() {
+        // A smooth gradient, which Sub turns into a run of ones
+        let rows = vec![(0..16).collect::<Vec<u8>>()];
+
+        let filtered = filter_rows(&rows, 1, FilterStrategy::MinSum);
+
+        assert_eq!(filtered[0], Filter::Sub as u8);
+        assert!(filtered[2..].iter().all(|&b| b == 1));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::png::{plte_not_allowed, ColorType, PixelBuffer, Png, Transparency};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Findings about a file's PLTE chunk and how the image uses it.
+#[derive(Debug)]
+pub struct PaletteReport {
+    pub entries: Vec<(u8, u8, u8)>,
+    /// tRNS alpha per entry, 255 for entries past the end of tRNS
+    pub alpha: Vec<u8>,
+    /// Pixels referencing each entry, `None` when the image is not indexed
+    pub usage: Option<Vec<u64>>,
+    /// Pixels with an index past the end of the palette
+    pub out_of_range: u64,
+    pub hist: Option<Vec<u16>>,
+    /// Groups of entries with the same color and alpha
+    pub duplicates: Vec<Vec<usize>>,
+    /// Pairs of different entries whose samples differ only in the least significant bit
+    pub near_duplicates: Vec<(usize, usize)>,
+    /// Problems with the PLTE, tRNS and hIST chunks
+    pub issues: Vec<String>,
+}
+
+impl PaletteReport {
+    pub fn unused(&self) -> Vec<usize> {
+        self.usage.as_ref().map_or(vec![], |usage| {
+            (0..usage.len()).filter(|&i| usage[i] == 0).collect()
+        })
+    }
+}
+
+/// Inspects the palette, returning `None` for files without a PLTE chunk.
+pub fn analyze_palette(png: &Png) -> Result<Option<PaletteReport>, Box<dyn Error>> {
+    let Some(plte_chunk) = png.chunks.iter().find(|chunk| chunk.chunk_type == "PLTE") else {
+        return Ok(None);
+    };
+    let ihdr = png.ihdr().ok_or("IHDR chunk not found")?;
+    let plte = png.plte().ok_or("PLTE chunk could not be parsed")?;
+    let entries = plte.entries;
+
+    let mut issues = vec![];
+    if plte_chunk.length % 3 != 0 {
+        issues.push(format!(
+            "PLTE length {} is not a multiple of 3",
+            plte_chunk.length
+        ));
+    }
+    if entries.is_empty() || entries.len() > 256 {
+        issues.push(format!(
+            "PLTE has {} entries, expected 1 to 256",
+            entries.len()
+        ));
+    }
+
+    let indexed = matches!(ihdr.color_type, ColorType::Indexed);
+    if indexed && entries.len() > 1 << ihdr.bit_depth.min(8) {
+        issues.push(format!(
+            "PLTE has {} entries, more than the {} a {}-bit image can index",
+            entries.len(),
+            1 << ihdr.bit_depth.min(8),
+            ihdr.bit_depth
+        ));
+    }
+    issues.extend(plte_not_allowed(&ihdr.color_type));
+
+    let mut alpha = vec![255; entries.len()];
+    if let Some(Transparency::Alpha(trns)) = plte.transparency {
+        if trns.len() > entries.len() {
+            issues.push(format!(
+                "tRNS has {} entries, more than the {} in PLTE",
+                trns.len(),
+                entries.len()
+            ));
+        }
+        for (alpha, value) in alpha.iter_mut().zip(trns) {
+            *alpha = value;
+        }
+    }
+
+    let mut out_of_range = 0;
+    let usage = if indexed {
+        let mut usage = vec![0; entries.len()];
+        for &index in png.get_raw_buffer()?.data() {
+            match usage.get_mut(index as usize) {
+                Some(count) => *count += 1,
+                None => out_of_range += 1,
+            }
+        }
+        Some(usage)
+    } else {
+        None
+    };
+
+    let hist = png.hist();
+    if let Some(hist) = &hist {
+        if hist.len() != entries.len() {
+            issues.push(format!(
+                "hIST has {} entries, PLTE has {}",
+                hist.len(),
+                entries.len()
+            ));
+        }
+        if let Some(usage) = &usage {
+            let used_without_hist: Vec<_> = (0..usage.len().min(hist.len()))
+                .filter(|&i| usage[i] > 0 && hist[i] == 0)
+                .collect();
+            let hist_without_use: Vec<_> = (0..usage.len().min(hist.len()))
+                .filter(|&i| usage[i] == 0 && hist[i] > 0)
+                .collect();
+            if !used_without_hist.is_empty() {
+                issues.push(format!(
+                    "Entries used by pixels but zero in hIST: {:?}",
+                    used_without_hist
+                ));
+            }
+            if !hist_without_use.is_empty() {
+                issues.push(format!(
+                    "Entries unused by pixels but non-zero in hIST: {:?}",
+                    hist_without_use
+                ));
+            }
+        }
+    }
+
+    let colors: Vec<[u8; 4]> = entries
+        .iter()
+        .zip(&alpha)
+        .map(|(&(r, g, b), &a)| [r, g, b, a])
+        .collect();
+
+    let mut groups: HashMap<[u8; 4], Vec<usize>> = HashMap::new();
+    for (i, color) in colors.iter().enumerate() {
+        groups.entry(*color).or_default().push(i);
+    }
+    let mut duplicates: Vec<_> = groups.into_values().filter(|g| g.len() > 1).collect();
+    duplicates.sort();
+
+    let mut near_duplicates = vec![];
+    for i in 0..colors.len() {
+        for j in i + 1..colors.len() {
+            let lsb_only = colors[i].iter().zip(&colors[j]).all(|(a, b)| a ^ b <= 1);
+            if lsb_only && colors[i] != colors[j] {
+                near_duplicates.push((i, j));
+            }
+        }
+    }
+
+    Ok(Some(PaletteReport {
+        entries,
+        alpha,
+        usage,
+        out_of_range,
+        hist,
+        duplicates,
+        near_duplicates,
+        issues,
+    }))
+}
+
+/// Replaces every palette entry, or every sample value of other images, with a random color,
+/// so that entries or values that look alike become clearly distinct. Alpha is dropped.
+pub fn randomize_palette(png: &Png) -> Result<PixelBuffer, Box<dyn Error>> {
+    let ihdr = png.ihdr().ok_or("IHDR chunk not found")?;
+
+    if matches!(ihdr.color_type, ColorType::Indexed) {
+        let indices = png.get_raw_buffer()?;
+        let data = indices
+            .data()
+            .iter()
+            .flat_map(|&index| random_color(index as u64))
+            .collect();
+        return Ok(PixelBuffer::from_data(
+            indices.width,
+            indices.height,
+            3,
+            8,
+            data,
+        ));
+    }
+
+    let buffer = png.get_buffer()?.to_8bit();
+    let color_channels = if buffer.channels >= 3 { 3 } else { 1 };
+    let data = buffer
+        .data()
+        .chunks_exact(buffer.channels as usize)
+        .flat_map(|pixel| {
+            pixel[..color_channels]
+                .iter()
+                .enumerate()
+                .map(|(c, &value)| random_color(((c as u64) << 8) | value as u64)[c])
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    Ok(PixelBuffer::from_data(
+        buffer.width,
+        buffer.height,
+        color_channels as u8,
+        8,
+        data,
+    ))
+}
+
+/// A fixed pseudo-random color for `seed` (splitmix64), so exports are reproducible.
+fn random_color(seed: u64) -> [u8; 3] {
+    let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    [z as u8, (z >> 8) as u8, (z >> 16) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, idat, ihdr};
+
+    fn indexed_png(plte: Vec<u8>, indices: u8) -> Png {
+        Png::new(
+            vec![ihdr(4, 1, 2, 3), chunk(b"PLTE", &plte), idat(&[0, indices])],
+            None,
+        )
+    }
+
+    #[test]
+    fn test_analyze_palette() {
+        // Indices 0, 0, 2, 2 into a palette where entry 1 duplicates 0 and 2 is an LSB away
+        let png = indexed_png(vec![10, 20, 30, 10, 20, 30, 11, 20, 30], 0b0000_1010);
+
+        let report = analyze_palette(&png).unwrap().unwrap();
+
+        assert_eq!(report.usage, Some(vec![2, 0, 2]));
+        assert_eq!(report.unused(), vec![1]);
+        assert_eq!(report.duplicates, vec![vec![0, 1]]);
+        assert_eq!(report.near_duplicates, vec![(0, 2), (1, 2)]);
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn test_palette_too_long_for_bit_depth() {
+        let png = indexed_png(vec![0; 15], 0);
+
+        let report = analyze_palette(&png).unwrap().unwrap();
+
+        assert_eq!(
+            report.issues,
+            vec!["PLTE has 5 entries, more than the 4 a 2-bit image can index"]
+        );
+    }
+
+    #[test]
+    fn test_randomize_palette() {
+        let png = indexed_png(vec![0; 9], 0b0001_1000);
+
+        let buffer = randomize_palette(&png).unwrap();
+
+        assert_eq!(buffer.pixel(0, 0), buffer.pixel(3, 0));
+        assert_ne!(buffer.pixel(0, 0), buffer.pixel(1, 0));
+    }
+}
@@ -1,10 +1,27 @@
+mod buffer;
 mod chunk;
-mod compression;
-mod filter;
+mod chunk_type;
+pub mod compression;
+pub mod filter;
+mod limits;
+mod rows;
 pub mod scanline;
-
-pub use crate::png::chunk::{Chunk, ColorType, Gama, ParsedChunk, Phys, SrgbRenderingIntent, IHDR};
+mod validate;
+pub mod writer;
+
+pub use crate::png::buffer::{ChannelView, PixelBuffer};
+pub use crate::png::chunk::{
+    Actl, Bkgd, Chrm, Chunk, ChunkField, ColorType, CompressionMethod, Fctl, FilterMethod, Gama,
+    Iccp, InterlaceMethod, ParsedChunk, Phys, SrgbRenderingIntent, ADAM7, IHDR,
+};
+pub use crate::png::chunk_type::ChunkType;
+pub use crate::png::limits::{is_limit_error, limit_error, Limits};
+pub use crate::png::rows::{IdatReader, Row, RowDecoder};
 use crate::png::scanline::Scanline;
+pub(crate) use crate::png::validate::plte_not_allowed;
+pub use crate::png::validate::{Code, Finding, Report, Severity, ValidateOptions};
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use std::io::Read;
 
 pub const HEADER: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
 
@@ -29,9 +46,11 @@ pub struct PLTE {
     pub transparency: Option<Transparency>,
 }
 
+#[derive(Debug, Clone)]
 pub struct Png {
     pub chunks: Vec<Chunk>,
     pub extra_bytes: Option<Vec<u8>>,
+    pub limits: Limits,
 }
 
 impl Png {
@@ -39,17 +58,60 @@ impl Png {
         Self {
             chunks,
             extra_bytes,
+            limits: Limits::default(),
         }
     }
 
-    pub fn color_type(&self) -> ColorType {
-        self.ihdr().map(|ihdr| ihdr.color_type).unwrap()
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Checks the file against the PNG specification, reporting every problem found.
+    pub fn validate(&self, options: &ValidateOptions) -> Report {
+        validate::validate(self, options)
+    }
+
+    /// File offset of the bytes following the last chunk, if the chunks were read from a file.
+    pub fn extra_bytes_offset(&self) -> Option<u64> {
+        self.extra_bytes.as_ref()?;
+        self.chunks.last()?.end_offset()
+    }
+
+    /// Apple's Xcode writes a leading CgBI chunk in iOS-optimized PNG files.
+    pub fn is_cgbi(&self) -> bool {
+        self.chunks.first().is_some_and(|c| c.chunk_type == "CgBI")
+    }
+
+    pub fn color_type(&self) -> Option<ColorType> {
+        self.ihdr().map(|ihdr| ihdr.color_type)
+    }
+
+    /// Parses every chunk with the Png's limits. The decompressed text size limit is shared by
+    /// all zTXt, iTXt and iCCP chunks, so many small chunks cannot add up to a large allocation.
+    pub fn parsed_chunks(&self) -> Vec<ParsedChunk> {
+        let mut limits = self.limits.clone();
+        self.chunks
+            .iter()
+            .map(|chunk| {
+                let parsed = chunk.parse_with_limits(&limits);
+                let decompressed = match &parsed {
+                    // Latin-1 text decodes to one char per byte
+                    ParsedChunk::Ztxt(ztxt) => ztxt.text.chars().count(),
+                    ParsedChunk::Itxt(itxt) if itxt.compression_flag == 1 => itxt.text.len(),
+                    ParsedChunk::Iccp(iccp) => iccp.profile.len(),
+                    _ => 0,
+                };
+                limits.max_text_size = limits.max_text_size.saturating_sub(decompressed);
+                parsed
+            })
+            .collect()
     }
 
     pub fn ihdr(&self) -> Option<IHDR> {
         let chunk = self.chunks.iter().find(|c| c.chunk_type == "IHDR")?;
 
-        match chunk.parse() {
+        match chunk.parse_with_limits(&self.limits) {
             ParsedChunk::IHDR(ihdr) => Some(ihdr),
             _ => None,
         }
@@ -58,7 +120,7 @@ impl Png {
     pub fn plte(&self) -> Option<PLTE> {
         let chunk = self.chunks.iter().find(|c| c.chunk_type == "PLTE")?;
 
-        match chunk.parse() {
+        match chunk.parse_with_limits(&self.limits) {
             ParsedChunk::PLTE(entries) => Some(PLTE {
                 entries,
                 transparency: self.trns(),
@@ -70,18 +132,20 @@ impl Png {
     pub fn trns(&self) -> Option<Transparency> {
         let chunk = self.chunks.iter().find(|c| c.chunk_type == "tRNS")?;
 
-        match chunk.parse() {
+        match chunk.parse_with_limits(&self.limits) {
             ParsedChunk::Trns(data) => match self.ihdr()?.color_type {
-                ColorType::Grayscale => {
-                    Some(Transparency::Grey(u16::from_be_bytes([data[0], data[1]])))
-                }
-                ColorType::Truecolor => {
-                    Some(Transparency::Rgb(
-                        u16::from_be_bytes([data[0], data[1]]),
-                        u16::from_be_bytes([data[2], data[3]]),
-                        u16::from_be_bytes([data[4], data[5]]),
-                    ))
-                }
+                ColorType::Grayscale => match data[..] {
+                    [g0, g1] => Some(Transparency::Grey(u16::from_be_bytes([g0, g1]))),
+                    _ => None,
+                },
+                ColorType::Truecolor => match data[..] {
+                    [r0, r1, g0, g1, b0, b1] => Some(Transparency::Rgb(
+                        u16::from_be_bytes([r0, r1]),
+                        u16::from_be_bytes([g0, g1]),
+                        u16::from_be_bytes([b0, b1]),
+                    )),
+                    _ => None,
+                },
                 ColorType::Indexed => Some(Transparency::Alpha(data.clone())),
                 _ => None,
             },
@@ -92,7 +156,7 @@ impl Png {
     pub fn phys(&self) -> Option<Phys> {
         let chunk = self.chunks.iter().find(|c| c.chunk_type == "pHYs")?;
 
-        match chunk.parse() {
+        match chunk.parse_with_limits(&self.limits) {
             ParsedChunk::Phys(phys) => Some(phys),
             _ => None,
         }
@@ -101,7 +165,7 @@ impl Png {
     pub fn srgb(&self) -> Option<SrgbRenderingIntent> {
         let chunk = self.chunks.iter().find(|c| c.chunk_type == "sRGB")?;
 
-        match chunk.parse() {
+        match chunk.parse_with_limits(&self.limits) {
             ParsedChunk::Srgb(intent) => Some(intent),
             _ => None,
         }
@@ -110,69 +174,426 @@ impl Png {
     pub fn gama(&self) -> Option<Gama> {
         let chunk = self.chunks.iter().find(|c| c.chunk_type == "gAMA")?;
 
-        match chunk.parse() {
+        match chunk.parse_with_limits(&self.limits) {
             ParsedChunk::Gama(gama) => Some(gama),
             _ => None,
         }
     }
 
+    pub fn chrm(&self) -> Option<Chrm> {
+        let chunk = self.chunks.iter().find(|c| c.chunk_type == "cHRM")?;
+
+        match chunk.parse_with_limits(&self.limits) {
+            ParsedChunk::Chrm(chrm) => Some(chrm),
+            _ => None,
+        }
+    }
+
+    pub fn iccp(&self) -> Option<Iccp> {
+        let chunk = self.chunks.iter().find(|c| c.chunk_type == "iCCP")?;
+
+        match chunk.parse_with_limits(&self.limits) {
+            ParsedChunk::Iccp(iccp) => Some(iccp),
+            _ => None,
+        }
+    }
+
+    pub fn hist(&self) -> Option<Vec<u16>> {
+        let chunk = self.chunks.iter().find(|c| c.chunk_type == "hIST")?;
+
+        match chunk.parse_with_limits(&self.limits) {
+            ParsedChunk::Hist(hist) => Some(hist),
+            _ => None,
+        }
+    }
+
+    pub fn bkgd(&self) -> Option<Bkgd> {
+        let chunk = self.chunks.iter().find(|c| c.chunk_type == "bKGD")?;
+
+        match chunk.parse_with_limits(&self.limits) {
+            ParsedChunk::Bkgd(bkgd) => Some(bkgd),
+            _ => None,
+        }
+    }
+
     pub fn get_pixels(&self) -> Result<Vec<Pixel>, std::io::Error> {
-        let scanlines = self.get_scanlines()?;
+        let ihdr = self.require_ihdr()?;
+        if matches!(ihdr.interlace_method, InterlaceMethod::Adam7) {
+            let scanlines = self.get_scanlines()?;
+            return Ok(scanlines.into_iter().flat_map(|s| s.pixels).collect());
+        }
 
-        let pixels = scanlines.iter().fold(vec![], |mut acc, scanline| {
-            acc.extend(scanline.pixels.iter().cloned());
-            acc
-        });
+        let plte = self.plte();
+        if matches!(ihdr.color_type, ColorType::Indexed) && plte.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "PLTE chunk not found",
+            ));
+        }
+
+        let mut rows = self.rows()?;
+        let mut pixels = Vec::with_capacity(ihdr.width as usize * ihdr.height as usize);
+        while let Some(row) = rows.next_row()? {
+            let start = pixels.len();
+            pixels.extend(scanline::parse_pixels(
+                &ihdr,
+                plte.as_ref(),
+                row.data,
+                row.width,
+            ));
+            if self.is_cgbi() {
+                scanline::convert_cgbi_pixels(&mut pixels[start..]);
+            }
+        }
 
         Ok(pixels)
     }
 
     pub fn get_scanlines(&self) -> Result<Vec<Scanline>, std::io::Error> {
-        let ihdr = match self.ihdr() {
-            Some(ihdr) => ihdr,
-            None => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    "IHDR chunk not found",
-                ))
+        let ihdr = self.require_ihdr()?;
+
+        let mut scanlines = scanline::decode_scanlines(&ihdr, self.plte().as_ref(), self.rows()?)?;
+
+        if self.is_cgbi() {
+            for scanline in &mut scanlines {
+                scanline::convert_cgbi_pixels(&mut scanline.pixels);
             }
+        }
+
+        Ok(scanlines)
+    }
+
+    /// Decodes the image into contiguous samples. Indexed images are expanded to 8-bit RGBA and
+    /// CgBI images are converted to straight RGBA.
+    pub fn get_buffer(&self) -> Result<PixelBuffer, std::io::Error> {
+        self.expand_buffer(self.get_raw_buffer()?)
+    }
+
+    /// Decodes the image into contiguous samples exactly as stored, indexed images hold palette
+    /// indices.
+    pub fn get_raw_buffer(&self) -> Result<PixelBuffer, std::io::Error> {
+        let ihdr = self.require_ihdr()?;
+        // Checks the limits before allocating
+        let mut rows = self.rows()?;
+        let mut buffer = PixelBuffer::new(
+            ihdr.width,
+            ihdr.height,
+            ihdr.color_type.channels(),
+            ihdr.bit_depth,
+        );
+
+        while let Some(row) = rows.next_row()? {
+            if row.pass == 0 && ihdr.bit_depth >= 8 {
+                buffer.row_mut(row.y).copy_from_slice(row.data);
+                continue;
+            }
+            put_row(&mut buffer, &row, row.y, |i| row.image_x(i));
+        }
+
+        Ok(buffer)
+    }
+
+    /// Decodes a single Adam7 pass (1-7) as the reduced image it is stored as, with indexed
+    /// images expanded like `get_buffer`.
+    pub fn get_pass(&self, pass: usize) -> Result<PixelBuffer, std::io::Error> {
+        self.expand_buffer(self.get_raw_pass(pass)?)
+    }
+
+    pub fn get_raw_pass(&self, pass: usize) -> Result<PixelBuffer, std::io::Error> {
+        let ihdr = self.require_ihdr()?;
+        if !matches!(ihdr.interlace_method, InterlaceMethod::Adam7) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Image is not interlaced",
+            ));
+        }
+        if !(1..=7).contains(&pass) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Adam7 pass must be between 1 and 7, found {}", pass),
+            ));
+        }
+
+        let mut rows = self.rows()?;
+        let (width, height) = ihdr.passes()[pass - 1];
+        let mut buffer =
+            PixelBuffer::new(width, height, ihdr.color_type.channels(), ihdr.bit_depth);
+
+        let mut y = 0;
+        while y < height {
+            let Some(row) = rows.next_row()? else {
+                break;
+            };
+            if row.pass == pass {
+                put_row(&mut buffer, &row, y, |i| i);
+                y += 1;
+            }
+        }
+
+        Ok(buffer)
+    }
+
+    fn expand_buffer(&self, mut buffer: PixelBuffer) -> Result<PixelBuffer, std::io::Error> {
+        if matches!(self.require_ihdr()?.color_type, ColorType::Indexed) {
+            return Ok(buffer.expand_palette(&self.straight_palette()?));
+        }
+
+        if self.is_cgbi() && buffer.bit_depth == 8 && buffer.channels >= 3 {
+            let (width, height, channels) = (buffer.width, buffer.height, buffer.channels);
+            let mut data = buffer.into_data();
+            scanline::convert_cgbi_samples(&mut data, channels);
+            buffer = PixelBuffer::from_data(width, height, channels, 8, data);
+        }
+
+        Ok(buffer)
+    }
+
+    /// The palette, with the entries of CgBI images converted like their RGBA samples.
+    fn straight_palette(&self) -> Result<PLTE, std::io::Error> {
+        let mut plte = self.plte().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "PLTE chunk not found")
+        })?;
+        if !self.is_cgbi() {
+            return Ok(plte);
+        }
+
+        let alpha = match &plte.transparency {
+            Some(Transparency::Alpha(alpha)) => &alpha[..],
+            _ => &[],
         };
+        for (i, (r, g, b)) in plte.entries.iter_mut().enumerate() {
+            let mut pixel = [*r, *g, *b, alpha.get(i).copied().unwrap_or(255)];
+            scanline::convert_cgbi_samples(&mut pixel, 4);
+            (*r, *g, *b) = (pixel[0], pixel[1], pixel[2]);
+        }
 
-        let idat_data = self.decompress_idat_data()?;
+        Ok(plte)
+    }
 
-        let scanlines = scanline::parse_scanlines(&ihdr, self.plte().as_ref(), &idat_data);
+    /// The fcTL chunks of an APNG, one per frame.
+    pub fn frame_controls(&self) -> Vec<Fctl> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.chunk_type == "fcTL")
+            .filter_map(|chunk| match chunk.parse_with_limits(&self.limits) {
+                ParsedChunk::Fctl(fctl) => Some(fctl),
+                _ => None,
+            })
+            .collect()
+    }
 
-        Ok(scanlines)
+    /// Extracts APNG frame `index`, counting fcTL chunks from 0, as a standalone PNG of the
+    /// frame's size. The frame is returned as stored, not composited onto the previous frames.
+    pub fn frame(&self, index: usize) -> Result<Png, std::io::Error> {
+        let ihdr = self
+            .chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type == "IHDR")
+            .and_then(|chunk| chunk.data.as_ref())
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "IHDR chunk not found")
+            })?;
+
+        let mut fctl = None;
+        let mut current = None;
+        let mut data_chunks = vec![];
+        for chunk in &self.chunks {
+            let data = chunk.data.as_deref().unwrap_or_default();
+            match chunk.chunk_type.as_str() {
+                "fcTL" => {
+                    current = Some(current.map_or(0, |i| i + 1));
+                    if current == Some(index) {
+                        fctl = match chunk.parse_with_limits(&self.limits) {
+                            ParsedChunk::Fctl(fctl) => Some(fctl),
+                            _ => None,
+                        };
+                    }
+                }
+                "IDAT" if current == Some(index) => data_chunks.push(chunk.clone()),
+                // fdAT is IDAT preceded by a sequence number
+                "fdAT" if current == Some(index) && data.len() >= 4 => data_chunks.push(
+                    Chunk::from_data(ChunkType::from(*b"IDAT"), data[4..].to_vec()),
+                ),
+                _ => {}
+            }
+        }
+
+        let fctl = fctl.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("APNG frame {} not found", index),
+            )
+        })?;
+
+        let mut ihdr = ihdr.clone();
+        ihdr[..4].copy_from_slice(&fctl.width.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&fctl.height.to_be_bytes());
+
+        let mut chunks = vec![Chunk::from_data(ChunkType::from(*b"IHDR"), ihdr)];
+        chunks.extend(
+            self.chunks
+                .iter()
+                .filter(|chunk| chunk.chunk_type == "PLTE" || chunk.chunk_type == "tRNS")
+                .cloned(),
+        );
+        chunks.extend(data_chunks);
+
+        Ok(Png::new(chunks, None).with_limits(self.limits.clone()))
+    }
+
+    /// Returns a decoder that inflates the IDAT stream incrementally and unfilters one row at a
+    /// time, so images larger than memory can be processed.
+    pub fn rows(&self) -> Result<RowDecoder<Box<dyn Read + '_>>, std::io::Error> {
+        let ihdr = self.require_ihdr()?;
+
+        self.limits.check_dimensions(&ihdr)?;
+        let expected_size = ihdr.expected_data_size();
+        if expected_size > self.limits.max_decompressed_size as u64 {
+            return Err(limits::limit_error(format!(
+                "Image data size {} exceeds the limit of {} bytes",
+                expected_size, self.limits.max_decompressed_size
+            )));
+        }
+
+        let idat = IdatReader::new(&self.chunks);
+        let reader: Box<dyn Read> = if self.is_cgbi() {
+            Box::new(DeflateDecoder::new(idat))
+        } else {
+            Box::new(ZlibDecoder::new(idat))
+        };
+
+        Ok(RowDecoder::new(&ihdr, reader))
     }
 
-    fn decompress_idat_data(&self) -> Result<Vec<u8>, std::io::Error> {
+    fn require_ihdr(&self) -> Result<IHDR, std::io::Error> {
+        self.ihdr().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "IHDR chunk not found")
+        })
+    }
+
+    /// Converts an Apple CgBI file to a standard PNG: the CgBI chunk is dropped and the
+    /// image data is re-encoded non-interlaced and zlib-compressed, keeping the color type and
+    /// bit depth. Indexed images keep their indices and get a converted palette instead.
+    pub fn uncrush(&self) -> Result<Png, std::io::Error> {
+        let ihdr = self.require_ihdr()?;
+        let mut buffer = self.get_raw_buffer()?;
+        let plte = match ihdr.color_type {
+            ColorType::Indexed => Some(self.straight_palette()?),
+            _ => {
+                buffer = self.expand_buffer(buffer)?;
+                None
+            }
+        };
+
+        let mut chunks = vec![];
+        for chunk in &self.chunks {
+            match (chunk.chunk_type.as_str(), &plte) {
+                // iDOT points into the original IDAT layout, which no longer exists
+                ("CgBI" | "iDOT", _) => {}
+                ("PLTE", Some(plte)) => {
+                    let data = plte.entries.iter().flat_map(|&(r, g, b)| [r, g, b]);
+                    chunks.push(Chunk::from_data(chunk.chunk_type, data.collect()));
+                }
+                _ => chunks.push(chunk.clone()),
+            }
+        }
+
+        Png::new(chunks, self.extra_bytes.clone())
+            .with_limits(self.limits.clone())
+            .with_image_data(&buffer)
+    }
+
+    /// Replaces the image data with `buffer`, stored non-interlaced. The buffer must match the
+    /// color type and bit depth in IHDR.
+    pub fn with_image_data(&self, buffer: &PixelBuffer) -> Result<Png, std::io::Error> {
+        let mut idat = Some(Chunk::from_data(
+            ChunkType::from(*b"IDAT"),
+            compression::compress(&writer::encode_buffer(buffer))?,
+        ));
+
+        let mut chunks = vec![];
+        for chunk in &self.chunks {
+            match chunk.chunk_type.as_str() {
+                "IHDR" => {
+                    let mut data = chunk.data.clone().unwrap_or_default();
+                    if let Some(interlace_method) = data.get_mut(12) {
+                        *interlace_method = 0;
+                    }
+                    chunks.push(Chunk::from_data(chunk.chunk_type, data));
+                }
+                "IDAT" => chunks.extend(idat.take()),
+                _ => chunks.push(chunk.clone()),
+            }
+        }
+
+        Ok(Png::new(chunks, self.extra_bytes.clone()).with_limits(self.limits.clone()))
+    }
+
+    /// Decompresses the IDAT stream. The size computed from IHDR is a hard ceiling on top of the
+    /// configured limit, so a zip bomb cannot grow past what the image needs.
+    pub fn decompress_idat_data(&self) -> Result<Vec<u8>, std::io::Error> {
         let idat_data = self.get_idat_data();
-        compression::decompress(&idat_data)
+        let expected_size = self
+            .ihdr()
+            .map(|ihdr| ihdr.expected_data_size())
+            .filter(|&size| size <= self.limits.max_decompressed_size as u64);
+        let limit = expected_size.map_or(self.limits.max_decompressed_size, |size| size as usize);
+
+        let result = if self.is_cgbi() {
+            compression::decompress_raw(&idat_data, limit)
+        } else {
+            compression::decompress(&idat_data, limit)
+        };
+
+        result.map_err(|e| match expected_size {
+            Some(size) if limits::is_limit_error(&e) => limits::limit_error(format!(
+                "IDAT data decompresses to more than the {} bytes expected from IHDR",
+                size
+            )),
+            _ => e,
+        })
     }
 
-    fn get_idat_data(&self) -> Vec<u8> {
+    /// The data of all IDAT chunks, concatenated.
+    pub fn get_idat_data(&self) -> Vec<u8> {
         self.chunks
             .iter()
             .filter(|c| c.chunk_type == "IDAT")
             .fold(vec![], |mut acc, c| {
-                match &c.data {
-                    Some(data) => acc.extend(data),
-                    None => (),
+                if let Some(data) = &c.data {
+                    acc.extend(data);
                 }
                 acc
             })
     }
 }
 
+/// Unpacks a decoded row into row `y` of `buffer`, `x` mapping sample positions in the row to
+/// columns of the buffer.
+fn put_row(buffer: &mut PixelBuffer, row: &Row, y: u32, x: impl Fn(u32) -> u32) {
+    let channels = buffer.channels;
+    let samples = scanline::unpack_samples(
+        row.data,
+        buffer.bit_depth,
+        row.width as usize * channels as usize,
+    );
+    for (i, pixel) in samples.chunks_exact(channels as usize).enumerate() {
+        for (channel, &value) in pixel.iter().enumerate() {
+            buffer.set_sample(x(i as u32), y, channel as u8, value);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::{cgbi, chunk, gray_2x2, idat, ihdr};
 
     #[test]
     fn test_get_idat_data_single_idat_chunk() {
         let chunk = Chunk::new(
             4,
-            String::from("IDAT"),
+            ChunkType::from(*b"IDAT"),
             Some(vec![0x01, 0x02, 0x03, 0x04]),
             283159080,
         );
@@ -185,13 +606,13 @@ mod tests {
     fn test_get_idat_data_multiple_idat_chunks() {
         let chunk1 = Chunk::new(
             4,
-            String::from("IDAT"),
+            ChunkType::from(*b"IDAT"),
             Some(vec![0x01, 0x02, 0x03, 0x04]),
             283159080,
         );
         let chunk2 = Chunk::new(
             4,
-            String::from("IDAT"),
+            ChunkType::from(*b"IDAT"),
             Some(vec![0x05, 0x06, 0x07, 0x08]),
             283159080,
         );
@@ -207,14 +628,14 @@ mod tests {
     fn test_get_idat_data_empty() {
         let png = Png::new(vec![], None);
 
-        assert_eq!(png.get_idat_data(), vec![]);
+        assert_eq!(png.get_idat_data(), Vec::<u8>::new());
     }
 
     #[test]
     fn test_decompress_idat_data() {
         let chunk1 = Chunk::new(
             4,
-            String::from("IDAT"),
+            ChunkType::from(*b"IDAT"),
             Some(vec![
                 0x78, 0x5e, 0x2b, 0x49, 0x2d, 0x2e, 0x01, 0x00, 0x04, 0x5d, 0x01, 0xc1,
             ]),
@@ -227,11 +648,179 @@ mod tests {
         assert_eq!(decompressed_data, vec![0x74, 0x65, 0x73, 0x74]);
     }
 
+    #[test]
+    fn test_uncrush() {
+        // Raw deflate of a single BGRA premultiplied pixel with filter type 0
+        let idat = chunk(b"IDAT", &[0x63, 0x10, 0x08, 0x28, 0x68, 0x00, 0x00]);
+        let png = Png::new(
+            vec![cgbi(), ihdr(1, 1, 8, 6), idat, chunk(b"IEND", &[])],
+            None,
+        );
+
+        assert!(png.is_cgbi());
+
+        let converted = png.uncrush().unwrap();
+
+        assert!(!converted.is_cgbi());
+        assert_eq!(converted.chunks.len(), 3);
+        assert_eq!(
+            converted.decompress_idat_data().unwrap(),
+            vec![0x00, 0xdf, 0x9f, 0x20, 0x80]
+        );
+    }
+
+    /// Turns a PNG into a CgBI file with the same samples, which are left as they are for
+    /// color types other than 8-bit RGB and RGBA.
+    fn crush(png: &Png) -> Png {
+        let data = png.decompress_idat_data().unwrap();
+        let idat = chunk(b"IDAT", &miniz_oxide::deflate::compress_to_vec(&data, 6));
+        let mut chunks = vec![cgbi()];
+        let mut idat = Some(idat);
+        for chunk in &png.chunks {
+            match chunk.chunk_type.as_str() {
+                "IDAT" => chunks.extend(idat.take()),
+                _ => chunks.push(chunk.clone()),
+            }
+        }
+        Png::new(chunks, None)
+    }
+
+    #[test]
+    fn test_uncrush_keeps_color_type_and_bit_depth() {
+        let suite = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/pngsuite");
+        // Interlaced, sub-8-bit, indexed with and without tRNS, 16-bit and 8-bit RGB
+        for name in [
+            "basi0g02.png",
+            "basi3p04.png",
+            "tbbn3p08.png",
+            "basi6a16.png",
+            "basi2c08.png",
+            "basn6a08.png",
+        ] {
+            let data = std::fs::read(suite.join(name)).unwrap();
+            let png = crush(&crate::parse_bytes(&data).unwrap());
+
+            let converted = png.uncrush().unwrap();
+            let (ihdr, converted_ihdr) = (png.ihdr().unwrap(), converted.ihdr().unwrap());
+
+            assert!(!converted.is_cgbi(), "{}", name);
+            assert_eq!(converted_ihdr.bit_depth, ihdr.bit_depth, "{}", name);
+            assert_eq!(converted_ihdr.color_type, ihdr.color_type, "{}", name);
+            assert_eq!(
+                converted.get_buffer().unwrap(),
+                png.get_buffer().unwrap(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_buffer_indexed() {
+        let png = Png::new(
+            vec![
+                ihdr(2, 1, 1, 3),
+                chunk(b"PLTE", &[1, 2, 3, 4, 5, 6]),
+                idat(&[0, 0b0100_0000]),
+            ],
+            None,
+        );
+
+        assert_eq!(png.get_raw_buffer().unwrap().data(), &[0, 1]);
+        assert_eq!(
+            png.get_buffer().unwrap().data(),
+            &[1, 2, 3, 255, 4, 5, 6, 255]
+        );
+    }
+
+    #[test]
+    fn test_get_buffer_cgbi() {
+        let png = Png::new(
+            vec![
+                cgbi(),
+                ihdr(1, 1, 8, 6),
+                chunk(b"IDAT", &[0x63, 0x10, 0x08, 0x28, 0x68, 0x00, 0x00]),
+            ],
+            None,
+        );
+
+        assert_eq!(png.get_buffer().unwrap().data(), &[0xdf, 0x9f, 0x20, 0x80]);
+    }
+
+    #[test]
+    fn test_frame() {
+        let mut fctl = vec![0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1];
+        fctl.extend([0, 1, 0, 10, 0, 0]);
+        let mut fdat = vec![0, 0, 0, 2];
+        fdat.extend(compression::compress(&[0, 7]).unwrap());
+        let mut chunks = gray_2x2();
+        chunks.extend([chunk(b"fcTL", &fctl), chunk(b"fdAT", &fdat)]);
+        let png = Png::new(chunks, None);
+
+        assert_eq!(png.frame_controls().len(), 1);
+        assert_eq!(png.frame(0).unwrap().get_buffer().unwrap().data(), &[7]);
+        assert!(png.frame(1).is_err());
+    }
+
+    #[test]
+    fn test_decompress_idat_data_exceeds_ihdr_size() {
+        let png = Png::new(vec![ihdr(1, 1, 8, 0), idat(&[0; 3])], None);
+
+        assert_eq!(
+            png.decompress_idat_data().unwrap_err().to_string(),
+            "IDAT data decompresses to more than the 2 bytes expected from IHDR"
+        );
+    }
+
+    #[test]
+    fn test_get_scanlines_dimension_limit() {
+        let png = Png::new(vec![ihdr(0x1000, 0x1000, 8, 0)], None).with_limits(Limits {
+            max_pixels: 1000,
+            ..Limits::default()
+        });
+
+        assert_eq!(
+            png.get_scanlines().unwrap_err().to_string(),
+            "Image pixel count 16777216 exceeds the limit of 1000"
+        );
+    }
+
+    #[test]
+    fn test_parsed_chunks_share_text_limit() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(compression::compress(&[b'a'; 60]).unwrap());
+        let ztxt = chunk(b"zTXt", &data);
+        let png = Png::new(vec![ztxt.clone(), ztxt], None).with_limits(Limits {
+            max_text_size: 100,
+            ..Limits::default()
+        });
+
+        let parsed = png.parsed_chunks();
+
+        assert!(matches!(parsed[0], ParsedChunk::Ztxt(_)));
+        assert!(matches!(parsed[1], ParsedChunk::Invalid(_)));
+    }
+
+    #[test]
+    fn test_trns_wrong_length() {
+        let png = |color_type, trns: &[u8]| {
+            Png::new(vec![ihdr(1, 1, 8, color_type), chunk(b"tRNS", trns)], None)
+        };
+
+        let gray = png(0, &[0]);
+        let rgb = png(2, &[0; 4]);
+        let valid = png(2, &[0, 1, 0, 2, 0, 3]);
+
+        assert!(gray.trns().is_none());
+        assert!(rgb.trns().is_none());
+        assert!(matches!(valid.trns(), Some(Transparency::Rgb(1, 2, 3))));
+    }
+
     #[test]
     fn test_decompress_idat_data_invalid_stream() {
         let chunk1 = Chunk::new(
             4,
-            String::from("IDAT"),
+            ChunkType::from(*b"IDAT"),
             Some(vec![0x01, 0x02, 0x03, 0x04]),
             283159080,
         );
@@ -0,0 +1,312 @@
+use crate::png::{Transparency, PLTE};
+use image::{DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, RgbImage, RgbaImage};
+
+/// Decoded image samples stored contiguously, interleaved by channel and row-major.
+///
+/// Samples of bit depths up to 8 take one byte each and keep their original value range, so a
+/// 1-bit image holds 0s and 1s. 16-bit samples take two big-endian bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixelBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub bit_depth: u8,
+    data: Vec<u8>,
+}
+
+impl PixelBuffer {
+    pub fn new(width: u32, height: u32, channels: u8, bit_depth: u8) -> Self {
+        let bytes_per_sample = if bit_depth == 16 { 2 } else { 1 };
+        Self {
+            width,
+            height,
+            channels,
+            bit_depth,
+            data: vec![0; width as usize * height as usize * channels as usize * bytes_per_sample],
+        }
+    }
+
+    pub fn from_data(width: u32, height: u32, channels: u8, bit_depth: u8, data: Vec<u8>) -> Self {
+        let buffer = Self {
+            width,
+            height,
+            channels,
+            bit_depth,
+            data,
+        };
+        assert_eq!(
+            buffer.data.len(),
+            buffer.row_len() * height as usize,
+            "Buffer size does not match its dimensions"
+        );
+        buffer
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        if self.bit_depth == 16 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Number of bytes in a row.
+    pub fn row_len(&self) -> usize {
+        self.width as usize * self.channels as usize * self.bytes_per_sample()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.row_len();
+        &self.data[start..start + self.row_len()]
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> &mut [u8] {
+        let start = y as usize * self.row_len();
+        let len = self.row_len();
+        &mut self.data[start..start + len]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks_exact(self.row_len().max(1))
+    }
+
+    /// The bytes of the pixel at (x, y), all channels.
+    pub fn pixel(&self, x: u32, y: u32) -> &[u8] {
+        let pixel_len = self.channels as usize * self.bytes_per_sample();
+        let start = y as usize * self.row_len() + x as usize * pixel_len;
+        &self.data[start..start + pixel_len]
+    }
+
+    pub fn sample(&self, x: u32, y: u32, channel: u8) -> u16 {
+        let index = (y as usize * self.width as usize + x as usize) * self.channels as usize
+            + channel as usize;
+        self.sample_at(index)
+    }
+
+    pub fn set_sample(&mut self, x: u32, y: u32, channel: u8, value: u16) {
+        let index = (y as usize * self.width as usize + x as usize) * self.channels as usize
+            + channel as usize;
+        match self.bit_depth {
+            16 => self.data[index * 2..index * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+            _ => self.data[index] = value as u8,
+        }
+    }
+
+    fn sample_at(&self, index: usize) -> u16 {
+        match self.bit_depth {
+            16 => u16::from_be_bytes([self.data[index * 2], self.data[index * 2 + 1]]),
+            _ => self.data[index] as u16,
+        }
+    }
+
+    /// A view of a single channel, without copying the samples.
+    pub fn channel(&self, channel: u8) -> ChannelView<'_> {
+        assert!(channel < self.channels, "Channel {} out of range", channel);
+        ChannelView {
+            buffer: self,
+            channel,
+        }
+    }
+
+    /// Copies a single channel into a new one-channel buffer.
+    pub fn extract_channel(&self, channel: u8) -> PixelBuffer {
+        let mut buffer = PixelBuffer::new(self.width, self.height, 1, self.bit_depth);
+        for (i, value) in self.channel(channel).iter().enumerate() {
+            match self.bit_depth {
+                16 => buffer.data[i * 2..i * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+                _ => buffer.data[i] = value as u8,
+            }
+        }
+        buffer
+    }
+
+    /// Keeps bit `plane` of every sample, giving a 1-bit buffer with the same channels.
+    pub fn bit_plane(&self, plane: u8) -> PixelBuffer {
+        let data = (0..self.data.len() / self.bytes_per_sample())
+            .map(|i| ((self.sample_at(i) >> plane) & 1) as u8)
+            .collect();
+
+        PixelBuffer::from_data(self.width, self.height, self.channels, 1, data)
+    }
+
+    /// Replaces palette indices with their RGBA color, using the palette's tRNS alpha values.
+    pub fn expand_palette(&self, plte: &PLTE) -> PixelBuffer {
+        let alpha = match &plte.transparency {
+            Some(Transparency::Alpha(alpha)) => &alpha[..],
+            _ => &[],
+        };
+
+        let data = self
+            .data
+            .iter()
+            .flat_map(|&index| {
+                let (r, g, b) = plte
+                    .entries
+                    .get(index as usize)
+                    .copied()
+                    .unwrap_or((0, 0, 0));
+                let a = alpha.get(index as usize).copied().unwrap_or(255);
+                [r, g, b, a]
+            })
+            .collect();
+
+        PixelBuffer::from_data(self.width, self.height, 4, 8, data)
+    }
+
+    /// Converts to 8 bits per sample: 16-bit samples keep their high byte and lower bit depths
+    /// are stretched to the full range.
+    pub fn to_8bit(&self) -> PixelBuffer {
+        let data = match self.bit_depth {
+            8 => self.data.clone(),
+            16 => self.data.iter().step_by(2).copied().collect(),
+            bit_depth => {
+                let max = (1u16 << bit_depth) - 1;
+                self.data
+                    .iter()
+                    .map(|&v| (v as u16 * 255 / max) as u8)
+                    .collect()
+            }
+        };
+
+        PixelBuffer::from_data(self.width, self.height, self.channels, 8, data)
+    }
+
+    pub fn to_dynamic_image(&self) -> DynamicImage {
+        if self.bit_depth == 16 {
+            let samples: Vec<u16> = self
+                .data
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect();
+            let (width, height) = (self.width, self.height);
+            return match self.channels {
+                1 => DynamicImage::ImageLuma16(
+                    ImageBuffer::from_raw(width, height, samples).unwrap(),
+                ),
+                2 => DynamicImage::ImageLumaA16(
+                    ImageBuffer::from_raw(width, height, samples).unwrap(),
+                ),
+                3 => {
+                    DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, samples).unwrap())
+                }
+                _ => DynamicImage::ImageRgba16(
+                    ImageBuffer::from_raw(width, height, samples).unwrap(),
+                ),
+            };
+        }
+
+        let buffer = self.to_8bit();
+        let (width, height) = (buffer.width, buffer.height);
+        match buffer.channels {
+            1 => DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, buffer.data).unwrap()),
+            2 => DynamicImage::ImageLumaA8(
+                GrayAlphaImage::from_raw(width, height, buffer.data).unwrap(),
+            ),
+            3 => DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, buffer.data).unwrap()),
+            _ => DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, buffer.data).unwrap()),
+        }
+    }
+
+    pub fn to_rgba8(&self) -> RgbaImage {
+        self.to_dynamic_image().to_rgba8()
+    }
+}
+
+/// A strided view of one channel of a `PixelBuffer`.
+pub struct ChannelView<'a> {
+    buffer: &'a PixelBuffer,
+    channel: u8,
+}
+
+impl ChannelView<'_> {
+    pub fn get(&self, x: u32, y: u32) -> u16 {
+        self.buffer.sample(x, y, self.channel)
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.width as usize * self.buffer.height as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Samples in row-major order.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let channels = self.buffer.channels as usize;
+        (0..self.len()).map(move |i| self.buffer.sample_at(i * channels + self.channel as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_views() {
+        let buffer = PixelBuffer::from_data(2, 2, 3, 8, (0..12).collect());
+
+        assert_eq!(buffer.row(1), &[6, 7, 8, 9, 10, 11]);
+        assert_eq!(buffer.pixel(1, 0), &[3, 4, 5]);
+        assert_eq!(buffer.sample(0, 1, 2), 8);
+        assert_eq!(
+            buffer.channel(1).iter().collect::<Vec<_>>(),
+            vec![1, 4, 7, 10]
+        );
+    }
+
+    #[test]
+    fn test_16bit_samples() {
+        let mut buffer = PixelBuffer::new(1, 1, 2, 16);
+        buffer.set_sample(0, 0, 1, 0x1234);
+
+        assert_eq!(buffer.data(), &[0, 0, 0x12, 0x34]);
+        assert_eq!(buffer.channel(1).get(0, 0), 0x1234);
+        assert_eq!(buffer.to_8bit().data(), &[0, 0x12]);
+    }
+
+    #[test]
+    fn test_to_8bit_low_bit_depth() {
+        let buffer = PixelBuffer::from_data(4, 1, 1, 2, vec![0, 1, 2, 3]);
+
+        assert_eq!(buffer.to_8bit().data(), &[0, 85, 170, 255]);
+    }
+
+    #[test]
+    fn test_extract_channel_and_bit_plane() {
+        let buffer = PixelBuffer::from_data(2, 1, 2, 8, vec![1, 2, 3, 4]);
+
+        assert_eq!(buffer.extract_channel(1).data(), &[2, 4]);
+        assert_eq!(buffer.bit_plane(1).data(), &[0, 1, 1, 0]);
+        assert_eq!(buffer.bit_plane(1).to_8bit().data(), &[0, 255, 255, 0]);
+    }
+
+    #[test]
+    fn test_expand_palette() {
+        let buffer = PixelBuffer::from_data(2, 1, 1, 1, vec![1, 0]);
+        let plte = PLTE {
+            entries: vec![(1, 2, 3), (4, 5, 6)],
+            transparency: Some(Transparency::Alpha(vec![128])),
+        };
+
+        assert_eq!(
+            buffer.expand_palette(&plte).data(),
+            &[4, 5, 6, 255, 1, 2, 3, 128]
+        );
+    }
+
+    #[test]
+    fn test_to_dynamic_image() {
+        let buffer = PixelBuffer::from_data(1, 1, 4, 8, vec![1, 2, 3, 4]);
+
+        assert_eq!(buffer.to_rgba8().get_pixel(0, 0).0, [1, 2, 3, 4]);
+    }
+}
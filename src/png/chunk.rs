@@ -1,5 +1,8 @@
+use crate::png::chunk_type::ChunkType;
 use crate::png::compression::decompress;
+use crate::png::limits::Limits;
 use std::fmt::Display;
+use std::ops::Range;
 
 pub enum ParsedChunk {
     IHDR(IHDR),
@@ -17,7 +20,11 @@ pub enum ParsedChunk {
     Ztxt(Ztxt),
     Hist(Hist),
     Chrm(Chrm),
-    Unknown(String, Option<Vec<u8>>),
+    Iccp(Iccp),
+    Actl(Actl),
+    Fctl(Fctl),
+    Invalid(String),
+    Unknown(ChunkType, Option<Vec<u8>>),
 }
 
 impl Display for ParsedChunk {
@@ -41,9 +48,18 @@ impl Display for ParsedChunk {
             ParsedChunk::Ztxt(ztxt) => format!("{:?}", ztxt),
             ParsedChunk::Hist(hist) => format!("{:?}", hist),
             ParsedChunk::Chrm(chrm) => format!("{:?}", chrm),
+            ParsedChunk::Iccp(iccp) => format!(
+                "Iccp {{ profile_name: {:?}, compression_method: {}, profile: {} bytes }}",
+                iccp.profile_name,
+                iccp.compression_method,
+                iccp.profile.len()
+            ),
+            ParsedChunk::Actl(actl) => format!("{:?}", actl),
+            ParsedChunk::Fctl(fctl) => format!("{:?}", fctl),
+            ParsedChunk::Invalid(message) => format!("Invalid chunk: {}", message),
             ParsedChunk::Unknown(chunk_type, data) => match data {
                 Some(data) => format!("{}: {:?}", chunk_type, data),
-                None => chunk_type.clone(),
+                None => chunk_type.to_string(),
             },
         };
 
@@ -84,9 +100,55 @@ impl IHDR {
             ColorType::TruecolorAlpha => 4,
         }
     }
+
+    pub fn bits_per_pixel(&self) -> u64 {
+        self.color_type.channels() as u64 * self.bit_depth as u64
+    }
+
+    /// Bytes in a row of `width` pixels, excluding the filter type byte.
+    pub fn row_bytes(&self, width: u32) -> u64 {
+        (width as u64 * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Dimensions of the sub-images stored in the data stream, a single one for non-interlaced
+    /// images and the seven Adam7 passes otherwise. Passes can be empty for small images.
+    pub fn passes(&self) -> Vec<(u32, u32)> {
+        match self.interlace_method {
+            InterlaceMethod::None => vec![(self.width, self.height)],
+            InterlaceMethod::Adam7 => ADAM7
+                .iter()
+                .map(|&(x, y, dx, dy)| {
+                    (
+                        (self.width + dx - 1 - x.min(self.width)) / dx,
+                        (self.height + dy - 1 - y.min(self.height)) / dy,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The exact size of the decompressed image data, including filter type bytes.
+    pub fn expected_data_size(&self) -> u64 {
+        self.passes()
+            .iter()
+            .filter(|(width, height)| *width > 0 && *height > 0)
+            .map(|&(width, height)| height as u64 * (1 + self.row_bytes(width)))
+            .sum()
+    }
 }
 
-#[derive(Debug)]
+/// Adam7 passes as (x start, y start, x step, y step).
+pub const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[derive(Debug, PartialEq)]
 pub enum ColorType {
     Grayscale,
     Truecolor,
@@ -96,6 +158,15 @@ pub enum ColorType {
 }
 
 impl ColorType {
+    pub fn channels(&self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Truecolor => 3,
+            ColorType::TruecolorAlpha => 4,
+        }
+    }
+
     pub fn has_alpha(&self) -> bool {
         matches!(self, ColorType::GrayscaleAlpha | ColorType::TruecolorAlpha)
     }
@@ -224,12 +295,15 @@ pub struct Itxt {
     pub language_tag: String,
     pub translated_keyword: String,
     pub text: String,
+    /// Problems that did not stop the chunk from being read
+    pub warnings: Vec<String>,
 }
 
 #[derive(Debug)]
 pub struct Text {
     pub keyword: String,
     pub text: String,
+    pub warnings: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -237,10 +311,18 @@ pub struct Ztxt {
     pub keyword: String,
     pub compression_method: u8,
     pub text: String,
+    pub warnings: Vec<String>,
 }
 
 pub type Hist = Vec<u16>;
 
+#[derive(Debug)]
+pub struct Iccp {
+    pub profile_name: String,
+    pub compression_method: u8,
+    pub profile: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct Chrm {
     pub white_point_x: u32,
@@ -253,11 +335,48 @@ pub struct Chrm {
     pub blue_y: u32,
 }
 
+/// APNG animation control.
+#[derive(Debug)]
+pub struct Actl {
+    pub num_frames: u32,
+    pub num_plays: u32,
+}
+
+/// APNG frame control: the region a frame covers and how it is composited.
+#[derive(Debug, Clone)]
+pub struct Fctl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: u8,
+    pub blend_op: u8,
+}
+
+/// A named field inside a chunk's data, with its byte range relative to the start of the data.
+#[derive(Debug, PartialEq)]
+pub struct ChunkField {
+    pub name: &'static str,
+    pub range: Range<usize>,
+}
+
+enum FieldLayout {
+    Fixed(&'static str, usize),
+    Terminated(&'static str),
+    Rest(&'static str),
+}
+
+#[derive(Debug, Clone)]
 pub struct Chunk {
     pub length: u32,
-    pub chunk_type: String,
+    pub chunk_type: ChunkType,
     pub data: Option<Vec<u8>>,
     pub crc: u32,
+    /// Absolute file offset of the chunk's length field, if it was read from a file
+    pub offset: Option<u64>,
 }
 
 impl Display for Chunk {
@@ -265,35 +384,217 @@ impl Display for Chunk {
         &self,
         formatter: &mut std::fmt::Formatter<'_>,
     ) -> std::result::Result<(), std::fmt::Error> {
-        formatter.write_str(
+        formatter.write_str(&self.describe(&self.parse()))
+    }
+}
+
+impl Chunk {
+    /// The chunk's offsets, header fields and `parsed` fields, as shown by `Display`, for
+    /// chunks parsed with other limits than the default ones.
+    pub fn describe(&self, parsed: &ParsedChunk) -> String {
+        let mut description = String::new();
+        if let Some(offset) = self.offset {
+            description.push_str(
+                format!(
+                    "Offset: {:#x} (length {:#x}, type {:#x}, data {:#x}, crc {:#x})\n",
+                    offset,
+                    offset,
+                    offset + 4,
+                    offset + 8,
+                    self.crc_offset().unwrap()
+                )
+                .as_str(),
+            );
+        }
+        description.push_str(
             format!(
-                "Length: {}\nCRC: {}, Valid: {}\nParsed:\n{}",
+                "Type: {} ({})\nLength: {}\nCRC: {}, Valid: {}\nParsed:\n{}",
+                self.chunk_type,
+                self.chunk_type.properties(),
                 self.length,
                 self.crc,
                 self.validate_checksum(),
-                self.parse()
+                parsed
             )
             .as_str(),
-        )
+        );
+        description
     }
-}
 
-impl Chunk {
-    pub fn new(length: u32, chunk_type: String, data: Option<Vec<u8>>, crc: u32) -> Self {
+    pub fn new(length: u32, chunk_type: ChunkType, data: Option<Vec<u8>>, crc: u32) -> Self {
         Self {
             length,
             chunk_type,
             data,
             crc,
+            offset: None,
         }
     }
 
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn length_offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    pub fn type_offset(&self) -> Option<u64> {
+        self.offset.map(|offset| offset + 4)
+    }
+
+    pub fn data_offset(&self) -> Option<u64> {
+        self.offset.map(|offset| offset + 8)
+    }
+
+    pub fn crc_offset(&self) -> Option<u64> {
+        self.offset
+            .map(|offset| offset + 8 + self.data_len() as u64)
+    }
+
+    /// Offset of the first byte after the chunk's CRC.
+    pub fn end_offset(&self) -> Option<u64> {
+        self.crc_offset().map(|offset| offset + 4)
+    }
+
+    pub fn data_len(&self) -> usize {
+        self.data.as_ref().map_or(0, |data| data.len())
+    }
+
+    /// Creates a chunk from its payload, computing the length and CRC.
+    pub fn from_data(chunk_type: ChunkType, data: Vec<u8>) -> Self {
+        let data = if data.is_empty() { None } else { Some(data) };
+        let mut chunk = Self::new(
+            data.as_ref().map_or(0, |d| d.len() as u32),
+            chunk_type,
+            data,
+            0,
+        );
+        chunk.crc = chunk.calculate_checksum();
+        chunk
+    }
+
+    /// Splits the chunk data into its fields for chunk types with a known layout. Fields that
+    /// would run past the end of the data are truncated.
+    pub fn data_fields(&self) -> Vec<ChunkField> {
+        use FieldLayout::*;
+
+        let layout: &[FieldLayout] = match self.chunk_type.as_str() {
+            "IHDR" => &[
+                Fixed("width", 4),
+                Fixed("height", 4),
+                Fixed("bit depth", 1),
+                Fixed("color type", 1),
+                Fixed("compression method", 1),
+                Fixed("filter method", 1),
+                Fixed("interlace method", 1),
+            ],
+            "pHYs" => &[
+                Fixed("pixels per unit x", 4),
+                Fixed("pixels per unit y", 4),
+                Fixed("unit specifier", 1),
+            ],
+            "gAMA" => &[Fixed("gamma", 4)],
+            "sRGB" => &[Fixed("rendering intent", 1)],
+            "cHRM" => &[
+                Fixed("white point x", 4),
+                Fixed("white point y", 4),
+                Fixed("red x", 4),
+                Fixed("red y", 4),
+                Fixed("green x", 4),
+                Fixed("green y", 4),
+                Fixed("blue x", 4),
+                Fixed("blue y", 4),
+            ],
+            "tIME" => &[
+                Fixed("year", 2),
+                Fixed("month", 1),
+                Fixed("day", 1),
+                Fixed("hour", 1),
+                Fixed("minute", 1),
+                Fixed("second", 1),
+            ],
+            "acTL" => &[Fixed("num frames", 4), Fixed("num plays", 4)],
+            "fcTL" => &[
+                Fixed("sequence number", 4),
+                Fixed("width", 4),
+                Fixed("height", 4),
+                Fixed("x offset", 4),
+                Fixed("y offset", 4),
+                Fixed("delay num", 2),
+                Fixed("delay den", 2),
+                Fixed("dispose op", 1),
+                Fixed("blend op", 1),
+            ],
+            "tEXt" => &[Terminated("keyword"), Rest("text")],
+            "zTXt" => &[
+                Terminated("keyword"),
+                Fixed("compression method", 1),
+                Rest("compressed text"),
+            ],
+            "iCCP" => &[
+                Terminated("profile name"),
+                Fixed("compression method", 1),
+                Rest("compressed profile"),
+            ],
+            "iTXt" => &[
+                Terminated("keyword"),
+                Fixed("compression flag", 1),
+                Fixed("compression method", 1),
+                Terminated("language tag"),
+                Terminated("translated keyword"),
+                Rest("text"),
+            ],
+            _ => &[],
+        };
+
+        let data: &[u8] = self.data.as_deref().unwrap_or(&[]);
+        let mut fields = vec![];
+        let mut start = 0;
+
+        for field in layout {
+            let start_clamped = start.min(data.len());
+            match *field {
+                Fixed(name, size) => {
+                    fields.push(ChunkField {
+                        name,
+                        range: start_clamped..(start + size).min(data.len()),
+                    });
+                    start += size;
+                }
+                Terminated(name) => {
+                    let end = data[start_clamped..]
+                        .iter()
+                        .position(|&b| b == 0)
+                        .map_or(data.len(), |p| start_clamped + p);
+                    fields.push(ChunkField {
+                        name,
+                        range: start_clamped..end,
+                    });
+                    fields.push(ChunkField {
+                        name: "null separator",
+                        range: end..(end + 1).min(data.len()),
+                    });
+                    start = end + 1;
+                }
+                Rest(name) => fields.push(ChunkField {
+                    name,
+                    range: start_clamped..data.len(),
+                }),
+            }
+        }
+
+        fields.retain(|field| !field.range.is_empty());
+        fields
+    }
+
     pub fn calculate_checksum(&self) -> u32 {
         let crc32 = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 
         match &self.data {
-            Some(data) => crc32.checksum(&[self.chunk_type.as_bytes(), &data[..]].concat()),
-            None => crc32.checksum(self.chunk_type.as_bytes()),
+            Some(data) => crc32.checksum(&[&self.chunk_type.bytes()[..], &data[..]].concat()),
+            None => crc32.checksum(&self.chunk_type.bytes()),
         }
     }
 
@@ -302,196 +603,339 @@ impl Chunk {
     }
 
     pub fn parse(&self) -> ParsedChunk {
+        self.parse_with_limits(&Limits::default())
+    }
+
+    pub fn parse_with_limits(&self, limits: &Limits) -> ParsedChunk {
         match self.chunk_type.as_str() {
-            "IHDR" => ParsedChunk::IHDR(self.parse_ihdr()),
+            "IHDR" => self
+                .parse_ihdr()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::IHDR),
             "PLTE" => ParsedChunk::PLTE(self.parse_plte()),
             "IDAT" => ParsedChunk::IDAT,
             "IEND" => ParsedChunk::IEND,
-            "tRNS" => ParsedChunk::Trns(self.data.as_ref().unwrap().to_vec()),
-            "pHYs" => ParsedChunk::Phys(self.parse_phys()),
-            "sRGB" => ParsedChunk::Srgb(self.parse_srgb()),
-            "gAMA" => ParsedChunk::Gama(self.parse_gama()),
-            "bKGD" => ParsedChunk::Bkgd(self.parse_bkgd()),
-            "sBIT" => ParsedChunk::Sbit(self.parse_sbit()),
-            "iTXt" => ParsedChunk::Itxt(self.parse_itxt()),
+            "tRNS" => ParsedChunk::Trns(self.data.clone().unwrap_or_default()),
+            "pHYs" => self
+                .parse_phys()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Phys),
+            "sRGB" => self
+                .parse_srgb()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Srgb),
+            "gAMA" => self
+                .parse_gama()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Gama),
+            "bKGD" => self
+                .parse_bkgd()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Bkgd),
+            "sBIT" => self
+                .parse_sbit()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Sbit),
+            "iTXt" => self
+                .parse_itxt(limits)
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Itxt),
             "tEXt" => ParsedChunk::Text(self.parse_text()),
-            "zTXt" => ParsedChunk::Ztxt(self.parse_ztxt()),
+            "zTXt" => self
+                .parse_ztxt(limits)
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Ztxt),
+            "iCCP" => self
+                .parse_iccp(limits)
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Iccp),
             "hIST" => ParsedChunk::Hist(self.parse_hist()),
-            "cHRM" => ParsedChunk::Chrm(self.parse_chrm()),
-            _ => ParsedChunk::Unknown(self.chunk_type.clone(), self.data.clone()),
+            "cHRM" => self
+                .parse_chrm()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Chrm),
+            "acTL" => self
+                .parse_actl()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Actl),
+            "fcTL" => self
+                .parse_fctl()
+                .map_or_else(ParsedChunk::Invalid, ParsedChunk::Fctl),
+            _ => ParsedChunk::Unknown(self.chunk_type, self.data.clone()),
+        }
+    }
+
+    /// The chunk data, which must be exactly `length` bytes long.
+    fn fixed_data(&self, length: usize) -> Result<&[u8], String> {
+        let data = self.data.as_deref().unwrap_or_default();
+        if data.len() != length {
+            return Err(format!(
+                "{} chunk must be {} bytes, found {}",
+                self.chunk_type,
+                length,
+                data.len()
+            ));
         }
+        Ok(data)
     }
 
-    fn parse_ihdr(&self) -> IHDR {
-        let data = self.data.as_ref().unwrap();
-        IHDR {
-            width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
-            height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+    fn parse_ihdr(&self) -> Result<IHDR, String> {
+        let data = self.fixed_data(13)?;
+        let bit_depths: &[u8] = match data[9] {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            color_type => return Err(format!("Invalid color type {}", color_type)),
+        };
+        if !bit_depths.contains(&data[8]) {
+            return Err(format!(
+                "Invalid bit depth {} for color type {}",
+                data[8], data[9]
+            ));
+        }
+        if data[10] != 0 {
+            return Err(format!("Invalid compression method {}", data[10]));
+        }
+        if data[11] != 0 {
+            return Err(format!("Invalid filter method {}", data[11]));
+        }
+        if data[12] > 1 {
+            return Err(format!("Invalid interlace method {}", data[12]));
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        if width == 0 || height == 0 {
+            return Err(format!("Invalid image size {}x{}", width, height));
+        }
+
+        Ok(IHDR {
+            width,
+            height,
             bit_depth: data[8],
             color_type: ColorType::from(data[9]),
             compression_method: CompressionMethod::from(data[10]),
             filter_method: FilterMethod::from(data[11]),
             interlace_method: InterlaceMethod::from(data[12]),
-        }
+        })
     }
 
     fn parse_plte(&self) -> Vec<(u8, u8, u8)> {
-        let data = self.data.as_ref().unwrap();
+        let data = self.data.as_deref().unwrap_or_default();
 
         data.chunks_exact(3)
             .map(|chunk| (chunk[0], chunk[1], chunk[2]))
             .collect()
     }
 
-    fn parse_phys(&self) -> Phys {
-        let data = self.data.as_ref().unwrap();
-        Phys {
+    fn parse_phys(&self) -> Result<Phys, String> {
+        let data = self.fixed_data(9)?;
+        if data[8] > 1 {
+            return Err(format!("Invalid unit specifier {}", data[8]));
+        }
+
+        Ok(Phys {
             pixels_per_unit_x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
             pixels_per_unit_y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
             unit_specifier: UnitSpecifier::from(data[8]),
-        }
+        })
     }
 
-    fn parse_srgb(&self) -> SrgbRenderingIntent {
-        let data = self.data.as_ref().unwrap();
-        SrgbRenderingIntent::from(data[0])
+    fn parse_srgb(&self) -> Result<SrgbRenderingIntent, String> {
+        let data = self.fixed_data(1)?;
+        if data[0] > 3 {
+            return Err(format!("Invalid rendering intent {}", data[0]));
+        }
+
+        Ok(SrgbRenderingIntent::from(data[0]))
     }
 
-    fn parse_gama(&self) -> Gama {
-        let data = self.data.as_ref().unwrap();
-        u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+    fn parse_gama(&self) -> Result<Gama, String> {
+        let data = self.fixed_data(4)?;
+        Ok(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
     }
 
-    fn parse_bkgd(&self) -> Bkgd {
-        let data = self.data.as_ref().unwrap();
+    fn parse_bkgd(&self) -> Result<Bkgd, String> {
+        let data = self.data.as_deref().unwrap_or_default();
 
         match data.len() {
-            1 => Bkgd::Indexed(data[0]),
-            2 => Bkgd::Grayscale(u16::from_be_bytes([data[0], data[1]])),
-            6 => Bkgd::Rgb(
+            1 => Ok(Bkgd::Indexed(data[0])),
+            2 => Ok(Bkgd::Grayscale(u16::from_be_bytes([data[0], data[1]]))),
+            6 => Ok(Bkgd::Rgb(
                 u16::from_be_bytes([data[0], data[1]]),
                 u16::from_be_bytes([data[2], data[3]]),
                 u16::from_be_bytes([data[4], data[5]]),
-            ),
-            _ => panic!("Invalid bKGD chunk"),
+            )),
+            length => Err(format!(
+                "bKGD chunk must be 1, 2 or 6 bytes, found {}",
+                length
+            )),
         }
     }
 
-    fn parse_sbit(&self) -> Sbit {
-        let data = self.data.as_ref().unwrap();
+    fn parse_sbit(&self) -> Result<Sbit, String> {
+        let data = self.data.as_deref().unwrap_or_default();
 
         match data.len() {
-            1 => Sbit::Grayscale(data[0]),
-            3 => Sbit::Truecolor(data[0], data[1], data[2]),
-            2 => Sbit::GrayscaleAlpha(data[0], data[1]),
-            4 => Sbit::TruecolorAlpha(data[0], data[1], data[2], data[3]),
-            _ => panic!("Invalid sBIT chunk"),
+            1 => Ok(Sbit::Grayscale(data[0])),
+            3 => Ok(Sbit::Truecolor(data[0], data[1], data[2])),
+            2 => Ok(Sbit::GrayscaleAlpha(data[0], data[1])),
+            4 => Ok(Sbit::TruecolorAlpha(data[0], data[1], data[2], data[3])),
+            length => Err(format!("sBIT chunk must be 1 to 4 bytes, found {}", length)),
         }
     }
 
-    fn parse_itxt(&self) -> Itxt {
-        let data = self.data.as_ref().unwrap();
-
-        let keyword_end = data.iter().position(|&x| x == 0).unwrap();
-        let keyword = String::from_utf8(data[..keyword_end].to_vec()).unwrap();
-
-        let compression_flag = data[keyword_end + 1];
-        let compression_method = data[keyword_end + 2];
-
-        let language_tag_start = keyword_end + 3;
-        let language_tag_end = language_tag_start
-            + data[language_tag_start..]
-                .iter()
-                .position(|&x| x == 0)
-                .unwrap();
-
-        let language_tag =
-            String::from_utf8(data[language_tag_start..language_tag_end].to_vec()).unwrap();
-
-        let translated_keyword_start = language_tag_end + 1;
-        let translated_keyword_end = translated_keyword_start
-            + data[translated_keyword_start..]
-                .iter()
-                .position(|&x| x == 0)
-                .unwrap();
-
-        let translated_keyword =
-            String::from_utf8(data[translated_keyword_start..translated_keyword_end].to_vec())
-                .unwrap();
+    fn parse_itxt(&self, limits: &Limits) -> Result<Itxt, String> {
+        let data = self.data.as_deref().unwrap_or_default();
+        let mut warnings = vec![];
 
-        let text_start = translated_keyword_end + 1;
-        let text = data[text_start..].to_vec();
+        let (keyword, rest) = split_null(data, "keyword", &mut warnings);
+        warnings.extend(keyword_warnings(keyword));
 
-        if compression_flag == 0 {
-            Itxt {
-                keyword,
-                compression_flag,
-                compression_method,
-                language_tag,
-                translated_keyword,
-                text: String::from_utf8(text).unwrap(),
-            }
-        } else {
-            let result = decompress(&text);
-
-            match result {
-                Ok(_) => Itxt {
-                    keyword,
-                    compression_flag,
-                    compression_method,
-                    language_tag,
-                    translated_keyword,
-                    text: String::from_utf8(result.unwrap()).unwrap(),
-                },
-                Err(e) => panic!("Error decompressing iTXt chunk: {}", e),
+        let (compression_flag, compression_method, rest) = match rest {
+            [flag, method, rest @ ..] => (*flag, *method, rest),
+            _ => {
+                warnings.push(String::from("Missing compression flag and method"));
+                (0, 0, &[][..])
             }
+        };
+        if compression_flag > 1 {
+            warnings.push(format!(
+                "Invalid compression flag {}, treating the text as uncompressed",
+                compression_flag
+            ));
+        }
+        if compression_flag == 1 && compression_method != 0 {
+            warnings.push(format!("Unknown compression method {}", compression_method));
         }
-    }
 
-    fn parse_text(&self) -> Text {
-        let data = self.data.as_ref().unwrap();
+        let (language_tag, rest) = split_null(rest, "language tag", &mut warnings);
+        let language_tag = latin1(language_tag);
+        if !is_valid_language_tag(&language_tag) {
+            warnings.push(format!("Invalid language tag {:?}", language_tag));
+        }
 
-        let keyword_end = data.iter().position(|&x| x == 0).unwrap();
-        let keyword = String::from_utf8(data[..keyword_end].to_vec()).unwrap();
+        let (translated_keyword, text) = split_null(rest, "translated keyword", &mut warnings);
+        let translated_keyword = utf8(translated_keyword, "Translated keyword", &mut warnings);
 
-        let text = String::from_utf8(data[keyword_end + 1..].to_vec()).unwrap();
+        let text = if compression_flag == 1 {
+            decompress(text, limits.max_text_size)
+                .map_err(|e| format!("Error decompressing iTXt chunk: {}", e))?
+        } else {
+            text.to_vec()
+        };
 
-        Text { keyword, text }
+        Ok(Itxt {
+            keyword: latin1(keyword),
+            compression_flag,
+            compression_method,
+            language_tag,
+            translated_keyword,
+            text: utf8(&text, "Text", &mut warnings),
+            warnings,
+        })
     }
 
-    fn parse_ztxt(&self) -> Ztxt {
-        let data = self.data.as_ref().unwrap();
+    fn parse_text(&self) -> Text {
+        let data = self.data.as_deref().unwrap_or_default();
+        let mut warnings = vec![];
+
+        let (keyword, text) = split_null(data, "keyword", &mut warnings);
+        warnings.extend(keyword_warnings(keyword));
+        if text.iter().any(|&b| b < 0x20 && b != b'\n') {
+            warnings.push(String::from(
+                "Text contains control characters other than line feeds",
+            ));
+        }
 
-        let keyword_end = data.iter().position(|&x| x == 0).unwrap();
-        let keyword = String::from_utf8(data[..keyword_end].to_vec()).unwrap();
-        let compression_method = data[keyword_end + 1];
+        Text {
+            keyword: latin1(keyword),
+            text: latin1(text),
+            warnings,
+        }
+    }
 
-        let text = data[keyword_end + 2..].to_vec();
+    fn parse_ztxt(&self, limits: &Limits) -> Result<Ztxt, String> {
+        let data = self.data.as_deref().unwrap_or_default();
+        let mut warnings = vec![];
+
+        let (keyword, rest) = split_null(data, "keyword", &mut warnings);
+        warnings.extend(keyword_warnings(keyword));
+
+        let Some((&compression_method, compressed)) = rest.split_first() else {
+            warnings.push(String::from("Missing compression method"));
+            return Ok(Ztxt {
+                keyword: latin1(keyword),
+                compression_method: 0,
+                text: String::new(),
+                warnings,
+            });
+        };
+        if compression_method != 0 {
+            warnings.push(format!("Unknown compression method {}", compression_method));
+        }
 
-        let result = decompress(&text);
+        match decompress(compressed, limits.max_text_size) {
+            Ok(text) => Ok(Ztxt {
+                keyword: latin1(keyword),
+                compression_method,
+                text: latin1(&text),
+                warnings,
+            }),
+            Err(e) => Err(format!("Error decompressing zTXt chunk: {}", e)),
+        }
+    }
 
-        match result {
-            Ok(_) => Ztxt {
-                keyword,
+    fn parse_iccp(&self, limits: &Limits) -> Result<Iccp, String> {
+        let data = self.data.as_deref().unwrap_or_default();
+
+        let name_end = data
+            .iter()
+            .position(|&x| x == 0)
+            .ok_or("Missing null separator after the profile name")?;
+        let profile_name = String::from_utf8_lossy(&data[..name_end]).into_owned();
+        let (&compression_method, compressed) = data[name_end + 1..]
+            .split_first()
+            .ok_or("Missing compression method")?;
+
+        match decompress(compressed, limits.max_text_size) {
+            Ok(profile) => Ok(Iccp {
+                profile_name,
                 compression_method,
-                text: String::from_utf8(result.unwrap()).unwrap(),
-            },
-            Err(e) => panic!("Error decompressing zTXt chunk: {}", e),
+                profile,
+            }),
+            Err(e) => Err(format!("Error decompressing iCCP chunk: {}", e)),
         }
     }
 
     fn parse_hist(&self) -> Hist {
-        let data = self.data.as_ref().unwrap();
+        let data = self.data.as_deref().unwrap_or_default();
 
         data.chunks_exact(2)
             .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
             .collect()
     }
 
-    fn parse_chrm(&self) -> Chrm {
-        let data = self.data.as_ref().unwrap();
+    fn parse_actl(&self) -> Result<Actl, String> {
+        let data = self.fixed_data(8)?;
+
+        Ok(Actl {
+            num_frames: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            num_plays: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        })
+    }
 
-        Chrm {
+    fn parse_fctl(&self) -> Result<Fctl, String> {
+        let data = self.fixed_data(26)?;
+
+        let u32_at =
+            |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        Ok(Fctl {
+            sequence_number: u32_at(0),
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16::from_be_bytes([data[20], data[21]]),
+            delay_den: u16::from_be_bytes([data[22], data[23]]),
+            dispose_op: data[24],
+            blend_op: data[25],
+        })
+    }
+
+    fn parse_chrm(&self) -> Result<Chrm, String> {
+        let data = self.fixed_data(32)?;
+
+        Ok(Chrm {
             white_point_x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
             white_point_y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
             red_x: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
@@ -500,19 +944,88 @@ impl Chunk {
             green_y: u32::from_be_bytes([data[20], data[21], data[22], data[23]]),
             blue_x: u32::from_be_bytes([data[24], data[25], data[26], data[27]]),
             blue_y: u32::from_be_bytes([data[28], data[29], data[30], data[31]]),
+        })
+    }
+}
+
+/// Splits `data` at the first null byte, warning when there is none and taking everything as the
+/// field.
+fn split_null<'a>(data: &'a [u8], field: &str, warnings: &mut Vec<String>) -> (&'a [u8], &'a [u8]) {
+    match data.iter().position(|&b| b == 0) {
+        Some(end) => (&data[..end], &data[end + 1..]),
+        None => {
+            warnings.push(format!("Missing null separator after the {}", field));
+            (data, &[])
         }
     }
 }
 
+/// Decodes ISO-8859-1, whose code points are the byte values.
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn utf8(bytes: &[u8], field: &str, warnings: &mut Vec<String>) -> String {
+    String::from_utf8(bytes.to_vec()).unwrap_or_else(|e| {
+        warnings.push(format!(
+            "{} is not valid UTF-8 at byte {}",
+            field,
+            e.utf8_error().valid_up_to()
+        ));
+        String::from_utf8_lossy(bytes).into_owned()
+    })
+}
+
+/// Checks the rules for tEXt, zTXt, iTXt and iCCP keywords: 1-79 printable Latin-1 characters
+/// without leading, trailing or consecutive spaces.
+fn keyword_warnings(keyword: &[u8]) -> Vec<String> {
+    let mut warnings = vec![];
+    if keyword.is_empty() || keyword.len() > 79 {
+        warnings.push(format!(
+            "Keyword is {} bytes long, expected 1 to 79",
+            keyword.len()
+        ));
+    }
+    if keyword.first() == Some(&b' ') || keyword.last() == Some(&b' ') {
+        warnings.push(String::from("Keyword has leading or trailing spaces"));
+    }
+    if keyword.windows(2).any(|pair| pair == b"  ") {
+        warnings.push(String::from("Keyword has consecutive spaces"));
+    }
+    if let Some(&b) = keyword
+        .iter()
+        .find(|&&b| !(32..=126).contains(&b) && b < 161)
+    {
+        warnings.push(format!(
+            "Keyword contains the non-printable byte {:#04x}",
+            b
+        ));
+    }
+    warnings
+}
+
+/// Language tags are hyphen-separated subtags of 1-8 ASCII letters or digits, starting with
+/// letters, as in `en` or `x-klingon`. An empty tag means unknown.
+fn is_valid_language_tag(tag: &str) -> bool {
+    tag.is_empty()
+        || tag.split('-').enumerate().all(|(i, subtag)| {
+            (1..=8).contains(&subtag.len())
+                && subtag
+                    .chars()
+                    .all(|c| c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()))
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixtures::gray_ihdr;
 
     #[test]
     fn test_validate_checksum() {
         let chunk = Chunk::new(
             4,
-            String::from("abcd"),
+            ChunkType::from(*b"abcd"),
             Some(vec![0x01, 0x02, 0x03, 0x04]),
             283159080,
         );
@@ -526,7 +1039,7 @@ mod tests {
 
     #[test]
     fn test_validate_checksum_empty_data() {
-        let chunk = Chunk::new(0, String::from("IEND"), None, 2923585666);
+        let chunk = Chunk::new(0, ChunkType::from(*b"IEND"), None, 2923585666);
 
         assert!(
             chunk.validate_checksum(),
@@ -535,11 +1048,256 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_data() {
+        let chunk = Chunk::from_data(ChunkType::from(*b"abcd"), vec![0x01, 0x02, 0x03, 0x04]);
+
+        assert_eq!(chunk.length, 4);
+        assert_eq!(chunk.crc, 283159080);
+    }
+
+    #[test]
+    fn test_data_fields_fixed() {
+        let chunk = Chunk::from_data(ChunkType::from(*b"gAMA"), vec![0x00, 0x00, 0xb1, 0x8f]);
+
+        assert_eq!(
+            chunk.data_fields(),
+            vec![ChunkField {
+                name: "gamma",
+                range: 0..4
+            }]
+        );
+    }
+
+    #[test]
+    fn test_data_fields_text() {
+        let chunk = Chunk::from_data(ChunkType::from(*b"tEXt"), b"Title\0PNG".to_vec());
+        let fields = chunk.data_fields();
+
+        assert_eq!(
+            fields.iter().map(|f| f.name).collect::<Vec<_>>(),
+            vec!["keyword", "null separator", "text"]
+        );
+        assert_eq!(fields[2].range, 6..9);
+    }
+
+    #[test]
+    fn test_data_fields_truncated() {
+        let chunk = Chunk::from_data(
+            ChunkType::from(*b"pHYs"),
+            vec![0x00, 0x00, 0x0b, 0x13, 0x00],
+        );
+        let fields = chunk.data_fields();
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[1].range, 4..5);
+    }
+
+    #[test]
+    fn test_field_offsets() {
+        let chunk = Chunk::from_data(ChunkType::from(*b"abcd"), vec![0x01, 0x02, 0x03, 0x04])
+            .with_offset(33);
+
+        assert_eq!(chunk.length_offset(), Some(33));
+        assert_eq!(chunk.type_offset(), Some(37));
+        assert_eq!(chunk.data_offset(), Some(41));
+        assert_eq!(chunk.crc_offset(), Some(45));
+        assert_eq!(chunk.end_offset(), Some(49));
+    }
+
+    #[test]
+    fn test_field_offsets_unknown() {
+        let chunk = Chunk::new(0, ChunkType::from(*b"IEND"), None, 2923585666);
+
+        assert_eq!(chunk.data_offset(), None);
+    }
+
+    #[test]
+    fn test_expected_data_size() {
+        let mut ihdr = gray_ihdr(3, 2, 1, InterlaceMethod::None);
+
+        assert_eq!(ihdr.expected_data_size(), 4);
+
+        ihdr.bit_depth = 16;
+        ihdr.color_type = ColorType::TruecolorAlpha;
+
+        assert_eq!(ihdr.expected_data_size(), 2 * (1 + 24));
+
+        ihdr.interlace_method = InterlaceMethod::Adam7;
+
+        // Passes 1, 4, 6 and 7 are non-empty: 1x1, 1x1, 1x1 and 3x1
+        assert_eq!(ihdr.expected_data_size(), 3 * (1 + 8) + (1 + 24));
+    }
+
+    #[test]
+    fn test_parse_ztxt_limit() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(crate::png::compression::compress(&[b'a'; 100]).unwrap());
+        let chunk = Chunk::from_data(ChunkType::from(*b"zTXt"), data);
+        let limits = Limits {
+            max_text_size: 99,
+            ..Limits::default()
+        };
+
+        assert!(matches!(
+            chunk.parse_with_limits(&limits),
+            ParsedChunk::Invalid(_)
+        ));
+        assert!(matches!(chunk.parse(), ParsedChunk::Ztxt(_)));
+    }
+
+    #[test]
+    fn test_parse_text_latin1() {
+        let chunk = Chunk::from_data(ChunkType::from(*b"tEXt"), b"Author\0Ren\xe9".to_vec());
+
+        match chunk.parse() {
+            ParsedChunk::Text(text) => {
+                assert_eq!(text.text, "René");
+                assert!(text.warnings.is_empty());
+            }
+            _ => panic!("Expected a tEXt chunk"),
+        }
+    }
+
+    #[test]
+    fn test_parse_text_missing_null() {
+        let chunk = Chunk::from_data(ChunkType::from(*b"tEXt"), b" Bad  keyword ".to_vec());
+
+        match chunk.parse() {
+            ParsedChunk::Text(text) => assert_eq!(
+                text.warnings,
+                vec![
+                    "Missing null separator after the keyword",
+                    "Keyword has leading or trailing spaces",
+                    "Keyword has consecutive spaces",
+                ]
+            ),
+            _ => panic!("Expected a tEXt chunk"),
+        }
+    }
+
+    #[test]
+    fn test_keyword_warnings() {
+        assert!(keyword_warnings(b"Comment").is_empty());
+        assert!(keyword_warnings(b"Caf\xe9").is_empty());
+        assert_eq!(
+            keyword_warnings(b""),
+            vec!["Keyword is 0 bytes long, expected 1 to 79"]
+        );
+        assert_eq!(keyword_warnings(&[b'a'; 80]).len(), 1);
+        assert_eq!(
+            keyword_warnings(b"Tab\there"),
+            vec!["Keyword contains the non-printable byte 0x09"]
+        );
+    }
+
+    #[test]
+    fn test_parse_itxt_invalid() {
+        let chunk = Chunk::from_data(
+            ChunkType::from(*b"iTXt"),
+            b"Title\0\0\0en_US\0\xff\0ok \xc3".to_vec(),
+        );
+
+        match chunk.parse() {
+            ParsedChunk::Itxt(itxt) => {
+                assert_eq!(itxt.text, "ok \u{fffd}");
+                assert_eq!(
+                    itxt.warnings,
+                    vec![
+                        "Invalid language tag \"en_US\"",
+                        "Translated keyword is not valid UTF-8 at byte 0",
+                        "Text is not valid UTF-8 at byte 3",
+                    ]
+                );
+            }
+            _ => panic!("Expected an iTXt chunk"),
+        }
+    }
+
+    #[test]
+    fn test_parse_itxt_truncated() {
+        let chunk = Chunk::from_data(ChunkType::from(*b"iTXt"), b"Title".to_vec());
+
+        assert!(matches!(chunk.parse(), ParsedChunk::Itxt(itxt) if itxt.warnings.len() == 4));
+    }
+
+    #[test]
+    fn test_is_valid_language_tag() {
+        assert!(is_valid_language_tag(""));
+        assert!(is_valid_language_tag("en-US"));
+        assert!(is_valid_language_tag("x-klingon"));
+        assert!(!is_valid_language_tag("en_US"));
+        assert!(!is_valid_language_tag("1en"));
+        assert!(!is_valid_language_tag("en-"));
+    }
+
+    #[test]
+    fn test_parse_fctl() {
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5];
+        data.extend([0, 1, 0, 10, 1, 0]);
+        let chunk = Chunk::from_data(ChunkType::from(*b"fcTL"), data);
+
+        match chunk.parse() {
+            ParsedChunk::Fctl(fctl) => {
+                assert_eq!((fctl.width, fctl.height), (2, 3));
+                assert_eq!((fctl.x_offset, fctl.y_offset), (4, 5));
+                assert_eq!((fctl.delay_num, fctl.delay_den), (1, 10));
+                assert_eq!((fctl.dispose_op, fctl.blend_op), (1, 0));
+            }
+            parsed => panic!("Unexpected {}", parsed),
+        }
+
+        let truncated = Chunk::from_data(ChunkType::from(*b"fcTL"), vec![0; 4]);
+        assert!(matches!(truncated.parse(), ParsedChunk::Invalid(_)));
+    }
+
+    #[test]
+    fn test_parse_invalid_chunks() {
+        let invalid = |chunk_type: &[u8; 4], data: Vec<u8>| {
+            let chunk = Chunk::from_data(ChunkType::from(*chunk_type), data);
+            matches!(chunk.parse(), ParsedChunk::Invalid(_))
+        };
+
+        assert!(invalid(b"IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0]));
+        assert!(invalid(
+            b"IHDR",
+            vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 5, 0, 0, 0]
+        ));
+        assert!(invalid(
+            b"IHDR",
+            vec![0, 0, 0, 1, 0, 0, 0, 1, 4, 2, 0, 0, 0]
+        ));
+        assert!(invalid(
+            b"IHDR",
+            vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 2]
+        ));
+        assert!(!invalid(
+            b"IHDR",
+            vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 1]
+        ));
+        assert!(invalid(
+            b"IHDR",
+            vec![0, 0, 0, 0, 0, 0, 0, 1, 8, 2, 0, 0, 0]
+        ));
+        assert!(invalid(
+            b"IHDR",
+            vec![0, 0, 0, 1, 0, 0, 0, 0, 8, 2, 0, 0, 0]
+        ));
+        assert!(invalid(b"pHYs", vec![0; 8]));
+        assert!(invalid(b"sRGB", vec![4]));
+        assert!(invalid(b"gAMA", vec![]));
+        assert!(invalid(b"bKGD", vec![0; 3]));
+        assert!(invalid(b"sBIT", vec![0; 5]));
+        assert!(invalid(b"cHRM", vec![0; 31]));
+        assert!(invalid(b"iCCP", b"no separator".to_vec()));
+        assert!(invalid(b"iCCP", b"name\0".to_vec()));
+    }
+
     #[test]
     fn test_validate_invalid_checksum() {
         let chunk = Chunk::new(
             4,
-            String::from("abcd"),
+            ChunkType::from(*b"abcd"),
             Some(vec![0x01, 0x02, 0x03, 0x04]),
             11111111,
         );
@@ -0,0 +1,195 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Chunk types defined by the PNG specification and its registered extensions.
+const KNOWN_CHUNK_TYPES: [&[u8; 4]; 30] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"cICP", b"mDCV", b"cLLI", b"tEXt", b"zTXt", b"iTXt", b"bKGD", b"hIST", b"pHYs", b"sPLT",
+    b"eXIf", b"tIME", b"acTL", b"fcTL", b"fdAT", b"oFFs", b"pCAL", b"sCAL", b"sTER", b"gIFg",
+];
+
+/// Private chunk types written by known applications.
+const VENDOR_CHUNK_TYPES: [(&[u8; 4], &str); 13] = [
+    (b"CgBI", "Apple (Xcode iOS-optimized PNG)"),
+    (b"iDOT", "Apple (multithreaded decoding hints)"),
+    (b"mkBF", "Adobe Fireworks"),
+    (b"mkBS", "Adobe Fireworks"),
+    (b"mkBT", "Adobe Fireworks"),
+    (b"mkTS", "Adobe Fireworks"),
+    (b"prVW", "Adobe Fireworks (preview)"),
+    (b"vpAg", "ImageMagick (virtual page)"),
+    (b"caNv", "ImageMagick (canvas)"),
+    (b"orNT", "ImageMagick (orientation)"),
+    (b"npTc", "Android (nine-patch)"),
+    (b"npLb", "Android (nine-patch layout bounds)"),
+    (b"msOG", "Microsoft Office (embedded GIF)"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkType([u8; 4]);
+
+impl ChunkType {
+    pub fn new(bytes: [u8; 4]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn bytes(&self) -> [u8; 4] {
+        self.0
+    }
+
+    /// Returns the type as a string, or `????` if the bytes are not valid UTF-8.
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).unwrap_or("????")
+    }
+
+    /// All four bytes must be ASCII letters (A-Z, a-z).
+    pub fn is_valid(&self) -> bool {
+        self.0.iter().all(|b| b.is_ascii_alphabetic())
+    }
+
+    /// Bit 5 of the first byte: uppercase means critical, lowercase means ancillary.
+    pub fn is_critical(&self) -> bool {
+        self.0[0] & 0x20 == 0
+    }
+
+    /// Bit 5 of the second byte: uppercase means public, lowercase means private.
+    pub fn is_public(&self) -> bool {
+        self.0[1] & 0x20 == 0
+    }
+
+    /// Bit 5 of the third byte must be zero (uppercase) in the current version of PNG.
+    pub fn is_reserved_bit_valid(&self) -> bool {
+        self.0[2] & 0x20 == 0
+    }
+
+    /// Bit 5 of the fourth byte: lowercase means safe to copy by editors that do not know it.
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.0[3] & 0x20 != 0
+    }
+
+    pub fn is_known(&self) -> bool {
+        KNOWN_CHUNK_TYPES.iter().any(|t| **t == self.0)
+    }
+
+    /// Looks up the application known to write this private chunk type.
+    pub fn vendor(&self) -> Option<&'static str> {
+        VENDOR_CHUNK_TYPES
+            .iter()
+            .find(|(t, _)| **t == self.0)
+            .map(|(_, vendor)| *vendor)
+    }
+
+    pub fn properties(&self) -> String {
+        format!(
+            "{}, {}, {}, {}",
+            if self.is_critical() {
+                "critical"
+            } else {
+                "ancillary"
+            },
+            if self.is_public() {
+                "public"
+            } else {
+                "private"
+            },
+            if self.is_reserved_bit_valid() {
+                "reserved bit ok"
+            } else {
+                "reserved bit set"
+            },
+            if self.is_safe_to_copy() {
+                "safe to copy"
+            } else {
+                "unsafe to copy"
+            }
+        )
+    }
+}
+
+impl From<[u8; 4]> for ChunkType {
+    fn from(bytes: [u8; 4]) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl FromStr for ChunkType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes: [u8; 4] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| format!("Chunk type must be 4 bytes: {}", s))?;
+        Ok(Self(bytes))
+    }
+}
+
+impl PartialEq<&str> for ChunkType {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl Display for ChunkType {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_valid() {
+            formatter.write_str(self.as_str())
+        } else {
+            write!(formatter, "{:02x?}", self.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_property_bits() {
+        let chunk_type = ChunkType::from(*b"bLOb");
+
+        assert!(!chunk_type.is_critical());
+        assert!(chunk_type.is_public());
+        assert!(chunk_type.is_reserved_bit_valid());
+        assert!(chunk_type.is_safe_to_copy());
+    }
+
+    #[test]
+    fn test_property_bits_critical() {
+        let chunk_type = ChunkType::from(*b"IHDR");
+
+        assert!(chunk_type.is_critical());
+        assert!(chunk_type.is_public());
+        assert!(chunk_type.is_reserved_bit_valid());
+        assert!(!chunk_type.is_safe_to_copy());
+        assert!(chunk_type.is_known());
+    }
+
+    #[test]
+    fn test_reserved_bit_set() {
+        let chunk_type = ChunkType::from(*b"RUst");
+
+        assert!(!chunk_type.is_reserved_bit_valid());
+    }
+
+    #[test]
+    fn test_is_valid() {
+        assert!(ChunkType::from(*b"tEXt").is_valid());
+        assert!(!ChunkType::from(*b"tE1t").is_valid());
+        assert!(!ChunkType::from([0x00, 0xff, b'a', b'b']).is_valid());
+    }
+
+    #[test]
+    fn test_vendor() {
+        assert_eq!(ChunkType::from(*b"mkBF").vendor(), Some("Adobe Fireworks"));
+        assert_eq!(ChunkType::from(*b"abCd").vendor(), None);
+    }
+
+    #[test]
+    fn test_from_str() {
+        let chunk_type: ChunkType = "IDAT".parse().unwrap();
+
+        assert_eq!(chunk_type, "IDAT");
+        assert!("IDATA".parse::<ChunkType>().is_err());
+    }
+}
@@ -1,15 +1,83 @@
-use flate2::read::ZlibDecoder;
-use std::io::Read;
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use miniz_oxide::deflate::core::{
+    compress_to_output, create_comp_flags_from_zip_params, CompressionStrategy, CompressorOxide,
+    TDEFLFlush,
+};
+use std::io::{Read, Write};
 
-pub fn decompress(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
-    let mut zlib_decoder = ZlibDecoder::new(data);
+use crate::png::limits::limit_error;
+
+/// Decompresses a zlib stream, failing if the output would grow past `limit` bytes.
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, std::io::Error> {
+    read_limited(ZlibDecoder::new(data), limit)
+}
+
+/// Decompresses a raw deflate stream without a zlib header, as written by Xcode for CgBI files.
+pub fn decompress_raw(data: &[u8], limit: usize) -> Result<Vec<u8>, std::io::Error> {
+    read_limited(DeflateDecoder::new(data), limit)
+}
+
+fn read_limited<R: Read>(reader: R, limit: usize) -> Result<Vec<u8>, std::io::Error> {
     let mut decompressed_data = vec![];
-    let result = zlib_decoder.read_to_end(&mut decompressed_data);
+    reader
+        .take((limit as u64).saturating_add(1))
+        .read_to_end(&mut decompressed_data)?;
 
-    match result {
-        Ok(_) => Ok(decompressed_data),
-        Err(e) => Err(e),
+    if decompressed_data.len() > limit {
+        return Err(limit_error(format!(
+            "Decompressed data exceeds the limit of {} bytes",
+            limit
+        )));
     }
+
+    Ok(decompressed_data)
+}
+
+/// Length of the zlib stream at the start of `data`, so that anything stored after it can be
+/// found. Fails if the stream is corrupt or decompresses past `limit` bytes.
+pub fn zlib_stream_len(data: &[u8], limit: usize) -> Result<usize, std::io::Error> {
+    let mut decoder = flate2::bufread::ZlibDecoder::new(data);
+    read_limited(&mut decoder, limit)?;
+    Ok(decoder.total_in() as usize)
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut zlib_encoder = ZlibEncoder::new(vec![], Compression::default());
+    zlib_encoder.write_all(data)?;
+    zlib_encoder.finish()
+}
+
+/// Deflate match-finding strategies, as in zlib.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Default,
+    /// Only matches of 5 bytes or more, suited to filtered image data
+    Filtered,
+    HuffmanOnly,
+    /// Only matches at distance 1
+    Rle,
+}
+
+/// Compresses into a zlib stream with a level from 0 to 10 and a strategy, which flate2 does not
+/// expose.
+pub fn compress_with(data: &[u8], level: u8, strategy: Strategy) -> Vec<u8> {
+    let strategy = match strategy {
+        Strategy::Default => CompressionStrategy::Default,
+        Strategy::Filtered => CompressionStrategy::Filtered,
+        Strategy::HuffmanOnly => CompressionStrategy::HuffmanOnly,
+        Strategy::Rle => CompressionStrategy::RLE,
+    };
+    let flags = create_comp_flags_from_zip_params(level as i32, 15, strategy as i32);
+    let mut compressor = CompressorOxide::new(flags);
+
+    let mut output = vec![];
+    compress_to_output(&mut compressor, data, TDEFLFlush::Finish, |bytes| {
+        output.extend_from_slice(bytes);
+        true
+    });
+    output
 }
 
 #[cfg(test)]
@@ -23,7 +91,64 @@ mod tests {
         ];
         let expected = vec![0x74, 0x65, 0x73, 0x74];
 
-        let result = decompress(&data).unwrap();
+        let result = decompress(&data, usize::MAX).unwrap();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_decompress_raw() {
+        let data = vec![0x2b, 0x49, 0x2d, 0x2e, 0x01, 0x00];
+        let expected = vec![0x74, 0x65, 0x73, 0x74];
+
+        let result = decompress_raw(&data, usize::MAX).unwrap();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let data = vec![0x74, 0x65, 0x73, 0x74];
+
+        let result = decompress(&compress(&data).unwrap(), usize::MAX).unwrap();
+
+        assert_eq!(result, data);
+    }
+
+    #[test]
+    fn test_zlib_stream_len() {
+        let mut data = vec![
+            0x78, 0x5e, 0x2b, 0x49, 0x2d, 0x2e, 0x01, 0x00, 0x04, 0x5d, 0x01, 0xc1,
+        ];
+        data.extend(b"after");
+
+        assert_eq!(zlib_stream_len(&data, usize::MAX).unwrap(), 12);
+        assert!(zlib_stream_len(&data, 3).is_err());
+    }
+
+    #[test]
+    fn test_compress_with_roundtrip() {
+        let data: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+
+        for strategy in [
+            Strategy::Default,
+            Strategy::Filtered,
+            Strategy::HuffmanOnly,
+            Strategy::Rle,
+        ] {
+            let compressed = compress_with(&data, 9, strategy);
+            assert_eq!(decompress(&compressed, usize::MAX).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let data = compress(&[0; 1000]).unwrap();
+
+        assert_eq!(decompress(&data, 1000).unwrap().len(), 1000);
+
+        let result = decompress(&data, 999).map_err(|e| e.kind());
+        let expected = Err(std::io::ErrorKind::InvalidData);
 
         assert_eq!(result, expected);
     }
@@ -32,7 +157,7 @@ mod tests {
     fn test_decompress_invalid_stream() {
         let data = vec![0x00, 0x01, 0x02, 0x03];
 
-        let result = decompress(&data).map_err(|e| e.kind());
+        let result = decompress(&data, usize::MAX).map_err(|e| e.kind());
         let expected = Err(std::io::ErrorKind::InvalidInput);
 
         assert_eq!(result, expected);
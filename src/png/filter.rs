@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Filter {
     None,
     Sub,
@@ -7,6 +7,93 @@ pub enum Filter {
     Paeth,
 }
 
+impl TryFrom<u8> for Filter {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Filter::None),
+            1 => Ok(Filter::Sub),
+            2 => Ok(Filter::Up),
+            3 => Ok(Filter::Average),
+            4 => Ok(Filter::Paeth),
+            _ => Err(value),
+        }
+    }
+}
+
+impl Filter {
+    pub const ALL: [Filter; 5] = [
+        Filter::None,
+        Filter::Sub,
+        Filter::Up,
+        Filter::Average,
+        Filter::Paeth,
+    ];
+}
+
+/// Applies `filter` to a row of raw bytes, the inverse of `filter_scanline`. `previous` is the
+/// raw previous row, all zeros for the first row.
+pub fn filter(filter: Filter, previous: &[u8], current: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+    let left = |i: usize| {
+        if i >= bytes_per_pixel {
+            current[i - bytes_per_pixel]
+        } else {
+            0
+        }
+    };
+    let upper_left = |i: usize| {
+        if i >= bytes_per_pixel {
+            previous[i - bytes_per_pixel]
+        } else {
+            0
+        }
+    };
+
+    (0..current.len())
+        .map(|i| {
+            let prediction = match filter {
+                Filter::None => 0,
+                Filter::Sub => left(i),
+                Filter::Up => previous[i],
+                Filter::Average => ((left(i) as u16 + previous[i] as u16) / 2) as u8,
+                Filter::Paeth => paeth_predictor(left(i), previous[i], upper_left(i)),
+            };
+            current[i].wrapping_sub(prediction)
+        })
+        .collect()
+}
+
+/// Picks the filter for a row with the minimum sum of absolute differences heuristic from the PNG
+/// specification: the output bytes, read as signed, with the smallest absolute sum tend to
+/// compress best. Returns the filter and the filtered row.
+pub fn choose_filter(previous: &[u8], current: &[u8], bytes_per_pixel: usize) -> (Filter, Vec<u8>) {
+    Filter::ALL
+        .into_iter()
+        .map(|f| (f, filter(f, previous, current, bytes_per_pixel)))
+        .min_by_key(|(_, filtered)| {
+            filtered
+                .iter()
+                .map(|&b| (b as i8).unsigned_abs() as u64)
+                .sum::<u64>()
+        })
+        .unwrap()
+}
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
 pub fn filter_scanline(
     filter: Filter,
     previous: &[u8],
@@ -80,6 +167,65 @@ fn paeth_filter(previous: &[u8], current: &mut [u8], bytes_per_pixel: usize) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    /// Two rows of the same length, a whole number of pixels long.
+    fn rows() -> impl Strategy<Value = (usize, Vec<u8>, Vec<u8>)> {
+        (1..=8usize, 0..=32usize).prop_flat_map(|(bytes_per_pixel, pixels)| {
+            let row = prop::collection::vec(any::<u8>(), bytes_per_pixel * pixels);
+            (Just(bytes_per_pixel), row.clone(), row)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_filter_roundtrip(
+            (bytes_per_pixel, previous, current) in rows(),
+            f in prop::sample::select(&Filter::ALL[..]),
+        ) {
+            let mut row = filter(f, &previous, &current, bytes_per_pixel);
+            filter_scanline(f, &previous, &mut row, bytes_per_pixel);
+
+            prop_assert_eq!(row, current);
+        }
+    }
+
+    #[test]
+    fn test_filter_first_row() {
+        let previous = vec![0; 6];
+        let current = vec![10, 20, 30, 40, 50, 60];
+
+        assert_eq!(filter(Filter::None, &previous, &current, 3), current);
+        assert_eq!(
+            filter(Filter::Sub, &previous, &current, 3),
+            vec![10, 20, 30, 30, 30, 30]
+        );
+        assert_eq!(filter(Filter::Up, &previous, &current, 3), current);
+        assert_eq!(
+            filter(Filter::Average, &previous, &current, 3),
+            vec![10, 20, 30, 35, 40, 45]
+        );
+        assert_eq!(
+            filter(Filter::Paeth, &previous, &current, 3),
+            vec![10, 20, 30, 30, 30, 30]
+        );
+    }
+
+    #[test]
+    fn test_choose_filter() {
+        let previous = vec![0; 16];
+        let gradient: Vec<u8> = (0..16).collect();
+
+        assert_eq!(
+            choose_filter(&previous, &gradient, 1),
+            (
+                Filter::Sub,
+                vec![0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1]
+            )
+        );
+        assert_eq!(choose_filter(&gradient, &gradient, 1).0, Filter::Up);
+        assert_eq!(choose_filter(&previous, &previous, 1).0, Filter::None);
+    }
 
     #[test]
     fn test_sub_filter() {
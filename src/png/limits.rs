@@ -0,0 +1,121 @@
+use crate::png::IHDR;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+
+/// Resource limits applied while parsing and decoding, to protect against hostile files such as
+/// zip bombs or chunks that claim gigabytes of data.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Largest accepted chunk length field
+    pub max_chunk_length: u32,
+    /// Largest accepted size of the decompressed IDAT stream
+    pub max_decompressed_size: usize,
+    /// Largest accepted decompressed size of all zTXt, iTXt and iCCP chunks together
+    pub max_text_size: usize,
+    /// Largest accepted number of bytes after `IEND`
+    pub max_trailing_size: usize,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_chunk_length: 64 * 1024 * 1024,
+            max_decompressed_size: 1024 * 1024 * 1024,
+            max_text_size: 16 * 1024 * 1024,
+            max_trailing_size: 256 * 1024 * 1024,
+            max_width: 1 << 20,
+            max_height: 1 << 20,
+            max_pixels: 1 << 30,
+        }
+    }
+}
+
+impl Limits {
+    /// No limits besides what the PNG specification allows.
+    pub fn unlimited() -> Self {
+        Self {
+            max_chunk_length: u32::MAX,
+            max_decompressed_size: usize::MAX,
+            max_text_size: usize::MAX,
+            max_trailing_size: usize::MAX,
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: u64::MAX,
+        }
+    }
+
+    pub fn check_chunk_length(&self, length: u32) -> Result<(), Error> {
+        if length > self.max_chunk_length {
+            return Err(limit_error(format!(
+                "Chunk length {} exceeds the limit of {} bytes",
+                length, self.max_chunk_length
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_dimensions(&self, ihdr: &IHDR) -> Result<(), Error> {
+        if ihdr.width > self.max_width || ihdr.height > self.max_height {
+            return Err(limit_error(format!(
+                "Image dimensions {}x{} exceed the limit of {}x{}",
+                ihdr.width, ihdr.height, self.max_width, self.max_height
+            )));
+        }
+
+        let pixels = ihdr.width as u64 * ihdr.height as u64;
+        if pixels > self.max_pixels {
+            return Err(limit_error(format!(
+                "Image pixel count {} exceeds the limit of {}",
+                pixels, self.max_pixels
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// The error wrapped in an `InvalidData` I/O error when a limit trips.
+#[derive(Debug)]
+pub struct LimitExceeded(pub String);
+
+impl Display for LimitExceeded {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+pub fn limit_error(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, LimitExceeded(message))
+}
+
+pub fn is_limit_error(error: &Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<LimitExceeded>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_chunk_length() {
+        let limits = Limits {
+            max_chunk_length: 10,
+            ..Limits::default()
+        };
+
+        assert!(limits.check_chunk_length(10).is_ok());
+        assert_eq!(
+            limits.check_chunk_length(11).unwrap_err().to_string(),
+            "Chunk length 11 exceeds the limit of 10 bytes"
+        );
+        assert!(is_limit_error(&limits.check_chunk_length(11).unwrap_err()));
+    }
+}
@@ -0,0 +1,315 @@
+use crate::png::chunk::ADAM7;
+use crate::png::filter::{filter_scanline, Filter};
+use crate::png::{Chunk, InterlaceMethod, IHDR};
+use std::io::{Error, ErrorKind, Read};
+
+/// Reads the data of consecutive IDAT chunks as one stream, without concatenating them.
+pub struct IdatReader<'a> {
+    chunks: std::slice::Iter<'a, Chunk>,
+    current: &'a [u8],
+}
+
+impl<'a> IdatReader<'a> {
+    pub fn new(chunks: &'a [Chunk]) -> Self {
+        Self {
+            chunks: chunks.iter(),
+            current: &[],
+        }
+    }
+}
+
+impl Read for IdatReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) if chunk.chunk_type == "IDAT" => {
+                    self.current = chunk.data.as_deref().unwrap_or(&[]);
+                }
+                Some(_) => {}
+                None => return Ok(0),
+            }
+        }
+
+        self.current.read(buf)
+    }
+}
+
+/// An unfiltered row of the image data stream.
+pub struct Row<'a> {
+    /// 0 for non-interlaced images, otherwise the Adam7 pass (1-7)
+    pub pass: usize,
+    /// The row's position in the full image
+    pub y: u32,
+    /// Number of pixels in the row
+    pub width: u32,
+    /// Column of the row's first pixel in the full image
+    pub x_offset: u32,
+    /// Distance in the full image between the row's pixels
+    pub x_step: u32,
+    /// Raw samples, packed according to the bit depth
+    pub data: &'a [u8],
+}
+
+impl Row<'_> {
+    /// Column in the full image of the row's `i`th pixel.
+    pub fn image_x(&self, i: u32) -> u32 {
+        self.x_offset + i * self.x_step
+    }
+}
+
+struct Pass {
+    number: usize,
+    width: u32,
+    height: u32,
+    x_offset: u32,
+    y_offset: u32,
+    x_step: u32,
+    y_step: u32,
+}
+
+/// Decodes the decompressed image data stream one row at a time. Only the current and previous
+/// rows are kept in memory.
+pub struct RowDecoder<R: Read> {
+    reader: R,
+    bits_per_pixel: u64,
+    filter_bytes_per_pixel: usize,
+    passes: Vec<Pass>,
+    pass_index: usize,
+    row_index: u32,
+    previous: Vec<u8>,
+    current: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> RowDecoder<R> {
+    pub fn new(ihdr: &IHDR, reader: R) -> Self {
+        let passes = match ihdr.interlace_method {
+            InterlaceMethod::None => vec![Pass {
+                number: 0,
+                width: ihdr.width,
+                height: ihdr.height,
+                x_offset: 0,
+                y_offset: 0,
+                x_step: 1,
+                y_step: 1,
+            }],
+            InterlaceMethod::Adam7 => ihdr
+                .passes()
+                .into_iter()
+                .zip(ADAM7)
+                .enumerate()
+                .map(|(i, ((width, height), (x, y, dx, dy)))| Pass {
+                    number: i + 1,
+                    width,
+                    height,
+                    x_offset: x,
+                    y_offset: y,
+                    x_step: dx,
+                    y_step: dy,
+                })
+                .collect(),
+        };
+
+        let bits_per_pixel = ihdr.bits_per_pixel();
+
+        Self {
+            reader,
+            bits_per_pixel,
+            filter_bytes_per_pixel: bits_per_pixel.div_ceil(8) as usize,
+            passes: passes
+                .into_iter()
+                .filter(|pass| pass.width > 0 && pass.height > 0)
+                .collect(),
+            pass_index: 0,
+            row_index: 0,
+            previous: vec![],
+            current: vec![],
+            finished: false,
+        }
+    }
+
+    /// Returns the next row, or `None` once every row of every pass has been read. Data left in
+    /// the stream after the last row is reported as an error.
+    pub fn next_row(&mut self) -> Result<Option<Row<'_>>, Error> {
+        if self.pass_index < self.passes.len()
+            && self.row_index == self.passes[self.pass_index].height
+        {
+            self.pass_index += 1;
+            self.row_index = 0;
+        }
+
+        let Some(pass) = self.passes.get(self.pass_index) else {
+            return self.finish().map(|_| None);
+        };
+
+        let row_bytes = (pass.width as u64 * self.bits_per_pixel).div_ceil(8) as usize;
+        if self.row_index == 0 {
+            self.previous.clear();
+            self.previous.resize(row_bytes, 0);
+        }
+        self.current.resize(row_bytes, 0);
+
+        let y = pass.y_offset + self.row_index * pass.y_step;
+        let mut filter_type = [0];
+        self.reader
+            .read_exact(&mut filter_type)
+            .and_then(|_| self.reader.read_exact(&mut self.current))
+            .map_err(|e| match e.kind() {
+                ErrorKind::UnexpectedEof => Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("Image data ended before row {} of pass {}", y, pass.number),
+                ),
+                _ => e,
+            })?;
+
+        let filter = Filter::try_from(filter_type[0]).map_err(|filter_type| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid filter type {} in row {}", filter_type, y),
+            )
+        })?;
+        filter_scanline(
+            filter,
+            &self.previous,
+            &mut self.current,
+            self.filter_bytes_per_pixel,
+        );
+
+        // The unfiltered row becomes the previous row for the next call
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.row_index += 1;
+
+        Ok(Some(Row {
+            pass: pass.number,
+            y,
+            width: pass.width,
+            x_offset: pass.x_offset,
+            x_step: pass.x_step,
+            data: &self.previous,
+        }))
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let mut extra = [0];
+        if self.reader.read(&mut extra)? > 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Image data is longer than expected from IHDR",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, gray_ihdr};
+
+    #[test]
+    fn test_idat_reader() {
+        let chunks = vec![
+            chunk(b"IDAT", &[1, 2]),
+            chunk(b"tEXt", &[9]),
+            chunk(b"IDAT", &[]),
+            chunk(b"IDAT", &[3]),
+        ];
+        let mut data = vec![];
+
+        IdatReader::new(&chunks).read_to_end(&mut data).unwrap();
+
+        assert_eq!(data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_next_row() {
+        let ihdr = gray_ihdr(2, 2, 8, InterlaceMethod::None);
+        let data = [0, 1, 2, 2, 1, 1];
+        let mut decoder = RowDecoder::new(&ihdr, &data[..]);
+
+        let row = decoder.next_row().unwrap().unwrap();
+        assert_eq!((row.y, row.data), (0, &[1, 2][..]));
+
+        let row = decoder.next_row().unwrap().unwrap();
+        assert_eq!((row.y, row.data), (1, &[2, 3][..]));
+
+        assert!(decoder.next_row().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_next_row_packed_samples() {
+        let ihdr = gray_ihdr(10, 1, 1, InterlaceMethod::None);
+        let data = [0, 0b1010_1010, 0b1100_0000];
+        let mut decoder = RowDecoder::new(&ihdr, &data[..]);
+
+        let row = decoder.next_row().unwrap().unwrap();
+
+        assert_eq!(row.data, &[0b1010_1010, 0b1100_0000]);
+    }
+
+    #[test]
+    fn test_next_row_adam7() {
+        // A 2x2 image only has pixels in passes 1, 6 and 7
+        let ihdr = gray_ihdr(2, 2, 8, InterlaceMethod::Adam7);
+        let data = [0, 1, 0, 2, 0, 3, 4];
+        let mut decoder = RowDecoder::new(&ihdr, &data[..]);
+        let mut rows = vec![];
+
+        while let Some(row) = decoder.next_row().unwrap() {
+            rows.push((row.pass, row.y, row.image_x(0), row.data.to_vec()));
+        }
+
+        assert_eq!(
+            rows,
+            vec![
+                (1, 0, 0, vec![1]),
+                (6, 0, 1, vec![2]),
+                (7, 1, 0, vec![3, 4])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_row_truncated() {
+        let ihdr = gray_ihdr(2, 2, 8, InterlaceMethod::None);
+        let data = [0, 1, 2, 0, 1];
+        let mut decoder = RowDecoder::new(&ihdr, &data[..]);
+
+        assert!(decoder.next_row().is_ok());
+        assert_eq!(
+            decoder.next_row().err().unwrap().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_next_row_invalid_filter() {
+        let ihdr = gray_ihdr(1, 1, 8, InterlaceMethod::None);
+        let data = [5, 1];
+        let mut decoder = RowDecoder::new(&ihdr, &data[..]);
+
+        assert_eq!(
+            decoder.next_row().err().unwrap().to_string(),
+            "Invalid filter type 5 in row 0"
+        );
+    }
+
+    #[test]
+    fn test_next_row_extra_data() {
+        let ihdr = gray_ihdr(1, 1, 8, InterlaceMethod::None);
+        let data = [0, 1, 0];
+        let mut decoder = RowDecoder::new(&ihdr, &data[..]);
+
+        assert!(decoder.next_row().is_ok());
+        assert_eq!(
+            decoder.next_row().err().unwrap().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,132 +1,266 @@
-use crate::png::filter::{filter_scanline, Filter};
-use crate::png::{Pixel, IHDR, PLTE};
+use crate::png::rows::RowDecoder;
+use crate::png::{ColorType, Pixel, Transparency, IHDR, PLTE};
+use std::io::Read;
 
 #[derive(Debug)]
 pub struct Scanline {
     pub pixels: Vec<Pixel>,
 }
 
-pub fn parse_scanlines(ihdr: &IHDR, plte: Option<&PLTE>, data: &[u8]) -> Vec<Scanline> {
-    let mut scanlines: Vec<Vec<u8>> = vec![];
+/// Decodes decompressed image data into full-size scanlines, placing the pixels of interlaced
+/// passes at their position in the image.
+pub fn parse_scanlines(
+    ihdr: &IHDR,
+    plte: Option<&PLTE>,
+    data: &[u8],
+) -> Result<Vec<Scanline>, std::io::Error> {
+    decode_scanlines(ihdr, plte, RowDecoder::new(ihdr, data))
+}
 
-    let bytes_per_pixel = ihdr.bytes_per_pixel();
-    let bytes_per_scanline = 1 + ihdr.width as usize * bytes_per_pixel;
+pub fn decode_scanlines<R: Read>(
+    ihdr: &IHDR,
+    plte: Option<&PLTE>,
+    mut decoder: RowDecoder<R>,
+) -> Result<Vec<Scanline>, std::io::Error> {
+    if matches!(ihdr.color_type, ColorType::Indexed) && plte.is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "PLTE chunk not found",
+        ));
+    }
 
-    for i in (0..data.len()).step_by(bytes_per_scanline) {
-        let filter_type = match data[i] {
-            0 => Filter::None,
-            1 => Filter::Sub,
-            2 => Filter::Up,
-            3 => Filter::Average,
-            4 => Filter::Paeth,
-            _ => panic!("Invalid filter type"),
-        };
+    let mut scanlines = vec![];
 
-        let mut scanline_data = data
-            .iter()
-            .take(i + bytes_per_scanline)
-            .skip(i + 1)
-            .cloned()
-            .collect::<Vec<u8>>();
-
-        let previous_scanline: Vec<u8> = match scanlines.last() {
-            Some(scanline) => scanline.clone(),
-            None => vec![0; bytes_per_scanline],
-        };
+    while let Some(row) = decoder.next_row()? {
+        let pixels = parse_pixels(ihdr, plte, row.data, row.width);
+        if row.pass == 0 {
+            scanlines.push(Scanline { pixels });
+            continue;
+        }
+
+        if scanlines.is_empty() {
+            let blank = Scanline {
+                pixels: vec![blank_pixel(&ihdr.color_type); ihdr.width as usize],
+            };
+            scanlines.resize_with(ihdr.height as usize, || Scanline {
+                pixels: blank.pixels.clone(),
+            });
+        }
+        let scanline = &mut scanlines[row.y as usize];
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            scanline.pixels[row.image_x(i as u32) as usize] = pixel;
+        }
+    }
 
-        filter_scanline(
-            filter_type,
-            &previous_scanline,
-            &mut scanline_data,
-            bytes_per_pixel,
-        );
+    Ok(scanlines)
+}
 
-        scanlines.push(scanline_data);
+fn blank_pixel(color_type: &ColorType) -> Pixel {
+    match color_type {
+        ColorType::Grayscale => Pixel::Grayscale(0),
+        ColorType::GrayscaleAlpha => Pixel::GrayscaleAlpha(0, 0),
+        ColorType::Truecolor => Pixel::Truecolor(0, 0, 0),
+        ColorType::TruecolorAlpha | ColorType::Indexed => Pixel::TruecolorAlpha(0, 0, 0, 0),
     }
+}
 
-    scanlines
-        .iter()
-        .map(|scanline| Scanline {
-            pixels: parse_pixels(ihdr, plte, scanline),
-        })
-        .collect()
+/// Unpacks `count` samples from a row, each sample keeping its original value range.
+pub fn unpack_samples(data: &[u8], bit_depth: u8, count: usize) -> Vec<u16> {
+    match bit_depth {
+        8 => data.iter().take(count).map(|&b| b as u16).collect(),
+        16 => data
+            .chunks_exact(2)
+            .take(count)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect(),
+        _ => {
+            let mask = (1u16 << bit_depth) - 1;
+            (0..count)
+                .map(|i| {
+                    let bit = i * bit_depth as usize;
+                    let byte = data.get(bit / 8).copied().unwrap_or(0) as u16;
+                    (byte >> (8 - bit_depth as usize - bit % 8)) & mask
+                })
+                .collect()
+        }
+    }
 }
 
-fn parse_pixels(ihdr: &IHDR, plte: Option<&PLTE>, scanline: &[u8]) -> Vec<Pixel> {
+/// Scales a sample to 8 bits: 16-bit samples keep their high byte, lower bit depths are
+/// stretched to the full range.
+fn scale_sample(value: u16, bit_depth: u8) -> u8 {
+    match bit_depth {
+        16 => (value >> 8) as u8,
+        8 => value as u8,
+        _ => (value as u32 * 255 / ((1 << bit_depth) - 1)) as u8,
+    }
+}
+
+/// CgBI files store pixels as premultiplied BGRA, convert them to straight RGBA.
+pub fn convert_cgbi_pixels(pixels: &mut [Pixel]) {
+    for pixel in pixels {
+        *pixel = match *pixel {
+            Pixel::Truecolor(b, g, r) => Pixel::Truecolor(r, g, b),
+            Pixel::TruecolorAlpha(b, g, r, a) => Pixel::TruecolorAlpha(
+                unpremultiply(r, a),
+                unpremultiply(g, a),
+                unpremultiply(b, a),
+                a,
+            ),
+            pixel => pixel,
+        };
+    }
+}
+
+/// The `convert_cgbi_pixels` conversion for interleaved 8-bit RGB or RGBA samples.
+pub fn convert_cgbi_samples(data: &mut [u8], channels: u8) {
+    for pixel in data.chunks_exact_mut(channels as usize) {
+        pixel.swap(0, 2);
+        if let [r, g, b, a] = pixel {
+            *r = unpremultiply(*r, *a);
+            *g = unpremultiply(*g, *a);
+            *b = unpremultiply(*b, *a);
+        }
+    }
+}
+
+fn unpremultiply(value: u8, alpha: u8) -> u8 {
+    match alpha {
+        0 => 0,
+        255 => value,
+        _ => ((value as u32 * 255 + alpha as u32 / 2) / alpha as u32).min(255) as u8,
+    }
+}
+
+pub fn parse_pixels(ihdr: &IHDR, plte: Option<&PLTE>, scanline: &[u8], width: u32) -> Vec<Pixel> {
+    let channels = ihdr.color_type.channels() as usize;
+    let samples = unpack_samples(scanline, ihdr.bit_depth, width as usize * channels);
+    let scale = |value: u16| scale_sample(value, ihdr.bit_depth);
+
     match ihdr.color_type {
-        super::ColorType::Indexed => {
-            let plte = plte.unwrap();
-            let alpha = match plte.transparency.clone() {
-                Some(transparency) => match transparency {
-                    super::Transparency::Alpha(alpha) => alpha,
-                    _ => panic!("Invalid transparency type"),
-                },
-                None => vec![],
+        ColorType::Indexed => {
+            let entries = plte.map_or(&[][..], |plte| &plte.entries[..]);
+            let alpha = match plte.and_then(|plte| plte.transparency.as_ref()) {
+                Some(Transparency::Alpha(alpha)) => &alpha[..],
+                _ => &[],
             };
 
-            return scanline
+            samples
                 .iter()
                 .map(|&index| {
-                    let (r, g, b) = plte.entries[index as usize];
-                    let a = if index >= alpha.len() as u8 {
-                        255
-                    } else {
-                        alpha[index as usize]
-                    };
+                    let (r, g, b) = entries.get(index as usize).copied().unwrap_or((0, 0, 0));
+                    let a = alpha.get(index as usize).copied().unwrap_or(255);
                     Pixel::TruecolorAlpha(r, g, b, a)
                 })
-                .collect();
-        }
-        super::ColorType::Grayscale => {
-            return scanline
-                .iter()
-                .map(|&gray| Pixel::Grayscale(gray))
-                .collect();
-        }
-        super::ColorType::GrayscaleAlpha => {
-            return scanline
-                .chunks(2)
-                .map(|chunk| Pixel::GrayscaleAlpha(chunk[0], chunk[1]))
-                .collect();
-        }
-        super::ColorType::Truecolor => {
-            return scanline
-                .chunks(3)
-                .map(|chunk| Pixel::Truecolor(chunk[0], chunk[1], chunk[2]))
-                .collect();
-        }
-        super::ColorType::TruecolorAlpha => {
-            return scanline
-                .chunks(4)
-                .map(|chunk| Pixel::TruecolorAlpha(chunk[0], chunk[1], chunk[2], chunk[3]))
-                .collect();
+                .collect()
         }
+        ColorType::Grayscale => samples
+            .iter()
+            .map(|&gray| Pixel::Grayscale(scale(gray)))
+            .collect(),
+        ColorType::GrayscaleAlpha => samples
+            .chunks_exact(2)
+            .map(|chunk| Pixel::GrayscaleAlpha(scale(chunk[0]), scale(chunk[1])))
+            .collect(),
+        ColorType::Truecolor => samples
+            .chunks_exact(3)
+            .map(|chunk| Pixel::Truecolor(scale(chunk[0]), scale(chunk[1]), scale(chunk[2])))
+            .collect(),
+        ColorType::TruecolorAlpha => samples
+            .chunks_exact(4)
+            .map(|chunk| {
+                Pixel::TruecolorAlpha(
+                    scale(chunk[0]),
+                    scale(chunk[1]),
+                    scale(chunk[2]),
+                    scale(chunk[3]),
+                )
+            })
+            .collect(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::png::ColorType;
-    use crate::png::chunk::{CompressionMethod, FilterMethod, InterlaceMethod};
+    use crate::fixtures::gray_ihdr;
+    use crate::png::chunk::InterlaceMethod;
 
     #[test]
     fn test_parse_pixels() {
-        let ihdr = IHDR {
-            width: 1,
-            height: 1,
-            bit_depth: 8,
-            color_type: ColorType::Grayscale,
-            compression_method: CompressionMethod::Deflate,
-            filter_method: FilterMethod::Adaptive,
-            interlace_method: InterlaceMethod::None,
-        };
+        let ihdr = gray_ihdr(1, 1, 8, InterlaceMethod::None);
 
         let plte = None;
         let scanline = vec![0x01];
 
-        let pixels = parse_pixels(&ihdr, plte, &scanline);
+        let pixels = parse_pixels(&ihdr, plte, &scanline, 1);
 
         assert_eq!(pixels.len(), 1);
     }
+
+    #[test]
+    fn test_parse_pixels_low_bit_depth() {
+        let ihdr = gray_ihdr(3, 1, 2, InterlaceMethod::None);
+
+        let pixels = parse_pixels(&ihdr, None, &[0b0001_1011], 3);
+
+        assert!(matches!(
+            pixels[..],
+            [
+                Pixel::Grayscale(0),
+                Pixel::Grayscale(85),
+                Pixel::Grayscale(170)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_unpack_samples() {
+        assert_eq!(unpack_samples(&[0b1011_0001], 1, 4), vec![1, 0, 1, 1]);
+        assert_eq!(unpack_samples(&[0b1011_0001], 4, 2), vec![11, 1]);
+        assert_eq!(unpack_samples(&[0x12, 0x34, 0x56], 16, 1), vec![0x1234]);
+    }
+
+    #[test]
+    fn test_parse_scanlines_adam7() {
+        let ihdr = gray_ihdr(2, 2, 8, InterlaceMethod::Adam7);
+
+        let scanlines = parse_scanlines(&ihdr, None, &[0, 1, 0, 2, 0, 3, 4]).unwrap();
+
+        assert!(matches!(
+            scanlines[0].pixels[..],
+            [Pixel::Grayscale(1), Pixel::Grayscale(2)]
+        ));
+        assert!(matches!(
+            scanlines[1].pixels[..],
+            [Pixel::Grayscale(3), Pixel::Grayscale(4)]
+        ));
+    }
+
+    #[test]
+    fn test_convert_cgbi_samples() {
+        let mut data = vec![0x10, 0x20, 0x40, 0x80, 0x10, 0x20, 0x40, 0x00];
+
+        convert_cgbi_samples(&mut data, 4);
+
+        assert_eq!(data, vec![0x80, 0x40, 0x20, 0x80, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_convert_cgbi_pixels() {
+        let mut pixels = vec![
+            Pixel::TruecolorAlpha(0x10, 0x20, 0x40, 0x80),
+            Pixel::TruecolorAlpha(0x10, 0x20, 0x40, 0x00),
+            Pixel::Truecolor(0x01, 0x02, 0x03),
+        ];
+
+        convert_cgbi_pixels(&mut pixels);
+
+        assert!(matches!(
+            pixels[0],
+            Pixel::TruecolorAlpha(0x80, 0x40, 0x20, 0x80)
+        ));
+        assert!(matches!(pixels[1], Pixel::TruecolorAlpha(0, 0, 0, 0)));
+        assert!(matches!(pixels[2], Pixel::Truecolor(0x03, 0x02, 0x01)));
+    }
 }
@@ -0,0 +1,423 @@
+use crate::png::{is_limit_error, Chunk, ColorType, ParsedChunk, Png};
+use serde::Serialize;
+use std::fmt::Display;
+
+#[derive(Debug, Clone)]
+pub struct ValidateOptions {
+    /// Inflate and unfilter the image data, which finds corrupt IDAT streams but takes time
+    /// proportional to the image size
+    pub decode: bool,
+}
+
+impl Default for ValidateOptions {
+    fn default() -> Self {
+        Self { decode: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "Info"),
+            Severity::Warning => write!(f, "Warning"),
+            Severity::Error => write!(f, "Error"),
+        }
+    }
+}
+
+/// What a finding is about, stable across releases unlike the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Code {
+    InvalidCrc,
+    InvalidChunkType,
+    ReservedBitSet,
+    /// The chunk data does not match its type's layout
+    InvalidChunk,
+    /// Malformed or unusual text in a tEXt, zTXt or iTXt chunk
+    InvalidText,
+    PrivateCriticalChunk,
+    PrivateChunk,
+    UnknownCriticalChunk,
+    UnknownPrivateChunk,
+    UnknownPublicChunk,
+    MissingIhdr,
+    IhdrNotFirst,
+    MissingPlte,
+    /// PLTE in a grayscale image
+    PlteNotAllowed,
+    MissingIdat,
+    MissingIend,
+    InvalidImageData,
+    /// Decoding was stopped by the limits, the data may well be valid
+    LimitExceeded,
+    ExtraBytes,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub code: Code,
+    /// Index of the chunk the finding is about, `None` for the file as a whole
+    pub chunk: Option<usize>,
+    /// File offset of the problem, if the file was read from a file or buffer
+    pub offset: Option<u64>,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Everything `Png::validate` found, in file order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Whether there are no errors, warnings and infos are allowed.
+    pub fn is_valid(&self) -> bool {
+        self.max_severity() < Some(Severity::Error)
+    }
+
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+
+    /// Findings about chunk `index`.
+    pub fn for_chunk(&self, index: usize) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(move |finding| finding.chunk == Some(index))
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        code: Code,
+        chunk: Option<usize>,
+        offset: Option<u64>,
+        message: String,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            code,
+            chunk,
+            offset,
+            message,
+        });
+    }
+}
+
+fn location(i: usize, chunk: &Chunk) -> String {
+    match chunk.offset {
+        Some(offset) => format!("chunk {} ({}) at {:#x}", i, chunk.chunk_type, offset),
+        None => format!("chunk {} ({})", i, chunk.chunk_type),
+    }
+}
+
+pub(crate) fn validate(png: &Png, options: &ValidateOptions) -> Report {
+    let mut report = Report::default();
+    for (i, (chunk, parsed)) in png.chunks.iter().zip(png.parsed_chunks()).enumerate() {
+        validate_chunk(i, chunk, parsed, &mut report);
+    }
+    validate_structure(png, options, &mut report);
+
+    if let Some(extra_bytes) = &png.extra_bytes {
+        let offset = png.extra_bytes_offset();
+        let message = match offset {
+            Some(offset) => format!(
+                "{} extra bytes after IEND at {:#x}",
+                extra_bytes.len(),
+                offset
+            ),
+            None => format!("{} extra bytes after IEND", extra_bytes.len()),
+        };
+        report.push(Severity::Warning, Code::ExtraBytes, None, offset, message);
+    }
+
+    report
+}
+
+fn validate_chunk(i: usize, chunk: &Chunk, parsed: ParsedChunk, report: &mut Report) {
+    let location = location(i, chunk);
+    let chunk_type = &chunk.chunk_type;
+    let mut push = |severity, code, offset, message| {
+        report.push(severity, code, Some(i), offset, message);
+    };
+
+    if !chunk.validate_checksum() {
+        let crc_offset = chunk.crc_offset();
+        let message = match crc_offset {
+            Some(crc_offset) => format!(
+                "{} has an invalid CRC {:#010x} at {:#x}, expected {:#010x}",
+                location,
+                chunk.crc,
+                crc_offset,
+                chunk.calculate_checksum()
+            ),
+            None => format!(
+                "{} has an invalid CRC {:#010x}, expected {:#010x}",
+                location,
+                chunk.crc,
+                chunk.calculate_checksum()
+            ),
+        };
+        push(Severity::Error, Code::InvalidCrc, crc_offset, message);
+    }
+    if !chunk_type.is_valid() {
+        let message = format!("{} has an invalid type", location);
+        push(
+            Severity::Error,
+            Code::InvalidChunkType,
+            chunk.offset,
+            message,
+        );
+        return;
+    }
+    if !chunk_type.is_reserved_bit_valid() {
+        let message = format!("{} has the reserved bit set", location);
+        push(Severity::Error, Code::ReservedBitSet, chunk.offset, message);
+    }
+
+    let text = matches!(chunk_type.as_str(), "tEXt" | "zTXt" | "iTXt");
+    let warnings = match parsed {
+        ParsedChunk::Text(text) => text.warnings,
+        ParsedChunk::Ztxt(ztxt) => ztxt.warnings,
+        ParsedChunk::Itxt(itxt) => itxt.warnings,
+        ParsedChunk::Invalid(message) if !text => {
+            let message = format!("{}: {}", location, message);
+            push(Severity::Error, Code::InvalidChunk, chunk.offset, message);
+            vec![]
+        }
+        ParsedChunk::Invalid(message) => vec![message],
+        _ => vec![],
+    };
+    for warning in warnings {
+        let message = format!("{}: {}", location, warning);
+        push(Severity::Warning, Code::InvalidText, chunk.offset, message);
+    }
+
+    if chunk_type.is_known() {
+        return;
+    }
+    let (severity, code, message) = match (chunk_type.vendor(), chunk_type.is_critical()) {
+        (Some(vendor), true) => (
+            Severity::Warning,
+            Code::PrivateCriticalChunk,
+            format!(
+                "{} is a private critical chunk from {}, standard decoders will reject it",
+                location, vendor
+            ),
+        ),
+        (Some(vendor), false) => (
+            Severity::Info,
+            Code::PrivateChunk,
+            format!("{} is a private chunk from {}", location, vendor),
+        ),
+        (None, true) => (
+            Severity::Error,
+            Code::UnknownCriticalChunk,
+            format!("{} is an unknown critical chunk", location),
+        ),
+        (None, false) if !chunk_type.is_public() => (
+            Severity::Info,
+            Code::UnknownPrivateChunk,
+            format!("{} is an unknown private ancillary chunk", location),
+        ),
+        (None, false) => (
+            Severity::Info,
+            Code::UnknownPublicChunk,
+            format!("{} is an unknown public ancillary chunk", location),
+        ),
+    };
+    push(severity, code, chunk.offset, message);
+}
+
+/// Why images of `color_type` cannot have a PLTE chunk, `None` when they can.
+pub(crate) fn plte_not_allowed(color_type: &ColorType) -> Option<String> {
+    match color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => {
+            Some(String::from("PLTE is not allowed in grayscale images"))
+        }
+        _ => None,
+    }
+}
+
+/// Checks the chunks a decoder needs are there, and optionally that the image data decodes.
+fn validate_structure(png: &Png, options: &ValidateOptions, report: &mut Report) {
+    let position = |chunk_type: &str| png.chunks.iter().position(|c| c.chunk_type == chunk_type);
+
+    // CgBI files start with their CgBI chunk
+    let first = usize::from(png.is_cgbi());
+    match position("IHDR") {
+        None => {
+            let message = "IHDR chunk not found".to_string();
+            report.push(Severity::Error, Code::MissingIhdr, None, None, message);
+        }
+        Some(i) if i != first => {
+            let message = format!("{} must be the first chunk", location(i, &png.chunks[i]));
+            let offset = png.chunks[i].offset;
+            report.push(
+                Severity::Error,
+                Code::IhdrNotFirst,
+                Some(i),
+                offset,
+                message,
+            );
+        }
+        Some(_) => {}
+    }
+
+    let color_type = png.color_type();
+    match (color_type, position("PLTE")) {
+        (Some(ColorType::Indexed), None) => {
+            let message = "PLTE chunk not found, indexed images need one".to_string();
+            report.push(Severity::Error, Code::MissingPlte, None, None, message);
+        }
+        (Some(color_type), Some(i)) => {
+            if let Some(problem) = plte_not_allowed(&color_type) {
+                let message = format!("{}: {}", location(i, &png.chunks[i]), problem);
+                let offset = png.chunks[i].offset;
+                report.push(
+                    Severity::Error,
+                    Code::PlteNotAllowed,
+                    Some(i),
+                    offset,
+                    message,
+                );
+            }
+        }
+        _ => {}
+    }
+    let has_idat = position("IDAT").is_some();
+    if !has_idat {
+        let message = "IDAT chunk not found".to_string();
+        report.push(Severity::Error, Code::MissingIdat, None, None, message);
+    }
+    if position("IEND").is_none() {
+        let message = "IEND chunk not found".to_string();
+        report.push(Severity::Error, Code::MissingIend, None, None, message);
+    }
+
+    if !options.decode || !has_idat || png.ihdr().is_none() {
+        return;
+    }
+    let result = png.rows().and_then(|mut rows| {
+        while rows.next_row()?.is_some() {}
+        Ok(())
+    });
+    if let Err(e) = result {
+        let (severity, code) = if is_limit_error(&e) {
+            (Severity::Warning, Code::LimitExceeded)
+        } else {
+            (Severity::Error, Code::InvalidImageData)
+        };
+        let message = format!("Image data could not be decoded: {}", e);
+        report.push(severity, code, None, None, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, gray_2x2, idat, reparse};
+
+    /// A 2x2 grayscale image, read back from bytes so chunks have offsets.
+    fn gray(extra: Vec<Chunk>) -> Png {
+        let mut chunks = gray_2x2();
+        chunks.extend(extra);
+        chunks.push(chunk(b"IEND", &[]));
+        reparse(&Png::new(chunks, None))
+    }
+
+    fn codes(report: &Report) -> Vec<Code> {
+        report.findings.iter().map(|finding| finding.code).collect()
+    }
+
+    #[test]
+    fn test_validate_valid_file() {
+        let report = gray(vec![]).validate(&ValidateOptions::default());
+
+        assert!(report.findings.is_empty());
+        assert!(report.is_valid());
+    }
+
+    #[test]
+    fn test_validate_chunks() {
+        let mut png = gray(vec![chunk(b"gAMA", &[0, 1]), chunk(b"prVt", &[1])]);
+        png.chunks[0].crc ^= 1;
+        png.extra_bytes = Some(b"trailing".to_vec());
+
+        let report = png.validate(&ValidateOptions::default());
+
+        assert_eq!(
+            codes(&report),
+            vec![
+                Code::InvalidCrc,
+                Code::InvalidChunk,
+                Code::UnknownPrivateChunk,
+                Code::ExtraBytes
+            ]
+        );
+        let crc = &report.findings[0];
+        assert_eq!(
+            (crc.severity, crc.chunk, crc.offset),
+            (Severity::Error, Some(0), Some(0x1d))
+        );
+        assert_eq!(report.for_chunk(2).count(), 1);
+        assert_eq!(report.max_severity(), Some(Severity::Error));
+        assert_eq!(
+            report.findings[1].to_string(),
+            format!(
+                "Error: chunk 2 (gAMA) at {:#x}: gAMA chunk must be 4 bytes, found 2",
+                png.chunks[2].offset.unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_validate_structure() {
+        let mut png = gray(vec![]);
+        png.chunks.swap(0, 1);
+        png.chunks.pop();
+
+        let report = png.validate(&ValidateOptions::default());
+
+        assert_eq!(codes(&report), vec![Code::IhdrNotFirst, Code::MissingIend]);
+        assert_eq!(report.findings[0].chunk, Some(1));
+    }
+
+    #[test]
+    fn test_validate_plte_in_grayscale() {
+        let report = gray(vec![chunk(b"PLTE", &[0, 0, 0])]).validate(&ValidateOptions::default());
+
+        assert_eq!(codes(&report), vec![Code::PlteNotAllowed]);
+        assert_eq!(report.findings[0].chunk, Some(2));
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn test_validate_image_data() {
+        let mut png = gray(vec![]);
+        png.chunks[1] = idat(&[0, 1, 2]);
+
+        let report = png.validate(&ValidateOptions::default());
+        let skipped = png.validate(&ValidateOptions { decode: false });
+
+        assert_eq!(codes(&report), vec![Code::InvalidImageData]);
+        assert!(!report.is_valid());
+        assert!(skipped.findings.is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::png::filter::choose_filter;
+use crate::png::{Chunk, PixelBuffer, Png, HEADER};
+use std::io::Write;
+
+pub fn write_png<W: Write>(png: &Png, writer: &mut W) -> Result<(), std::io::Error> {
+    writer.write_all(&HEADER)?;
+
+    for chunk in &png.chunks {
+        write_chunk(chunk, writer)?;
+    }
+
+    if let Some(extra_bytes) = &png.extra_bytes {
+        writer.write_all(extra_bytes)?;
+    }
+
+    Ok(())
+}
+
+pub fn write_chunk<W: Write>(chunk: &Chunk, writer: &mut W) -> Result<(), std::io::Error> {
+    writer.write_all(&chunk.length.to_be_bytes())?;
+    writer.write_all(&chunk.chunk_type.bytes())?;
+    if let Some(data) = &chunk.data {
+        writer.write_all(data)?;
+    }
+    writer.write_all(&chunk.crc.to_be_bytes())
+}
+
+/// Serializes a buffer into non-interlaced image data at the buffer's bit depth, filtering each
+/// row with the filter picked by `choose_filter`.
+pub fn encode_buffer(buffer: &PixelBuffer) -> Vec<u8> {
+    let mut data = vec![];
+    let bytes_per_pixel = (buffer.channels as usize * buffer.bit_depth as usize).div_ceil(8);
+    let mut previous = vec![];
+
+    for row in buffer.rows() {
+        let row = pack_row(row, buffer.bit_depth);
+        previous.resize(row.len(), 0);
+        let (filter, filtered) = choose_filter(&previous, &row, bytes_per_pixel);
+        data.push(filter as u8);
+        data.extend(filtered);
+        previous = row;
+    }
+
+    data
+}
+
+/// Packs a buffer row into the bytes PNG stores, several samples to a byte below 8 bits.
+pub fn pack_row(row: &[u8], bit_depth: u8) -> Vec<u8> {
+    if bit_depth >= 8 {
+        return row.to_vec();
+    }
+
+    let per_byte = 8 / bit_depth as usize;
+    row.chunks(per_byte)
+        .map(|samples| {
+            samples.iter().enumerate().fold(0, |byte, (i, &sample)| {
+                byte | sample << (8 - bit_depth as usize * (i + 1))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::ChunkType;
+
+    #[test]
+    fn test_write_chunk() {
+        let chunk = Chunk::new(0, ChunkType::from(*b"IEND"), None, 2923585666);
+        let mut output = vec![];
+
+        write_chunk(&chunk, &mut output).unwrap();
+
+        assert_eq!(
+            output,
+            vec![0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn test_encode_buffer_packs_samples() {
+        let buffer = PixelBuffer::from_data(3, 2, 1, 2, vec![1, 2, 3, 3, 0, 1]);
+
+        assert_eq!(encode_buffer(&buffer), vec![0, 0b0110_1100, 0, 0b1100_0100]);
+    }
+
+    #[test]
+    fn test_encode_buffer_filters_rows() {
+        let buffer = PixelBuffer::from_data(4, 2, 1, 8, vec![10, 20, 30, 40, 10, 20, 30, 40]);
+
+        assert_eq!(
+            encode_buffer(&buffer),
+            vec![1, 10, 10, 10, 10, 2, 0, 0, 0, 0]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 enum ByteMatch {
     Same(u8),
-    Diff { left: Option<u8>, right: Option<u8> },
+    Diff { left: Option<u8> },
 }
 
 fn check_sameness(a: &[u8], b: &[u8]) -> Vec<ByteMatch> {
@@ -11,7 +11,7 @@ fn check_sameness(a: &[u8], b: &[u8]) -> Vec<ByteMatch> {
         let right = if b.len() <= i { None } else { Some(b[i]) };
         match (left, right) {
             (Some(left), Some(right)) if left == right => result.push(ByteMatch::Same(left)),
-            _ => result.push(ByteMatch::Diff { left, right }),
+            _ => result.push(ByteMatch::Diff { left }),
         }
     }
 
@@ -48,10 +48,7 @@ pub fn soft_assert(title: &str, actual: &[u8], expected: &[u8]) {
         .iter()
         .map(|b| match b {
             ByteMatch::Same(b) => hex_colored(b, COLOR_GREEN),
-            ByteMatch::Diff {
-                left: Some(left),
-                right: _,
-            } => hex_colored(left, COLOR_RED),
+            ByteMatch::Diff { left: Some(left) } => hex_colored(left, COLOR_RED),
             _ => format!("{}__{}", COLOR_RED, COLOR_RESET),
         })
         .collect();
@@ -0,0 +1,346 @@
+//! Property tests over structurally valid random PNGs, and over damaged and arbitrary input
+//! that the parser must reject without panicking.
+
+use crate::fixtures::{chunk, idat, ihdr, reparse, to_bytes};
+use crate::optimize::{canonical_pixels, optimize, OptimizeOptions};
+use crate::parse_bytes_with_limits;
+use crate::png::compression::{compress, decompress};
+use crate::png::scanline::parse_scanlines;
+use crate::png::writer::pack_row;
+use crate::png::{Chunk, Limits, ParsedChunk, PixelBuffer, Png, ADAM7};
+use proptest::prelude::*;
+
+/// Color types with the bit depths they allow.
+const FORMATS: [(u8, &[u8]); 5] = [
+    (0, &[1, 2, 4, 8, 16]),
+    (2, &[8, 16]),
+    (3, &[1, 2, 4, 8]),
+    (4, &[8, 16]),
+    (6, &[8, 16]),
+];
+
+/// Every chunk type `Chunk::parse` handles, plus the unknown `prVt` on purpose so that unknown
+/// chunks are covered too.
+const CHUNK_TYPES: [&[u8; 4]; 19] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"pHYs", b"sRGB", b"gAMA", b"bKGD", b"sBIT",
+    b"iTXt", b"tEXt", b"zTXt", b"iCCP", b"hIST", b"cHRM", b"acTL", b"fcTL", b"prVt",
+];
+
+/// A generated image and the samples it was built from.
+#[derive(Debug, Clone)]
+pub struct GeneratedPng {
+    pub png: Png,
+    pub buffer: PixelBuffer,
+}
+
+/// Serializes `buffer` as unfiltered image data, split into Adam7 passes when `interlaced`.
+fn encode(buffer: &PixelBuffer, interlaced: bool) -> Vec<u8> {
+    let passes: &[(u32, u32, u32, u32)] = if interlaced { &ADAM7 } else { &[(0, 0, 1, 1)] };
+
+    let mut data = vec![];
+    for &(x0, y0, dx, dy) in passes {
+        let columns: Vec<u32> = (x0..buffer.width).step_by(dx as usize).collect();
+        if columns.is_empty() {
+            continue;
+        }
+        for y in (y0..buffer.height).step_by(dy as usize) {
+            let row: Vec<u8> = columns
+                .iter()
+                .flat_map(|&x| buffer.pixel(x, y).to_vec())
+                .collect();
+            data.push(0);
+            data.extend(pack_row(&row, buffer.bit_depth));
+        }
+    }
+    data
+}
+
+/// Random images of every color type and bit depth, interlaced or not, with the image data split
+/// over several IDAT chunks and optional tRNS, bKGD, sBIT, cHRM and text chunks and trailing
+/// bytes.
+pub fn arbitrary_png() -> impl Strategy<Value = GeneratedPng> {
+    let format = (0..FORMATS.len()).prop_flat_map(|i| {
+        let (color_type, depths) = FORMATS[i];
+        (Just(color_type), prop::sample::select(depths))
+    });
+
+    (format, 1..=17u32, 1..=17u32, any::<bool>(), 1..=256usize)
+        .prop_flat_map(
+            |((color_type, bit_depth), width, height, interlaced, colors)| {
+                let channels = match color_type {
+                    0 | 3 => 1,
+                    4 => 2,
+                    2 => 3,
+                    _ => 4,
+                };
+                let max = if color_type == 3 {
+                    colors.min(1 << bit_depth) as u32 - 1
+                } else {
+                    (1u32 << bit_depth) - 1
+                };
+                let samples = width as usize * height as usize * channels as usize;
+                // Indexed images have 8-bit palette samples, and sBIT covers the palette's RGB
+                let (sbit_depth, sbit_channels) = match color_type {
+                    3 => (8, 3),
+                    _ => (bit_depth, channels as usize),
+                };
+                (
+                    Just((color_type, bit_depth, width, height, interlaced, channels)),
+                    prop::collection::vec(0..=max as u16, samples),
+                    prop::collection::vec(any::<u8>(), (max as usize + 1) * 3),
+                    1..=3usize,
+                    prop::option::of("[ -~]{0,20}"),
+                    prop::option::of(prop::collection::vec(any::<u8>(), 1..16)),
+                    any::<[bool; 4]>(),
+                    prop::collection::vec(0..=max as u16, 3),
+                    prop::collection::vec(any::<u8>(), 1..=max as usize + 1),
+                    prop::collection::vec(1..=sbit_depth, sbit_channels),
+                    prop::collection::vec(any::<u8>(), 32),
+                )
+            },
+        )
+        .prop_map(
+            |(
+                (color_type, bit_depth, width, height, interlaced, channels),
+                samples,
+                palette,
+                idat_count,
+                text,
+                extra_bytes,
+                [has_trns, has_bkgd, has_sbit, has_chrm],
+                color,
+                alpha,
+                sbit,
+                chrm,
+            )| {
+                let mut buffer = PixelBuffer::new(width, height, channels, bit_depth);
+                for (i, &sample) in samples.iter().enumerate() {
+                    let pixel = i / channels as usize;
+                    let (x, y) = (pixel as u32 % width, pixel as u32 / width);
+                    buffer.set_sample(x, y, (i % channels as usize) as u8, sample);
+                }
+
+                let mut ihdr = vec![];
+                ihdr.extend(width.to_be_bytes());
+                ihdr.extend(height.to_be_bytes());
+                ihdr.extend([bit_depth, color_type, 0, 0, interlaced as u8]);
+                let mut chunks = vec![chunk(b"IHDR", &ihdr)];
+                if has_chrm {
+                    chunks.push(chunk(b"cHRM", &chrm));
+                }
+                if has_sbit {
+                    chunks.push(chunk(b"sBIT", &sbit));
+                }
+                if color_type == 3 {
+                    chunks.push(chunk(b"PLTE", &palette));
+                }
+                // A gray or RGB color in the image's sample depth, or a palette index
+                let color_bytes = |count: usize| -> Vec<u8> {
+                    color[..count]
+                        .iter()
+                        .flat_map(|c| c.to_be_bytes())
+                        .collect()
+                };
+                if has_bkgd {
+                    let data = match color_type {
+                        0 | 4 => color_bytes(1),
+                        3 => vec![color[0] as u8],
+                        _ => color_bytes(3),
+                    };
+                    chunks.push(chunk(b"bKGD", &data));
+                }
+                if has_trns {
+                    match color_type {
+                        0 => chunks.push(chunk(b"tRNS", &color_bytes(1))),
+                        2 => chunks.push(chunk(b"tRNS", &color_bytes(3))),
+                        3 => chunks.push(chunk(b"tRNS", &alpha)),
+                        _ => {}
+                    }
+                }
+                if let Some(text) = text {
+                    chunks.push(chunk(b"tEXt", format!("Comment\0{}", text).as_bytes()));
+                }
+                let idat = compress(&encode(&buffer, interlaced)).unwrap();
+                let size = idat.len().div_ceil(idat_count);
+                chunks.extend(idat.chunks(size).map(|data| chunk(b"IDAT", data)));
+                chunks.push(chunk(b"IEND", &[]));
+
+                GeneratedPng {
+                    png: Png::new(chunks, extra_bytes),
+                    buffer,
+                }
+            },
+        )
+}
+
+/// 8-bit grayscale and truecolor images, with or without alpha, with every sample a multiple of
+/// 85, so that they look like candidates for 2-bit grayscale.
+fn few_level_png() -> impl Strategy<Value = Png> {
+    let format = prop::sample::select(&[(0u8, 1u8), (2, 3), (4, 2), (6, 4)][..]);
+    (format, 1..=8u32, 1..=8u32)
+        .prop_flat_map(|((color_type, channels), width, height)| {
+            let samples = width as usize * height as usize * channels as usize;
+            (
+                Just((color_type, channels, width, height)),
+                prop::collection::vec(0..4u8, samples),
+            )
+        })
+        .prop_map(|((color_type, channels, width, height), levels)| {
+            let data = levels.iter().map(|level| level * 85).collect();
+            let buffer = PixelBuffer::from_data(width, height, channels, 8, data);
+
+            Png::new(
+                vec![
+                    ihdr(width, height, 8, color_type),
+                    idat(&encode(&buffer, false)),
+                    chunk(b"IEND", &[]),
+                ],
+                None,
+            )
+        })
+}
+
+/// Small enough that decoding hostile dimensions stays quick.
+fn limits() -> Limits {
+    Limits {
+        max_decompressed_size: 1 << 20,
+        max_text_size: 1 << 16,
+        ..Limits::default()
+    }
+}
+
+/// Runs everything that decodes a parsed file, which may fail but must not panic.
+fn decode_everything(png: &Png) {
+    for chunk in &png.chunks {
+        let _ = chunk.parse().to_string();
+        let _ = chunk.data_fields();
+    }
+    let _ = png.get_raw_buffer();
+    let _ = png.get_buffer();
+    let _ = png.get_scanlines();
+    let _ = png.get_pixels();
+    let _ = png.trns();
+    let _ = png.bkgd();
+    let _ = png.chrm();
+    let _ = png.frame_controls();
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_roundtrip(generated in arbitrary_png()) {
+        let parsed = reparse(&generated.png);
+
+        prop_assert_eq!(parsed.chunks.len(), generated.png.chunks.len());
+        for (parsed, original) in parsed.chunks.iter().zip(&generated.png.chunks) {
+            prop_assert_eq!(parsed.chunk_type, original.chunk_type);
+            prop_assert_eq!(&parsed.data, &original.data);
+            prop_assert!(parsed.validate_checksum());
+        }
+        prop_assert_eq!(&parsed.extra_bytes, &generated.png.extra_bytes);
+        prop_assert_eq!(parsed.get_raw_buffer().unwrap(), generated.buffer);
+    }
+
+    #[test]
+    fn test_reencode_roundtrip(generated in arbitrary_png()) {
+        let reencoded = generated.png.with_image_data(&generated.buffer).unwrap();
+        let parsed = reparse(&reencoded);
+
+        prop_assert_eq!(parsed.get_raw_buffer().unwrap(), generated.buffer);
+    }
+
+    #[test]
+    fn test_optimize_is_lossless(generated in arbitrary_png()) {
+        let options = OptimizeOptions {
+            levels: vec![6],
+            ..OptimizeOptions::default()
+        };
+
+        let (optimized, report) = optimize(&generated.png, &options).unwrap();
+
+        prop_assert!(report.output_size <= report.input_size);
+        let parsed = reparse(&optimized);
+        prop_assert_eq!(
+            canonical_pixels(&parsed).unwrap(),
+            canonical_pixels(&generated.png).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_optimize_few_levels_is_lossless(png in few_level_png()) {
+        let options = OptimizeOptions {
+            levels: vec![6],
+            ..OptimizeOptions::default()
+        };
+
+        let (optimized, _) = optimize(&png, &options).unwrap();
+
+        prop_assert_eq!(canonical_pixels(&optimized).unwrap(), canonical_pixels(&png).unwrap());
+    }
+
+    #[test]
+    fn test_damaged_files_do_not_panic(
+        generated in arbitrary_png(),
+        damage in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+        fix_checksums in any::<bool>(),
+    ) {
+        let mut png = generated.png;
+        // Damaged chunk data with fixed checksums gets past the reader to the chunk parsers and
+        // decoders, raw damage tests the reader itself
+        if fix_checksums {
+            for (index, value) in &damage {
+                let chunk = index.get_mut(&mut png.chunks);
+                let mut data = chunk.data.clone().unwrap_or_default();
+                match data.len() {
+                    0 => data.push(*value),
+                    len => data[index.index(len)] = *value,
+                }
+                *chunk = Chunk::from_data(chunk.chunk_type, data);
+            }
+        }
+        let mut bytes = to_bytes(&png);
+        if !fix_checksums {
+            for (index, value) in &damage {
+                let i = index.index(bytes.len());
+                bytes[i] = *value;
+            }
+        }
+
+        if let Ok(png) = parse_bytes_with_limits(&bytes, limits()) {
+            decode_everything(&png);
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_bytes_do_not_panic(mut data in prop::collection::vec(any::<u8>(), 0..256)) {
+        if let Ok(png) = parse_bytes_with_limits(&data, limits()) {
+            decode_everything(&png);
+        }
+        data.splice(0..0, crate::png::HEADER);
+        if let Ok(png) = parse_bytes_with_limits(&data, limits()) {
+            decode_everything(&png);
+        }
+    }
+
+    #[test]
+    fn test_chunk_parse_does_not_panic(
+        chunk_type in prop::sample::select(&CHUNK_TYPES[..]),
+        data in prop::collection::vec(any::<u8>(), 0..64),
+    ) {
+        let chunk = chunk(chunk_type, &data);
+
+        let parsed = chunk.parse();
+
+        let _ = parsed.to_string();
+        let _ = chunk.data_fields();
+        if let ParsedChunk::IHDR(ihdr) = parsed {
+            let _ = parse_scanlines(&ihdr, None, &[]);
+        }
+    }
+
+    #[test]
+    fn test_decompress_does_not_panic(data in prop::collection::vec(any::<u8>(), 0..256)) {
+        let _ = decompress(&data, 1 << 16);
+    }
+}
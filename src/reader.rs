@@ -0,0 +1,230 @@
+use crate::png::{limit_error, Chunk, ChunkType, Limits, HEADER};
+use std::io::{Error, ErrorKind, Read};
+
+/// Reads chunks one at a time from any reader, without buffering the whole file.
+///
+/// The signature is checked before the first chunk is returned. Iteration stops after `IEND`
+/// or the first error; bytes following `IEND` can then be read with `read_trailing_bytes`.
+pub struct ChunkReader<R: Read> {
+    reader: R,
+    limits: Limits,
+    offset: u64,
+    state: State,
+}
+
+#[derive(PartialEq)]
+enum State {
+    Signature,
+    Chunks,
+    Done,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_limits(reader, Limits::default())
+    }
+
+    pub fn with_limits(reader: R, limits: Limits) -> Self {
+        Self {
+            reader,
+            limits,
+            offset: 0,
+            state: State::Signature,
+        }
+    }
+
+    /// Number of bytes consumed from the reader so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether the `IEND` chunk (or an error) has been reached.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Reads everything left in the reader, normally the bytes following `IEND`, up to the
+    /// trailing size limit.
+    pub fn read_trailing_bytes(&mut self) -> Result<Vec<u8>, Error> {
+        let limit = self.limits.max_trailing_size;
+        let mut trailing_bytes = vec![];
+        (&mut self.reader)
+            .take((limit as u64).saturating_add(1))
+            .read_to_end(&mut trailing_bytes)?;
+        self.offset += trailing_bytes.len() as u64;
+
+        if trailing_bytes.len() > limit {
+            return Err(limit_error(format!(
+                "Data after IEND exceeds the limit of {} bytes",
+                limit
+            )));
+        }
+
+        Ok(trailing_bytes)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_signature(&mut self) -> Result<(), Error> {
+        let mut signature = [0; 8];
+        self.reader.read_exact(&mut signature)?;
+        self.offset += 8;
+
+        if signature != HEADER {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid PNG signature"));
+        }
+
+        Ok(())
+    }
+
+    fn read_chunk(&mut self) -> Result<Chunk, Error> {
+        let offset = self.offset;
+
+        let mut header = [0; 8];
+        self.reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk_type = ChunkType::new([header[4], header[5], header[6], header[7]]);
+        self.limits.check_chunk_length(length)?;
+
+        // Grow the buffer as data arrives instead of trusting the length field up front
+        let mut data = vec![];
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)?;
+        if data.len() < length as usize {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!(
+                    "{} chunk at {:#x} is truncated: expected {} bytes of data, found {}",
+                    chunk_type,
+                    offset,
+                    length,
+                    data.len()
+                ),
+            ));
+        }
+
+        let mut crc = [0; 4];
+        self.reader.read_exact(&mut crc)?;
+
+        let data = if length == 0 { None } else { Some(data) };
+        let chunk =
+            Chunk::new(length, chunk_type, data, u32::from_be_bytes(crc)).with_offset(offset);
+        self.offset = chunk.end_offset().unwrap();
+
+        Ok(chunk)
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<Chunk, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.state == State::Signature {
+            if let Err(e) = self.read_signature() {
+                self.state = State::Done;
+                return Some(Err(e));
+            }
+            self.state = State::Chunks;
+        }
+
+        if self.state == State::Done {
+            return None;
+        }
+
+        let result = self.read_chunk();
+        match &result {
+            Ok(chunk) if chunk.chunk_type == "IEND" => self.state = State::Done,
+            Err(_) => self.state = State::Done,
+            _ => {}
+        }
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iend() -> Vec<u8> {
+        vec![0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let mut data = HEADER.to_vec();
+        data.extend([0, 0, 0, 4, b'a', b'b', b'c', b'd', 1, 2, 3, 4]);
+        data.extend(283159080u32.to_be_bytes());
+        data.extend(iend());
+        data.extend(b"trailing");
+
+        let mut reader = ChunkReader::new(&data[..]);
+        let chunks = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].chunk_type, "abcd");
+        assert_eq!(chunks[0].offset, Some(8));
+        assert!(chunks[0].validate_checksum());
+        assert_eq!(chunks[1].offset, Some(24));
+        assert!(reader.is_done());
+        assert_eq!(reader.read_trailing_bytes().unwrap(), b"trailing");
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let data = [0; 8];
+        let mut reader = ChunkReader::new(&data[..]);
+
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().to_string(),
+            "Invalid PNG signature"
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let mut data = HEADER.to_vec();
+        data.extend([0, 0, 0, 4, b'a', b'b', b'c', b'd', 1, 2]);
+
+        let mut reader = ChunkReader::new(&data[..]);
+        let error = reader.next().unwrap().unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_chunk_length_limit() {
+        let mut data = HEADER.to_vec();
+        data.extend([0xff, 0xff, 0xff, 0xff, b'I', b'D', b'A', b'T']);
+
+        let mut reader = ChunkReader::new(&data[..]);
+        let error = reader.next().unwrap().unwrap_err();
+
+        assert!(crate::png::is_limit_error(&error));
+    }
+
+    #[test]
+    fn test_trailing_size_limit() {
+        let mut data = HEADER.to_vec();
+        data.extend(iend());
+        data.extend(b"trailing");
+        let limits = |max_trailing_size| Limits {
+            max_trailing_size,
+            ..Limits::default()
+        };
+
+        let mut reader = ChunkReader::with_limits(&data[..], limits(8));
+        reader.by_ref().for_each(drop);
+        assert_eq!(reader.read_trailing_bytes().unwrap(), b"trailing");
+
+        let mut reader = ChunkReader::with_limits(&data[..], limits(7));
+        reader.by_ref().for_each(drop);
+        let error = reader.read_trailing_bytes().unwrap_err();
+        assert!(crate::png::is_limit_error(&error));
+    }
+}
@@ -0,0 +1,409 @@
+use crate::color::ColorSpace;
+use crate::png::compression::zlib_stream_len;
+use crate::png::writer::write_png;
+use crate::png::{Chunk, ChunkType, ColorType, Png};
+use std::error::Error;
+
+/// Chunks kept by default: everything needed to decode and display the image, animation
+/// included, and nothing that describes where it came from.
+pub const DEFAULT_ALLOWLIST: &[&str] = &[
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "gAMA", "cHRM", "sRGB", "sBIT", "bKGD", "pHYs", "acTL",
+    "fcTL", "fdAT",
+];
+
+#[derive(Debug, Clone)]
+pub struct SanitizeOptions {
+    /// Chunk types to keep, everything else is removed
+    pub allow: Vec<String>,
+    /// Convert the pixels of images with an iCCP chunk to sRGB and tag them with an sRGB chunk,
+    /// instead of only dropping the profile
+    pub iccp_to_srgb: bool,
+}
+
+impl Default for SanitizeOptions {
+    fn default() -> Self {
+        Self {
+            allow: DEFAULT_ALLOWLIST.iter().map(|s| s.to_string()).collect(),
+            iccp_to_srgb: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Removal {
+    /// Position in the input, or in the converted file when it was uncrushed or converted to sRGB
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    /// Bytes removed, all of the chunk's data unless only its end was cut off
+    pub length: u32,
+    pub reason: &'static str,
+}
+
+/// What `sanitize` removed or changed, also what a dry run would do.
+#[derive(Debug, Default)]
+pub struct SanitizeReport {
+    pub removed: Vec<Removal>,
+    /// Number of bytes after IEND
+    pub extra_bytes: Option<usize>,
+    /// The CgBI file was converted to a standard PNG first
+    pub uncrushed: bool,
+    /// The iCCP profile was replaced by an sRGB chunk, with the pixels converted using gAMA and
+    /// cHRM
+    pub converted_to_srgb: Option<String>,
+}
+
+impl SanitizeReport {
+    pub fn is_clean(&self) -> bool {
+        self.removed.is_empty()
+            && self.extra_bytes.is_none()
+            && !self.uncrushed
+            && self.converted_to_srgb.is_none()
+    }
+}
+
+/// Removes every chunk that is not on the allowlist, data hidden in IDAT after the zlib stream and
+/// any trailing data. Apple CgBI files are converted to standard PNGs first, as their image data
+/// is unreadable without the CgBI chunk.
+pub fn sanitize(
+    png: &Png,
+    options: &SanitizeOptions,
+) -> Result<(Png, SanitizeReport), Box<dyn Error>> {
+    let mut report = SanitizeReport::default();
+    let uncrushed;
+    let png = if png.is_cgbi() {
+        report.uncrushed = true;
+        uncrushed = png.uncrush()?;
+        &uncrushed
+    } else {
+        png
+    };
+
+    let allowed = |chunk_type: &ChunkType| options.allow.iter().any(|a| a == chunk_type.as_str());
+    let convert = options.iccp_to_srgb
+        && !allowed(&ChunkType::from(*b"iCCP"))
+        && png.chunks.iter().any(|c| c.chunk_type.as_str() == "iCCP");
+
+    let source = if convert {
+        let color_space = ColorSpace::from_png(png);
+        report.converted_to_srgb = Some(color_space.source.clone());
+        convert_to_srgb(png, &color_space)?
+    } else {
+        png.clone()
+    };
+
+    // IDAT bytes left before the end of the zlib stream, if it can be found
+    let mut stream_left =
+        zlib_stream_len(&source.get_idat_data(), source.limits.max_decompressed_size).ok();
+    let mut chunks = vec![];
+    for (index, chunk) in source.chunks.iter().enumerate() {
+        let chunk_type = chunk.chunk_type;
+        let reason = match chunk_type.as_str() {
+            "IDAT" if allowed(&chunk_type) => {
+                let data = chunk.data.as_deref().unwrap_or_default();
+                let kept = stream_left.map_or(data.len(), |left| left.min(data.len()));
+                if let Some(left) = &mut stream_left {
+                    *left -= kept;
+                }
+                if kept == data.len() {
+                    chunks.push(chunk.clone());
+                    continue;
+                }
+                if kept > 0 {
+                    chunks.push(Chunk::from_data(chunk_type, data[..kept].to_vec()));
+                }
+                report.removed.push(Removal {
+                    index,
+                    chunk_type,
+                    length: (data.len() - kept) as u32,
+                    reason: "data after the zlib stream",
+                });
+                continue;
+            }
+            "gAMA" | "cHRM" if convert => "describes the colors before conversion to sRGB",
+            "sRGB" if convert => {
+                chunks.push(chunk.clone());
+                continue;
+            }
+            _ if allowed(&chunk_type) => {
+                chunks.push(chunk.clone());
+                continue;
+            }
+            _ => removal_reason(&chunk_type),
+        };
+
+        report.removed.push(Removal {
+            index,
+            chunk_type,
+            length: chunk.length,
+            reason,
+        });
+    }
+    report.extra_bytes = source.extra_bytes.as_ref().map(Vec::len);
+
+    Ok((
+        Png::new(chunks, None).with_limits(png.limits.clone()),
+        report,
+    ))
+}
+
+fn removal_reason(chunk_type: &ChunkType) -> &'static str {
+    match chunk_type.as_str() {
+        "tEXt" | "zTXt" | "iTXt" => "text metadata",
+        "eXIf" => "EXIF metadata",
+        "tIME" => "modification time",
+        "iCCP" => "ICC profile",
+        _ if !chunk_type.is_public() => "private chunk",
+        _ => "not on the allowlist",
+    }
+}
+
+/// Converts the pixels, or the palette, to sRGB using gAMA and cHRM, the fallback the PNG
+/// specification requires alongside iCCP, and puts an sRGB chunk where iCCP was.
+fn convert_to_srgb(png: &Png, color_space: &ColorSpace) -> Result<Png, Box<dyn Error>> {
+    if png.chunks.iter().any(|c| c.chunk_type.as_str() == "fdAT") {
+        return Err("Converting animated PNGs to sRGB is not supported".into());
+    }
+    let ihdr = png.ihdr().ok_or("IHDR chunk not found")?;
+    let max = ((1u32 << ihdr.bit_depth) - 1) as f64;
+    let convert = |samples: &mut [u16]| {
+        let rgb = match samples.len() {
+            1 => [samples[0]; 3],
+            _ => [samples[0], samples[1], samples[2]],
+        };
+        let srgb = color_space.to_srgb(rgb.map(|v| v as f64 / max));
+        let quantize = |value: f64| (value * max).round() as u16;
+        match samples.len() {
+            1 => samples[0] = quantize(srgb.iter().sum::<f64>() / 3.0),
+            _ => {
+                for (sample, value) in samples.iter_mut().zip(srgb) {
+                    *sample = quantize(value);
+                }
+            }
+        }
+    };
+
+    let mut converted = if matches!(ihdr.color_type, ColorType::Indexed) {
+        png.clone()
+    } else {
+        let mut buffer = png.get_raw_buffer()?;
+        let color_channels = if buffer.channels >= 3 { 3 } else { 1 };
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                let mut samples: Vec<u16> = (0..color_channels)
+                    .map(|c| buffer.sample(x, y, c))
+                    .collect();
+                convert(&mut samples);
+                for (c, sample) in samples.into_iter().enumerate() {
+                    buffer.set_sample(x, y, c as u8, sample);
+                }
+            }
+        }
+        png.with_image_data(&buffer)?
+    };
+
+    let mut chunks = vec![];
+    for chunk in &converted.chunks {
+        let data = chunk.data.as_deref().unwrap_or_default();
+        match chunk.chunk_type.as_str() {
+            "iCCP" => chunks.push(Chunk::from_data(ChunkType::from(*b"sRGB"), vec![0])),
+            "PLTE" => chunks.push(Chunk::from_data(
+                chunk.chunk_type,
+                data.chunks(3)
+                    .flat_map(|entry| {
+                        let mut samples: Vec<u16> = entry.iter().map(|&v| v as u16).collect();
+                        if samples.len() == 3 {
+                            convert(&mut samples);
+                        }
+                        samples.into_iter().map(|v| v as u8)
+                    })
+                    .collect(),
+            )),
+            // The transparent color has to follow the pixels
+            "tRNS" if !matches!(ihdr.color_type, ColorType::Indexed) => {
+                let mut samples: Vec<u16> = data
+                    .chunks_exact(2)
+                    .map(|v| u16::from_be_bytes([v[0], v[1]]))
+                    .collect();
+                if matches!(samples.len(), 1 | 3) {
+                    convert(&mut samples);
+                }
+                let data = samples.iter().flat_map(|v| v.to_be_bytes()).collect();
+                chunks.push(Chunk::from_data(chunk.chunk_type, data));
+            }
+            _ => chunks.push(chunk.clone()),
+        }
+    }
+    converted.chunks = chunks;
+
+    Ok(converted)
+}
+
+/// Writes the sanitized file, parses it again and checks that it is a clean PNG: valid CRCs, IHDR
+/// first, IEND last, no trailing data after IEND or the zlib stream, only allowed chunks and image
+/// data that decodes. Unless
+/// colors were converted, the pixels must be identical to the original's.
+pub fn verify(
+    original: &Png,
+    sanitized: &Png,
+    options: &SanitizeOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = vec![];
+    write_png(sanitized, &mut bytes)?;
+    let parsed = crate::parse_bytes_with_limits(&bytes, sanitized.limits.clone())
+        .map_err(|e| format!("Sanitized file does not parse: {}", e))?;
+
+    if let Some(chunk) = parsed.chunks.iter().find(|c| !c.validate_checksum()) {
+        return Err(format!("Sanitized {} chunk has an invalid CRC", chunk.chunk_type).into());
+    }
+    if parsed.chunks.first().map(|c| c.chunk_type.as_str()) != Some("IHDR") {
+        return Err("Sanitized file does not start with IHDR".into());
+    }
+    if parsed.chunks.last().map(|c| c.chunk_type.as_str()) != Some("IEND") {
+        return Err("Sanitized file does not end with IEND".into());
+    }
+    if parsed.extra_bytes.is_some() {
+        return Err("Sanitized file has data after IEND".into());
+    }
+    let idat_data = parsed.get_idat_data();
+    let stream_length = zlib_stream_len(&idat_data, parsed.limits.max_decompressed_size)
+        .map_err(|e| format!("Sanitized image data does not decode: {}", e))?;
+    if stream_length < idat_data.len() {
+        return Err("Sanitized file has data after the zlib stream".into());
+    }
+    if let Some(chunk) = parsed.chunks.iter().find(|c| {
+        let chunk_type = c.chunk_type.as_str();
+        let allowed = options.allow.iter().any(|a| a == chunk_type)
+            || (options.iccp_to_srgb && chunk_type == "sRGB");
+        !allowed
+    }) {
+        return Err(format!("Sanitized file still has a {} chunk", chunk.chunk_type).into());
+    }
+
+    let pixels = parsed
+        .get_raw_buffer()
+        .map_err(|e| format!("Sanitized image data does not decode: {}", e))?;
+    let converted = parsed.srgb().is_some() && original.srgb().is_none();
+    if !converted {
+        let original = if original.is_cgbi() {
+            original.uncrush()?.get_raw_buffer()?
+        } else {
+            original.get_raw_buffer()?
+        };
+        if pixels != original {
+            return Err("Sanitized image data differs from the original".into());
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embed::{embed, Technique};
+    use crate::fixtures::{chunk, idat, ihdr};
+    use crate::png::compression::compress;
+
+    /// A 1x1 RGB image with `extra` chunks after IHDR and trailing data.
+    fn png(extra: Vec<Chunk>) -> Png {
+        let mut chunks = vec![ihdr(1, 1, 8, 2)];
+        chunks.extend(extra);
+        chunks.push(idat(&[0, 10, 128, 250]));
+        chunks.push(chunk(b"IEND", &[]));
+        Png::new(chunks, Some(b"trailing".to_vec()))
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let original = png(vec![
+            chunk(b"tEXt", b"Author\0me"),
+            chunk(b"tIME", &[7, 232, 1, 1, 0, 0, 0]),
+            chunk(b"gAMA", &[0, 0, 177, 143]),
+            chunk(b"prVt", b"secret"),
+        ]);
+        let options = SanitizeOptions::default();
+
+        let (sanitized, report) = sanitize(&original, &options).unwrap();
+
+        let removed: Vec<_> = report
+            .removed
+            .iter()
+            .map(|r| (r.chunk_type.to_string(), r.reason))
+            .collect();
+        assert_eq!(
+            removed,
+            vec![
+                (String::from("tEXt"), "text metadata"),
+                (String::from("tIME"), "modification time"),
+                (String::from("prVt"), "private chunk"),
+            ]
+        );
+        assert_eq!(report.extra_bytes, Some(8));
+        assert_eq!(sanitized.chunks.len(), 4);
+        assert!(verify(&original, &sanitized, &options).is_ok());
+    }
+
+    #[test]
+    fn test_sanitize_allowlist() {
+        let original = png(vec![chunk(b"tEXt", b"Author\0me")]);
+        let mut options = SanitizeOptions::default();
+        options.allow.push(String::from("tEXt"));
+
+        let (_, report) = sanitize(&original, &options).unwrap();
+
+        assert!(report.removed.is_empty());
+    }
+
+    #[test]
+    fn test_sanitize_after_zlib_stream() {
+        let original = embed(&png(vec![]), b"secret", &Technique::AfterZlib).unwrap();
+        let options = SanitizeOptions::default();
+
+        let (sanitized, report) = sanitize(&original, &options).unwrap();
+
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.removed[0].chunk_type, "IDAT");
+        assert_eq!(report.removed[0].length, 6);
+        assert_eq!(report.removed[0].reason, "data after the zlib stream");
+        assert!(verify(&original, &sanitized, &options).is_ok());
+
+        let mut unsanitized = original.clone();
+        unsanitized.extra_bytes = None;
+        assert_eq!(
+            verify(&original, &unsanitized, &options)
+                .unwrap_err()
+                .to_string(),
+            "Sanitized file has data after the zlib stream"
+        );
+    }
+
+    #[test]
+    fn test_iccp_to_srgb() {
+        let mut profile = b"Profile\0\0".to_vec();
+        profile.extend(compress(b"not a real profile").unwrap());
+        let original = png(vec![
+            chunk(b"iCCP", &profile),
+            chunk(b"gAMA", &[0, 0, 177, 143]),
+        ]);
+        let options = SanitizeOptions {
+            iccp_to_srgb: true,
+            ..SanitizeOptions::default()
+        };
+
+        let (sanitized, report) = sanitize(&original, &options).unwrap();
+
+        assert!(report.converted_to_srgb.unwrap().ends_with("gAMA 0.45455"));
+        let types: Vec<_> = sanitized
+            .chunks
+            .iter()
+            .map(|c| c.chunk_type.to_string())
+            .collect();
+        assert_eq!(types, vec!["IHDR", "sRGB", "IDAT", "IEND"]);
+        // gAMA 1/2.2 is close to sRGB except for the toe of the curve
+        assert_eq!(
+            sanitized.get_raw_buffer().unwrap().pixel(0, 0),
+            [3, 129, 250]
+        );
+        assert!(verify(&original, &sanitized, &options).is_ok());
+    }
+}
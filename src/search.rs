@@ -0,0 +1,470 @@
+use crate::embed::{extract_lsb, LsbLayout};
+use crate::png::compression::{decompress, zlib_stream_len};
+use crate::png::writer::write_png;
+use crate::png::{is_limit_error, ColorType, PixelBuffer, Png};
+use base64::Engine;
+use regex::bytes::Regex;
+use std::error::Error;
+use std::fmt;
+use std::sync::LazyLock;
+
+/// File signatures worth reporting when they turn up inside an image.
+const MAGIC_NUMBERS: &[(&str, &[u8])] = &[
+    ("PNG image", b"\x89PNG\r\n\x1a\n"),
+    ("JPEG image", b"\xff\xd8\xff"),
+    ("GIF image", b"GIF87a"),
+    ("GIF image", b"GIF89a"),
+    ("ZIP archive", b"PK\x03\x04"),
+    ("7-Zip archive", b"7z\xbc\xaf\x27\x1c"),
+    ("RAR archive", b"Rar!\x1a\x07"),
+    ("gzip data", b"\x1f\x8b\x08"),
+    ("bzip2 data", b"BZh91AY&SY"),
+    ("xz data", b"\xfd7zXZ\x00"),
+    ("tar archive", b"ustar"),
+    ("PDF document", b"%PDF-"),
+    ("ELF executable", b"\x7fELF"),
+    ("Java class", b"\xca\xfe\xba\xbe"),
+    ("SQLite database", b"SQLite format 3\x00"),
+    ("RIFF container", b"RIFF"),
+    ("Ogg stream", b"OggS"),
+    ("MP3 with ID3 tag", b"ID3"),
+    ("TIFF image", b"II*\x00"),
+    ("TIFF image", b"MM\x00*"),
+];
+
+/// Shortest run of printable characters searched for base64 and hex blobs, which is also the
+/// shortest blob either pattern matches.
+const ENCODED_MIN_LENGTH: usize = 16;
+
+static BASE64: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[A-Za-z0-9+/]{16,}={0,2}").unwrap());
+static HEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:[0-9a-fA-F]{2}){8,}").unwrap());
+
+/// Matches the usual capture-the-flag formats, such as `CTF{...}` and `picoCTF{...}`.
+pub const FLAG_PATTERN: &str = r"[A-Za-z0-9_]{2,16}\{[ -z|~]{1,128}\}";
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Shortest run of printable characters reported as a string
+    pub min_length: usize,
+    pub patterns: Vec<Regex>,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            patterns: vec![Regex::new(FLAG_PATTERN).unwrap()],
+        }
+    }
+}
+
+/// A stream of bytes taken from the file, such as a chunk payload or the decompressed image
+/// data.
+#[derive(Debug)]
+pub struct Layer {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchKind {
+    String,
+    Pattern(String),
+    Base64,
+    Hex,
+    Magic(&'static str),
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchKind::String => write!(f, "string"),
+            MatchKind::Pattern(pattern) => write!(f, "pattern {}", pattern),
+            MatchKind::Base64 => write!(f, "base64"),
+            MatchKind::Hex => write!(f, "hex"),
+            MatchKind::Magic(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub layer: String,
+    /// Offset of the match within the layer
+    pub offset: usize,
+    pub kind: MatchKind,
+    /// The match, or for encoded blobs what it decodes to
+    pub preview: String,
+}
+
+/// Searches every layer of the file for strings, patterns, encoded blobs and magic numbers.
+pub fn search(png: &Png, options: &SearchOptions) -> Result<Vec<Match>, Box<dyn Error>> {
+    Ok(layers(png)?
+        .iter()
+        .flat_map(|layer| search_layer(layer, options))
+        .collect())
+}
+
+/// Collects the file as written, every chunk payload, decompressed image and text data, data
+/// hidden after the image's zlib stream, the least significant bits of each channel and any
+/// trailing bytes. Layers that cannot be decoded are left out.
+pub fn layers(png: &Png) -> Result<Vec<Layer>, Box<dyn Error>> {
+    let mut layers = vec![];
+    let mut file = vec![];
+    write_png(png, &mut file)?;
+    layers.push(Layer {
+        name: String::from("file"),
+        data: file,
+    });
+
+    // The text size limit is shared by all chunks, like when parsing them
+    let mut text_budget = png.limits.max_text_size;
+    for (i, chunk) in png.chunks.iter().enumerate() {
+        let Some(data) = &chunk.data else {
+            continue;
+        };
+        let name = format!("chunk {} ({})", i, chunk.chunk_type);
+        if let Some(text) = decompress_text(&chunk.chunk_type.to_string(), data, text_budget)? {
+            text_budget -= text.len();
+            layers.push(Layer {
+                name: format!("{} text", name),
+                data: text,
+            });
+        }
+        layers.push(Layer {
+            name,
+            data: data.clone(),
+        });
+    }
+
+    if let Ok(data) = png.decompress_idat_data() {
+        layers.push(Layer {
+            name: String::from("decompressed IDAT"),
+            data,
+        });
+    }
+    if let Some(data) = after_zlib_stream(png) {
+        layers.push(Layer {
+            name: String::from("IDAT after zlib stream"),
+            data,
+        });
+    }
+
+    if let Ok(buffer) = png.get_raw_buffer() {
+        let indexed = matches!(
+            png.ihdr().map(|ihdr| ihdr.color_type),
+            Some(ColorType::Indexed)
+        );
+        layers.extend(lsb_layers(&buffer, indexed));
+    }
+
+    if let Some(extra_bytes) = &png.extra_bytes {
+        layers.push(Layer {
+            name: String::from("extra bytes"),
+            data: extra_bytes.clone(),
+        });
+    }
+
+    Ok(layers)
+}
+
+/// The compressed text of zTXt and iTXt chunks, without going through the chunk parsers so that
+/// malformed keywords do not hide the text. Corrupt text is left out, text decompressing past
+/// `limit` bytes is an error.
+fn decompress_text(
+    chunk_type: &str,
+    data: &[u8],
+    limit: usize,
+) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let Some(compressed) = compressed_text(chunk_type, data) else {
+        return Ok(None);
+    };
+
+    match decompress(compressed, limit) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) if is_limit_error(&e) => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+fn compressed_text<'a>(chunk_type: &str, data: &'a [u8]) -> Option<&'a [u8]> {
+    let keyword_end = data.iter().position(|&b| b == 0)?;
+    match chunk_type {
+        "zTXt" => data.get(keyword_end + 2..),
+        "iTXt" if data.get(keyword_end + 1) == Some(&1) => {
+            let rest = data.get(keyword_end + 3..)?;
+            let language_end = rest.iter().position(|&b| b == 0)?;
+            let rest = &rest[language_end + 1..];
+            let translated_end = rest.iter().position(|&b| b == 0)?;
+            Some(&rest[translated_end + 1..])
+        }
+        _ => None,
+    }
+}
+
+/// Bit 0 of each channel and of all channels interleaved, packed most significant bit first.
+/// The samples are taken as stored, so 16-bit images give the low bit of the low byte and
+/// indexed images that of the palette indices.
+fn lsb_layers(buffer: &PixelBuffer, indexed: bool) -> Vec<Layer> {
+    let names: &[&str] = match buffer.channels {
+        1 if indexed => &["index"],
+        1 => &["gray"],
+        2 => &["gray", "a"],
+        3 => &["r", "g", "b"],
+        _ => &["r", "g", "b", "a"],
+    };
+
+    let mut layers: Vec<Layer> = names
+        .iter()
+        .enumerate()
+        .map(|(c, name)| Layer {
+            name: format!("LSB {}", name),
+            data: extract_lsb(
+                buffer,
+                &LsbLayout {
+                    channels: vec![c as u8],
+                    ..LsbLayout::all_channels(buffer.channels)
+                },
+            ),
+        })
+        .collect();
+
+    if buffer.channels > 1 {
+        layers.push(Layer {
+            name: format!("LSB {}", names.concat()),
+            data: extract_lsb(buffer, &LsbLayout::all_channels(buffer.channels)),
+        });
+    }
+
+    layers
+}
+
+/// The bytes stored in IDAT chunks after the end of the zlib stream, which decoders never read.
+fn after_zlib_stream(png: &Png) -> Option<Vec<u8>> {
+    let data: Vec<u8> = png
+        .chunks
+        .iter()
+        .filter(|chunk| chunk.chunk_type == "IDAT")
+        .flat_map(|chunk| chunk.data.iter().flatten().copied())
+        .collect();
+    let length = zlib_stream_len(&data, png.limits.max_decompressed_size).ok()?;
+    (length < data.len()).then(|| data[length..].to_vec())
+}
+
+pub fn search_layer(layer: &Layer, options: &SearchOptions) -> Vec<Match> {
+    let data = &layer.data;
+    let mut matches = vec![];
+    let mut push = |offset: usize, kind: MatchKind, preview: String| {
+        matches.push(Match {
+            layer: layer.name.clone(),
+            offset,
+            kind,
+            preview,
+        })
+    };
+
+    for (name, magic) in MAGIC_NUMBERS {
+        let mut start = 0;
+        while let Some(position) = find(&data[start..], magic) {
+            let offset = start + position;
+            // The file's own signature
+            if !(layer.name == "file" && offset == 0) {
+                push(offset, MatchKind::Magic(name), preview(magic));
+            }
+            start = offset + 1;
+        }
+    }
+
+    for pattern in &options.patterns {
+        for found in pattern.find_iter(data) {
+            push(
+                found.start(),
+                MatchKind::Pattern(pattern.as_str().to_string()),
+                preview(found.as_bytes()),
+            );
+        }
+    }
+
+    for (start, string) in printable_strings(data, options.min_length) {
+        push(start, MatchKind::String, preview(string));
+    }
+    for (start, string) in printable_strings(data, ENCODED_MIN_LENGTH) {
+        for found in HEX.find_iter(string) {
+            if let Some(decoded) = decode_hex(found.as_bytes()).filter(|d| looks_meaningful(d)) {
+                push(start + found.start(), MatchKind::Hex, preview(&decoded));
+            }
+        }
+        for found in BASE64.find_iter(string) {
+            let decoded = base64::engine::general_purpose::STANDARD.decode(found.as_bytes());
+            if let Some(decoded) = decoded.ok().filter(|d| looks_meaningful(d)) {
+                push(start + found.start(), MatchKind::Base64, preview(&decoded));
+            }
+        }
+    }
+
+    matches.sort_by_key(|found| found.offset);
+    matches
+}
+
+/// Runs of printable ASCII of at least `min_length` bytes, with their offsets.
+fn printable_strings(data: &[u8], min_length: usize) -> Vec<(usize, &[u8])> {
+    let is_printable = |b: &u8| b.is_ascii_graphic() || *b == b' ' || *b == b'\t';
+    let mut strings = vec![];
+    let mut start = 0;
+    for run in data.split(|b| !is_printable(b)) {
+        if run.len() >= min_length.max(1) {
+            strings.push((start, run));
+        }
+        start += run.len() + 1;
+    }
+    strings
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn decode_hex(text: &[u8]) -> Option<Vec<u8>> {
+    text.chunks_exact(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Decoded blobs are only reported when they are mostly text or start with a magic number, as
+/// long runs of letters often happen to be valid base64.
+fn looks_meaningful(decoded: &[u8]) -> bool {
+    let printable = decoded
+        .iter()
+        .filter(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+        .count();
+    printable * 10 >= decoded.len() * 9
+        || MAGIC_NUMBERS
+            .iter()
+            .any(|(_, magic)| decoded.starts_with(magic))
+}
+
+/// Up to 64 bytes, with non-printable bytes escaped.
+fn preview(bytes: &[u8]) -> String {
+    let mut preview: String = bytes
+        .iter()
+        .take(64)
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect();
+    if bytes.len() > 64 {
+        preview.push_str("...");
+    }
+    preview
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{chunk, ihdr};
+    use crate::png::compression::compress;
+    use crate::png::Limits;
+
+    fn layer(data: &[u8]) -> Layer {
+        Layer {
+            name: String::from("test"),
+            data: data.to_vec(),
+        }
+    }
+
+    fn kinds(matches: &[Match]) -> Vec<(usize, MatchKind)> {
+        matches.iter().map(|m| (m.offset, m.kind.clone())).collect()
+    }
+
+    #[test]
+    fn test_search_layer() {
+        let data = b"\x00\x01CTF{hidden}\x00PK\x03\x04\x00aGVsbG8gd29ybGQgYWdhaW4=\x00";
+
+        let matches = search_layer(&layer(data), &SearchOptions::default());
+
+        assert_eq!(
+            kinds(&matches),
+            vec![
+                (2, MatchKind::Pattern(FLAG_PATTERN.to_string())),
+                (2, MatchKind::String),
+                (14, MatchKind::Magic("ZIP archive")),
+                (19, MatchKind::String),
+                (19, MatchKind::Base64),
+            ]
+        );
+        assert_eq!(matches[0].preview, "CTF{hidden}");
+        assert_eq!(matches[4].preview, "hello world again");
+    }
+
+    #[test]
+    fn test_hex_blob() {
+        let matches = search_layer(
+            &layer(b"x 666c61677b6865787d y"),
+            &SearchOptions {
+                min_length: 4,
+                patterns: vec![],
+            },
+        );
+
+        assert_eq!(matches[1].kind, MatchKind::Hex);
+        assert_eq!(matches[1].offset, 2);
+        assert_eq!(matches[1].preview, "flag{hex}");
+    }
+
+    #[test]
+    fn test_encoded_blob_longer_min_length() {
+        let matches = search_layer(
+            &layer(b"\x00aGVsbG8gd29ybGQgYWdhaW4=\x00"),
+            &SearchOptions {
+                min_length: 100,
+                patterns: vec![],
+            },
+        );
+
+        assert_eq!(kinds(&matches), vec![(1, MatchKind::Base64)]);
+    }
+
+    #[test]
+    fn test_lsb_layers_16_bit() {
+        // The high bytes are all 1, the low bits spell out "A"
+        let samples = [0, 1, 0, 0, 0, 0, 0, 1].map(|bit| 0x0100 | bit);
+        let mut buffer = PixelBuffer::new(8, 1, 1, 16);
+        for (x, sample) in samples.into_iter().enumerate() {
+            buffer.set_sample(x as u32, 0, 0, sample);
+        }
+        let png = Png::new(vec![ihdr(8, 1, 16, 0), chunk(b"IDAT", &[])], None)
+            .with_image_data(&buffer)
+            .unwrap();
+
+        let layers = layers(&png).unwrap();
+        let lsb = layers
+            .iter()
+            .find(|layer| layer.name == "LSB gray")
+            .unwrap();
+
+        assert_eq!(lsb.data, b"A");
+    }
+
+    #[test]
+    fn test_text_limit() {
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend(compress(&[b'a'; 100]).unwrap());
+        let png = Png::new(vec![chunk(b"zTXt", &ztxt)], None).with_limits(Limits {
+            max_text_size: 50,
+            ..Limits::default()
+        });
+
+        let error = layers(&png).unwrap_err();
+
+        assert!(error
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(is_limit_error));
+    }
+
+    #[test]
+    fn test_preview() {
+        assert_eq!(preview(b"a\x00\xff"), "a\\x00\\xff");
+        assert_eq!(preview(&[b'a'; 65]), format!("{}...", "a".repeat(64)));
+    }
+}
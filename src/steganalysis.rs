@@ -0,0 +1,411 @@
+use crate::png::{ColorType, PixelBuffer, Png};
+use serde::Serialize;
+use std::error::Error;
+
+/// Estimates of least significant bit embedding for every channel of an image.
+#[derive(Debug, Serialize)]
+pub struct SteganalysisReport {
+    pub channels: Vec<ChannelReport>,
+    /// Mean of the per-channel estimates, as a fraction of the samples carrying payload bits
+    pub estimated_rate: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChannelReport {
+    pub channel: String,
+    pub chi_square: ChiSquare,
+    /// Embedding rate estimated by RS analysis, `None` when there is no solution, as for
+    /// images too small or flat or with every sample embedded
+    pub rs: Option<f64>,
+    /// Embedding rate estimated by sample pair analysis
+    pub sample_pair: Option<f64>,
+}
+
+impl ChannelReport {
+    /// The mean of the RS and sample pair estimates.
+    pub fn estimated_rate(&self) -> Option<f64> {
+        let estimates: Vec<f64> = self.rs.iter().chain(&self.sample_pair).copied().collect();
+        (!estimates.is_empty()).then(|| estimates.iter().sum::<f64>() / estimates.len() as f64)
+    }
+}
+
+/// Westfeld and Pfitzmann's chi-square attack on pairs of values.
+#[derive(Debug, Serialize)]
+pub struct ChiSquare {
+    pub statistic: f64,
+    pub degrees_of_freedom: usize,
+    /// Probability that the pairs of values were equalized by embedding
+    pub probability: f64,
+    /// The probability over the rows scanned so far, one entry per row
+    pub curve: Vec<f64>,
+    /// Fraction of rows scanned before the probability falls below one half, an estimate of the
+    /// payload length for sequential embedding
+    pub embedded_fraction: f64,
+}
+
+/// Runs the chi-square attack, RS analysis and sample pair analysis on every channel.
+///
+/// The stored samples are used: palette indices for indexed images and the low byte of 16-bit
+/// samples, where LSB embedding makes its changes.
+pub fn steganalysis(png: &Png) -> Result<SteganalysisReport, Box<dyn Error>> {
+    let ihdr = png.ihdr().ok_or("IHDR chunk not found")?;
+    let buffer = png.get_raw_buffer()?;
+    let names: &[&str] = match ihdr.color_type {
+        ColorType::Indexed => &["Index"],
+        ColorType::Grayscale => &["Gray"],
+        ColorType::GrayscaleAlpha => &["Gray", "Alpha"],
+        ColorType::Truecolor => &["Red", "Green", "Blue"],
+        ColorType::TruecolorAlpha => &["Red", "Green", "Blue", "Alpha"],
+    };
+
+    let channels: Vec<ChannelReport> = names
+        .iter()
+        .enumerate()
+        .map(|(c, name)| {
+            let plane = Plane::new(&buffer, c as u8);
+            ChannelReport {
+                channel: name.to_string(),
+                chi_square: chi_square(&plane),
+                rs: rs_analysis(&plane),
+                sample_pair: sample_pair_analysis(&plane),
+            }
+        })
+        .collect();
+
+    let estimates: Vec<f64> = channels
+        .iter()
+        .filter_map(ChannelReport::estimated_rate)
+        .collect();
+    let estimated_rate =
+        (!estimates.is_empty()).then(|| estimates.iter().sum::<f64>() / estimates.len() as f64);
+
+    Ok(SteganalysisReport {
+        channels,
+        estimated_rate,
+    })
+}
+
+/// One channel's samples, reduced to 8 bits by keeping the low byte.
+struct Plane {
+    width: usize,
+    height: usize,
+    values: Vec<u8>,
+}
+
+impl Plane {
+    fn new(buffer: &PixelBuffer, channel: u8) -> Self {
+        Self {
+            width: buffer.width as usize,
+            height: buffer.height as usize,
+            values: buffer.channel(channel).iter().map(|v| v as u8).collect(),
+        }
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.values.chunks_exact(self.width.max(1))
+    }
+}
+
+fn chi_square(plane: &Plane) -> ChiSquare {
+    let mut histogram = [0u64; 256];
+    let mut curve = Vec::with_capacity(plane.height);
+    for row in plane.rows() {
+        for &value in row {
+            histogram[value as usize] += 1;
+        }
+        curve.push(chi_square_histogram(&histogram).2);
+    }
+    let (statistic, degrees_of_freedom, probability) = chi_square_histogram(&histogram);
+
+    let embedded_rows = curve.iter().take_while(|&&p| p >= 0.5).count();
+    ChiSquare {
+        statistic,
+        degrees_of_freedom,
+        probability,
+        embedded_fraction: embedded_rows as f64 / plane.height.max(1) as f64,
+        curve,
+    }
+}
+
+/// Compares the even value of each pair against the pair's mean, which LSB embedding tends
+/// towards. Returns the statistic, the degrees of freedom and the embedding probability.
+fn chi_square_histogram(histogram: &[u64; 256]) -> (f64, usize, f64) {
+    let mut statistic = 0.0;
+    let mut pairs = 0;
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected > 0.0 {
+            statistic += (pair[0] as f64 - expected).powi(2) / expected;
+            pairs += 1;
+        }
+    }
+
+    if pairs < 2 {
+        return (statistic, 0, 0.0);
+    }
+    let degrees_of_freedom = pairs - 1;
+    let probability = upper_incomplete_gamma(degrees_of_freedom as f64 / 2.0, statistic / 2.0);
+    (statistic, degrees_of_freedom, probability)
+}
+
+/// Fridrich's RS analysis with groups of four horizontally adjacent samples and the mask
+/// `[0, 1, 1, 0]`.
+fn rs_analysis(plane: &Plane) -> Option<f64> {
+    const MASK: [bool; 4] = [false, true, true, false];
+
+    let flip = |value: i32| value ^ 1;
+    let shift = |value: i32| ((value + 1) ^ 1) - 1;
+    let smoothness =
+        |group: &[i32; 4]| -> i32 { group.windows(2).map(|w| (w[1] - w[0]).abs()).sum() };
+
+    // Regular and singular group counts for the mask and its negation, on the image as is and
+    // with every LSB flipped
+    let mut counts = [[0u64; 4]; 2];
+    let mut groups = 0u64;
+    for row in plane.rows() {
+        for group in row.chunks_exact(4) {
+            groups += 1;
+            for (flipped, counts) in counts.iter_mut().enumerate() {
+                let group: [i32; 4] = std::array::from_fn(|i| {
+                    let value = group[i] as i32;
+                    if flipped == 1 {
+                        flip(value)
+                    } else {
+                        value
+                    }
+                });
+                let original = smoothness(&group);
+                let positive: [i32; 4] =
+                    std::array::from_fn(|i| if MASK[i] { flip(group[i]) } else { group[i] });
+                let negative: [i32; 4] =
+                    std::array::from_fn(|i| if MASK[i] { shift(group[i]) } else { group[i] });
+
+                match smoothness(&positive).cmp(&original) {
+                    std::cmp::Ordering::Greater => counts[0] += 1,
+                    std::cmp::Ordering::Less => counts[1] += 1,
+                    _ => {}
+                }
+                match smoothness(&negative).cmp(&original) {
+                    std::cmp::Ordering::Greater => counts[2] += 1,
+                    std::cmp::Ordering::Less => counts[3] += 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if groups == 0 {
+        return None;
+    }
+    let rate = |count: u64| count as f64 / groups as f64;
+    let [r_m, s_m, r_neg, s_neg] = counts[0].map(rate);
+    let [r_m1, s_m1, r_neg1, s_neg1] = counts[1].map(rate);
+
+    let d0 = r_m - s_m;
+    let d1 = r_m1 - s_m1;
+    let d_neg0 = r_neg - s_neg;
+    let d_neg1 = r_neg1 - s_neg1;
+
+    let a = 2.0 * (d1 + d0);
+    let b = d_neg0 - d_neg1 - d1 - 3.0 * d0;
+    let c = d0 - d_neg0;
+    let x = smaller_root(a, b, c)?;
+    if (x - 0.5).abs() < f64::EPSILON {
+        return None;
+    }
+
+    Some((x / (x - 0.5)).clamp(0.0, 1.0))
+}
+
+/// Dumitrescu, Wu and Wang's sample pair analysis on horizontally adjacent samples.
+fn sample_pair_analysis(plane: &Plane) -> Option<f64> {
+    let (mut x, mut y, mut k, mut pairs) = (0u64, 0u64, 0u64, 0u64);
+    for row in plane.rows() {
+        for pair in row.windows(2) {
+            let (u, v) = (pair[0], pair[1]);
+            pairs += 1;
+            if (v % 2 == 0 && u < v) || (v % 2 == 1 && u > v) {
+                x += 1;
+            }
+            if (v % 2 == 0 && u > v) || (v % 2 == 1 && u < v) {
+                y += 1;
+            }
+            if u / 2 == v / 2 {
+                k += 1;
+            }
+        }
+    }
+
+    if k == 0 {
+        return None;
+    }
+    let a = k as f64 / 2.0;
+    let b = 2.0 * x as f64 - pairs as f64;
+    let c = y as f64 - x as f64;
+
+    smaller_root(a, b, c).map(|p| p.clamp(0.0, 1.0))
+}
+
+/// The root of `a x^2 + b x + c` with the smaller magnitude, or `None` if there is none.
+fn smaller_root(a: f64, b: f64, c: f64) -> Option<f64> {
+    if a.abs() < f64::EPSILON {
+        return (b.abs() > f64::EPSILON).then(|| -c / b);
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let roots = [
+        (-b + discriminant.sqrt()) / (2.0 * a),
+        (-b - discriminant.sqrt()) / (2.0 * a),
+    ];
+    Some(if roots[0].abs() < roots[1].abs() {
+        roots[0]
+    } else {
+        roots[1]
+    })
+}
+
+/// The regularized upper incomplete gamma function Q(a, x), the chi-square survival function
+/// for `a = k / 2` and `x = statistic / 2`.
+fn upper_incomplete_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+
+    if x < a + 1.0 {
+        // Series for the lower function
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..1000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        let lower = sum * (-x + a * x.ln() - ln_gamma(a)).exp();
+        return (1.0 - lower).clamp(0.0, 1.0);
+    }
+
+    // Continued fraction, evaluated with the modified Lentz method
+    let tiny = 1e-300;
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / tiny;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for i in 1..1000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < tiny {
+            d = tiny;
+        }
+        c = b + an / c;
+        if c.abs() < tiny {
+            c = tiny;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+
+    (h * (-x + a * x.ln() - ln_gamma(a)).exp()).clamp(0.0, 1.0)
+}
+
+/// Lanczos approximation of ln Γ(x).
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1.0 + i as f64);
+    }
+
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A smooth surface with some noise, standing in for a photograph.
+    fn cover(width: usize, height: usize) -> Plane {
+        let mut state = 12345u32;
+        let values = (0..width * height)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((state >> 16) % 7) as f64 + ((state >> 8) % 7) as f64 - 6.0;
+                let (x, y) = ((i % width) as f64, (i / width) as f64);
+                let surface = 128.0 + 60.0 * (x / 23.0).sin() * (y / 31.0).cos() + x / 4.0;
+                (surface + noise / 2.0).round().clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        Plane {
+            width,
+            height,
+            values,
+        }
+    }
+
+    /// Replaces the LSB of about one sample in `every` with a random bit.
+    fn embed(values: &mut [u8], every: u32) {
+        let mut state = 987654321u32;
+        for value in values {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            if (state >> 8).is_multiple_of(every) {
+                *value = (*value & !1) | ((state >> 20) & 1) as u8;
+            }
+        }
+    }
+
+    #[test]
+    fn test_upper_incomplete_gamma() {
+        // Chi-square survival function with 2 degrees of freedom is exp(-x / 2)
+        assert!((upper_incomplete_gamma(1.0, 1.5) - (-1.5f64).exp()).abs() < 1e-9);
+        assert!((upper_incomplete_gamma(5.0, 2.0) - 0.947346982656289).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_chi_square() {
+        // Only even values, as in an image scaled up from 7 bits
+        let mut plane = cover(200, 200);
+        plane.values.iter_mut().for_each(|value| *value &= !1);
+        assert!(chi_square(&plane).probability < 0.01);
+
+        // Embedding in the first 30 rows only
+        embed(&mut plane.values[..200 * 30], 1);
+        let chi_square = chi_square(&plane);
+
+        assert!(chi_square.probability < 0.01);
+        assert_eq!(chi_square.curve.len(), 200);
+        assert!(chi_square.curve[10] > 0.9);
+        assert!(chi_square.embedded_fraction > 0.1 && chi_square.embedded_fraction < 0.3);
+    }
+
+    #[test]
+    fn test_rs_and_sample_pair_analysis() {
+        let mut plane = cover(200, 200);
+        assert!(rs_analysis(&plane).unwrap() < 0.05);
+        assert!(sample_pair_analysis(&plane).unwrap() < 0.05);
+
+        // Half of the samples carry a random bit, so about a quarter are changed
+        embed(&mut plane.values, 2);
+
+        assert!((rs_analysis(&plane).unwrap() - 0.5).abs() < 0.1);
+        assert!((sample_pair_analysis(&plane).unwrap() - 0.5).abs() < 0.1);
+    }
+}
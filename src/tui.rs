@@ -15,8 +15,11 @@
 
 #![allow(clippy::enum_glob_use, clippy::wildcard_imports)]
 
-use pngcheck::png::Chunk;
 use pngcheck::png::Png;
+use pngcheck::png::{
+    Chunk, ChunkField, ColorType, ParsedChunk, PixelBuffer, Report, Severity, ValidateOptions,
+    HEADER,
+};
 use std::{error::Error, io, io::stdout};
 
 use color_eyre::config::HookBuilder;
@@ -31,6 +34,33 @@ const CHUNK_HEADER_BG: Color = tailwind::BLUE.c950;
 const NORMAL_ROW_COLOR: Color = tailwind::SLATE.c950;
 const SELECTED_STYLE_FG: Color = tailwind::BLUE.c300;
 const TEXT_COLOR: Color = tailwind::SLATE.c200;
+const HEX_LENGTH_COLOR: Color = tailwind::AMBER.c400;
+const HEX_TYPE_COLOR: Color = tailwind::EMERALD.c400;
+const HEX_CRC_COLOR: Color = tailwind::ROSE.c400;
+const HEX_FIELD_COLORS: [Color; 2] = [tailwind::SKY.c400, tailwind::VIOLET.c400];
+const HEX_OFFSET_COLOR: Color = tailwind::SLATE.c500;
+const ERROR_COLOR: Color = tailwind::ROSE.c400;
+const WARNING_COLOR: Color = tailwind::AMBER.c400;
+const HEX_BYTES_PER_ROW: usize = 16;
+
+#[derive(PartialEq)]
+enum Pane {
+    Info,
+    Hex,
+    Image,
+}
+
+/// How the decoded image is shown in the image pane.
+struct ImageView {
+    /// 0 shows all channels, otherwise the 1-based channel index
+    channel: usize,
+    bit_plane: Option<u8>,
+    invert: bool,
+    /// Magnification as a power of two, negative values shrink the image
+    zoom: i32,
+    pan_x: i64,
+    pan_y: i64,
+}
 
 struct StatefulList<'a> {
     state: ListState,
@@ -46,7 +76,16 @@ struct StatefulList<'a> {
 /// Check the drawing logic for items on how to specify the highlighting style for selected items.
 struct App<'a> {
     png: &'a Png,
+    report: Report,
+    /// Parsed once with the file's limits, which text chunks share
+    parsed: Vec<ParsedChunk>,
     items: StatefulList<'a>,
+    pane: Pane,
+    hex_scroll: usize,
+    buffer: Option<Result<PixelBuffer, String>>,
+    /// Samples as stored, for bit planes of the original bit depth and of palette indices
+    raw_buffer: Option<PixelBuffer>,
+    image_view: ImageView,
 }
 
 pub fn tui(png: &Png) -> Result<(), Box<dyn Error>> {
@@ -95,16 +134,128 @@ impl<'a> App<'a> {
     fn new(png: &'a Png) -> Self {
         Self {
             png,
+            // Decoding the image data takes long for large images, so it waits for the image pane
+            report: png.validate(&ValidateOptions { decode: false }),
+            parsed: png.parsed_chunks(),
             items: StatefulList::with_items(&png.chunks),
+            pane: Pane::Info,
+            hex_scroll: 0,
+            buffer: None,
+            raw_buffer: None,
+            image_view: ImageView {
+                channel: 0,
+                bit_plane: None,
+                invert: false,
+                zoom: 0,
+                pan_x: 0,
+                pan_y: 0,
+            },
         }
     }
 
     fn go_top(&mut self) {
         self.items.state.select(Some(0));
+        self.hex_scroll = 0;
     }
 
     fn go_bottom(&mut self) {
         self.items.state.select(Some(self.items.items.len() - 1));
+        self.hex_scroll = 0;
+    }
+
+    fn next(&mut self) {
+        self.items.next();
+        self.hex_scroll = 0;
+    }
+
+    fn previous(&mut self) {
+        self.items.previous();
+        self.hex_scroll = 0;
+    }
+
+    fn toggle_pane(&mut self, pane: Pane) {
+        self.pane = if self.pane == pane { Pane::Info } else { pane };
+
+        if self.pane == Pane::Image && self.buffer.is_none() {
+            self.report = self.png.validate(&ValidateOptions::default());
+            self.buffer = Some(
+                self.png
+                    .get_buffer()
+                    .map(|buffer| buffer.to_8bit())
+                    .map_err(|e| e.to_string()),
+            );
+            self.raw_buffer = self.png.get_raw_buffer().ok();
+        }
+    }
+
+    fn channel_names(&self) -> &'static [&'static str] {
+        match self.png.ihdr().map(|ihdr| ihdr.color_type) {
+            Some(ColorType::Indexed) if self.image_view.bit_plane.is_some() => &["Index"],
+            Some(ColorType::Grayscale) => &["Gray"],
+            Some(ColorType::GrayscaleAlpha) => &["Gray", "Alpha"],
+            Some(ColorType::Truecolor) => &["Red", "Green", "Blue"],
+            _ => &["Red", "Green", "Blue", "Alpha"],
+        }
+    }
+
+    fn cycle_channel(&mut self) {
+        self.image_view.channel = (self.image_view.channel + 1) % (self.channel_names().len() + 1);
+    }
+
+    fn cycle_bit_plane(&mut self) {
+        let bit_depth = self.png.ihdr().map_or(8, |ihdr| ihdr.bit_depth);
+        self.image_view.bit_plane = match self.image_view.bit_plane {
+            None => Some(bit_depth - 1),
+            Some(0) => None,
+            Some(plane) => Some(plane - 1),
+        };
+        // Indexed images have a single index channel while a bit plane is shown
+        if self.image_view.channel > self.channel_names().len() {
+            self.image_view.channel = 0;
+        }
+    }
+
+    fn zoom(&mut self, steps: i32) {
+        self.image_view.zoom = (self.image_view.zoom + steps).clamp(-6, 4);
+    }
+
+    fn pan(&mut self, x: i64, y: i64) {
+        // Pan by a tenth of a screen worth of image pixels at the current zoom
+        let step = (2f64.powi(-self.image_view.zoom) * 8.0).max(1.0) as i64;
+        self.image_view.pan_x = (self.image_view.pan_x + x * step).max(0);
+        self.image_view.pan_y = (self.image_view.pan_y + y * step).max(0);
+    }
+
+    fn reset_image_view(&mut self) {
+        self.image_view.zoom = 0;
+        self.image_view.pan_x = 0;
+        self.image_view.pan_y = 0;
+    }
+
+    fn scroll_hex(&mut self, rows: isize) {
+        let Some(i) = self.items.state.selected() else {
+            return;
+        };
+        let total_rows = hex_dump_len(&self.items.items[i]).div_ceil(HEX_BYTES_PER_ROW);
+        self.hex_scroll = self
+            .hex_scroll
+            .saturating_add_signed(rows)
+            .min(total_rows.saturating_sub(1));
+    }
+
+    /// The file offset of a chunk, assuming chunks follow the signature back to back if the
+    /// chunk does not know its own offset.
+    fn chunk_offset(&self, index: usize) -> usize {
+        match self.items.items[index].offset {
+            Some(offset) => offset as usize,
+            None => {
+                HEADER.len()
+                    + self.items.items[..index]
+                        .iter()
+                        .map(hex_dump_len)
+                        .sum::<usize>()
+            }
+        }
     }
 }
 
@@ -119,10 +270,26 @@ impl App<'_> {
                     match key.code {
                         Char('q') | Esc => return Ok(()),
                         Char('h') | Left => self.items.unselect(),
-                        Char('j') | Down => self.items.next(),
-                        Char('k') | Up => self.items.previous(),
+                        Char('j') | Down => self.next(),
+                        Char('k') | Up => self.previous(),
                         Char('g') => self.go_top(),
                         Char('G') => self.go_bottom(),
+                        Char('x') => self.toggle_pane(Pane::Hex),
+                        Char('i') => self.toggle_pane(Pane::Image),
+                        Char('J') if self.pane == Pane::Image => self.pan(0, 1),
+                        Char('K') if self.pane == Pane::Image => self.pan(0, -1),
+                        Char('H') => self.pan(-1, 0),
+                        Char('L') => self.pan(1, 0),
+                        Char('J') => self.scroll_hex(1),
+                        Char('K') => self.scroll_hex(-1),
+                        PageDown => self.scroll_hex(16),
+                        PageUp => self.scroll_hex(-16),
+                        Char('c') => self.cycle_channel(),
+                        Char('b') => self.cycle_bit_plane(),
+                        Char('v') => self.image_view.invert = !self.image_view.invert,
+                        Char('+') | Char('=') => self.zoom(1),
+                        Char('-') => self.zoom(-1),
+                        Char('0') => self.reset_image_view(),
                         _ => {}
                     }
                 }
@@ -153,7 +320,11 @@ impl Widget for &mut App<'_> {
 
         render_title(header_area, buf);
         self.render_chunks(upper_item_list_area, buf);
-        self.render_info(lower_item_list_area, buf);
+        match (&self.pane, self.items.state.selected()) {
+            (Pane::Hex, Some(i)) => self.render_hex(i, lower_item_list_area, buf),
+            (Pane::Image, _) => self.render_image(lower_item_list_area, buf),
+            _ => self.render_info(lower_item_list_area, buf),
+        }
         render_footer(footer_area, buf);
     }
 }
@@ -187,14 +358,17 @@ impl App<'_> {
             .enumerate()
             .map(|(i, chunk)| {
                 ListItem::new(Line::styled(
-                    format!("{}: {}", i, chunk.chunk_type),
+                    match chunk.offset {
+                        Some(offset) => format!("{}: {} @ {:#x}", i, chunk.chunk_type, offset),
+                        None => format!("{}: {}", i, chunk.chunk_type),
+                    },
                     if self.items.state.selected() == Some(i) {
                         Style::default()
                             .add_modifier(Modifier::BOLD)
                             .add_modifier(Modifier::REVERSED)
                             .fg(SELECTED_STYLE_FG)
                     } else {
-                        Style::default().fg(TEXT_COLOR)
+                        Style::default().fg(self.chunk_color(i))
                     },
                 ))
             })
@@ -218,11 +392,34 @@ impl App<'_> {
         StatefulWidget::render(items, inner_area, buf, &mut self.items.state);
     }
 
+    /// Chunks with errors or warnings stand out in the list.
+    fn chunk_color(&self, index: usize) -> Color {
+        match self.report.for_chunk(index).map(|f| f.severity).max() {
+            Some(Severity::Error) => ERROR_COLOR,
+            Some(Severity::Warning) => WARNING_COLOR,
+            _ => TEXT_COLOR,
+        }
+    }
+
     fn render_info(&self, area: Rect, buf: &mut Buffer) {
-        let info = if let Some(i) = self.items.state.selected() {
-            self.items.items[i].to_string()
-        } else {
+        // The selected chunk's findings follow its fields, those about the whole file are shown
+        // until a chunk is selected
+        let selected = self.items.state.selected();
+        let mut lines: Vec<String> = selected
+            .map(|i| self.items.items[i].describe(&self.parsed[i]))
+            .into_iter()
+            .collect();
+        lines.extend(
+            self.report
+                .findings
+                .iter()
+                .filter(|f| f.chunk == selected)
+                .map(|f| f.to_string()),
+        );
+        let info = if lines.is_empty() {
             "Nothing to see here...".to_string()
+        } else {
+            lines.join("\n")
         };
 
         // We show the list item's info under the list in this paragraph
@@ -255,6 +452,221 @@ impl App<'_> {
     }
 }
 
+impl App<'_> {
+    fn render_hex(&self, index: usize, area: Rect, buf: &mut Buffer) {
+        let chunk = &self.items.items[index];
+
+        let outer_hex_block = Block::new()
+            .borders(Borders::NONE)
+            .title_alignment(Alignment::Center)
+            .title("Hex Dump")
+            .fg(TEXT_COLOR)
+            .bg(CHUNK_HEADER_BG);
+        let inner_hex_block = Block::new()
+            .borders(Borders::NONE)
+            .padding(Padding::horizontal(1))
+            .bg(NORMAL_ROW_COLOR);
+
+        let outer_hex_area = area;
+        let inner_hex_area = outer_hex_block.inner(outer_hex_area);
+        outer_hex_block.render(outer_hex_area, buf);
+
+        let fields = chunk.data_fields();
+        let mut legend = vec![
+            Span::styled("length ", Style::default().fg(HEX_LENGTH_COLOR)),
+            Span::styled("type ", Style::default().fg(HEX_TYPE_COLOR)),
+        ];
+        legend.extend(fields.iter().enumerate().map(|(i, field)| {
+            Span::styled(
+                format!("{} ", field.name),
+                Style::default().fg(HEX_FIELD_COLORS[i % HEX_FIELD_COLORS.len()]),
+            )
+        }));
+        if fields.is_empty() {
+            legend.push(Span::styled("data ", Style::default().fg(TEXT_COLOR)));
+        }
+        legend.push(Span::styled("crc", Style::default().fg(HEX_CRC_COLOR)));
+
+        let mut lines = vec![Line::from(legend), Line::default()];
+
+        // Only the visible rows are built, IDAT chunks can be megabytes long
+        let offset = self.chunk_offset(index);
+        let len = hex_dump_len(chunk);
+        let visible_rows = inner_hex_area.height.saturating_sub(2) as usize;
+        let first = self.hex_scroll * HEX_BYTES_PER_ROW;
+        for row_start in (first..len).step_by(HEX_BYTES_PER_ROW).take(visible_rows) {
+            let row_end = (row_start + HEX_BYTES_PER_ROW).min(len);
+            let mut spans = vec![Span::styled(
+                format!("{:08x}  ", offset + row_start),
+                Style::default().fg(HEX_OFFSET_COLOR),
+            )];
+            for i in row_start..row_start + HEX_BYTES_PER_ROW {
+                if i < row_end {
+                    spans.push(Span::styled(
+                        format!("{:02x} ", hex_dump_byte(chunk, i)),
+                        Style::default().fg(hex_dump_color(chunk, &fields, i)),
+                    ));
+                } else {
+                    spans.push(Span::raw("   "));
+                }
+            }
+            spans.push(Span::raw(" "));
+            for i in row_start..row_end {
+                let byte = hex_dump_byte(chunk, i);
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                spans.push(Span::styled(
+                    c.to_string(),
+                    Style::default().fg(hex_dump_color(chunk, &fields, i)),
+                ));
+            }
+            lines.push(Line::from(spans));
+        }
+
+        Paragraph::new(lines)
+            .block(inner_hex_block)
+            .fg(TEXT_COLOR)
+            .render(inner_hex_area, buf);
+    }
+}
+
+impl App<'_> {
+    fn render_image(&self, area: Rect, buf: &mut Buffer) {
+        let view = &self.image_view;
+        let channel_names = self.channel_names();
+
+        let title = format!(
+            "Image - channel: {}, bit plane: {}{}, zoom: {}",
+            match view.channel {
+                0 => "All",
+                c => channel_names[c - 1],
+            },
+            view.bit_plane.map_or("all".to_string(), |p| p.to_string()),
+            if view.invert { ", inverted" } else { "" },
+            if view.zoom >= 0 {
+                format!("{}x", 1 << view.zoom)
+            } else {
+                format!("1/{}x", 1 << -view.zoom)
+            }
+        );
+        let outer_image_block = Block::new()
+            .borders(Borders::NONE)
+            .title_alignment(Alignment::Center)
+            .title(title)
+            .fg(TEXT_COLOR)
+            .bg(CHUNK_HEADER_BG);
+        let inner_image_block = Block::new().borders(Borders::NONE).bg(NORMAL_ROW_COLOR);
+
+        let outer_image_area = area;
+        let inner_image_area = outer_image_block.inner(outer_image_area);
+        outer_image_block.render(outer_image_area, buf);
+        inner_image_block.render(inner_image_area, buf);
+
+        let buffer = match &self.buffer {
+            Some(Ok(buffer)) => buffer,
+            Some(Err(e)) => {
+                Paragraph::new(format!("Failed to decode image: {}", e))
+                    .fg(TEXT_COLOR)
+                    .render(inner_image_area, buf);
+                return;
+            }
+            None => return,
+        };
+
+        // Each cell shows two vertically stacked pixels using the upper half block character
+        let scale = 2f64.powi(-view.zoom);
+        let pixel_at = |column: u16, row: u16| {
+            let x = view.pan_x + (column as f64 * scale) as i64;
+            let y = view.pan_y + (row as f64 * scale) as i64;
+            let in_bounds =
+                (0..buffer.width as i64).contains(&x) && (0..buffer.height as i64).contains(&y);
+            in_bounds.then(|| match (view.bit_plane, &self.raw_buffer) {
+                (Some(plane), Some(raw)) => {
+                    let bits: Vec<u8> = (0..raw.channels)
+                        .map(|c| ((raw.sample(x as u32, y as u32, c) >> plane) & 1) as u8 * 255)
+                        .collect();
+                    view.color(&bits)
+                }
+                _ => view.color(buffer.pixel(x as u32, y as u32)),
+            })
+        };
+
+        for cy in 0..inner_image_area.height {
+            for cx in 0..inner_image_area.width {
+                let top = pixel_at(cx, cy * 2);
+                let bottom = pixel_at(cx, cy * 2 + 1);
+                if top.is_none() && bottom.is_none() {
+                    continue;
+                }
+                let cell = buf.get_mut(inner_image_area.x + cx, inner_image_area.y + cy);
+                cell.set_char('▀')
+                    .set_fg(top.unwrap_or(NORMAL_ROW_COLOR))
+                    .set_bg(bottom.unwrap_or(NORMAL_ROW_COLOR));
+            }
+        }
+    }
+}
+
+impl ImageView {
+    /// Colors 8-bit samples, or the bits of a plane scaled to 0 and 255.
+    fn color(&self, samples: &[u8]) -> Color {
+        let transform = |value: u8| {
+            if self.invert {
+                255 - value
+            } else {
+                value
+            }
+        };
+
+        match self.channel {
+            0 if samples.len() >= 3 => Color::Rgb(
+                transform(samples[0]),
+                transform(samples[1]),
+                transform(samples[2]),
+            ),
+            0 => {
+                let l = transform(samples[0]);
+                Color::Rgb(l, l, l)
+            }
+            c => {
+                let l = transform(samples.get(c - 1).copied().unwrap_or(0));
+                Color::Rgb(l, l, l)
+            }
+        }
+    }
+}
+
+/// Number of bytes a chunk occupies in the file: length, type, data and CRC.
+fn hex_dump_len(chunk: &Chunk) -> usize {
+    12 + chunk.data_len()
+}
+
+fn hex_dump_byte(chunk: &Chunk, i: usize) -> u8 {
+    let data_len = chunk.data_len();
+    match i {
+        0..=3 => chunk.length.to_be_bytes()[i],
+        4..=7 => chunk.chunk_type.bytes()[i - 4],
+        _ if i < 8 + data_len => chunk.data.as_ref().unwrap()[i - 8],
+        _ => chunk.crc.to_be_bytes()[i - 8 - data_len],
+    }
+}
+
+fn hex_dump_color(chunk: &Chunk, fields: &[ChunkField], i: usize) -> Color {
+    let data_len = chunk.data_len();
+    match i {
+        0..=3 => HEX_LENGTH_COLOR,
+        4..=7 => HEX_TYPE_COLOR,
+        _ if i < 8 + data_len => fields
+            .iter()
+            .position(|field| field.range.contains(&(i - 8)))
+            .map_or(TEXT_COLOR, |f| HEX_FIELD_COLORS[f % HEX_FIELD_COLORS.len()]),
+        _ => HEX_CRC_COLOR,
+    }
+}
+
 fn render_title(area: Rect, buf: &mut Buffer) {
     Paragraph::new("PNG Chunk Viewer")
         .bold()
@@ -263,13 +675,15 @@ fn render_title(area: Rect, buf: &mut Buffer) {
 }
 
 fn render_footer(area: Rect, buf: &mut Buffer) {
-    Paragraph::new("\nUse ↓↑ to move, g/G to go top/bottom.")
-        .centered()
-        .render(area, buf);
+    Paragraph::new(
+        "Use ↓↑ to move, g/G to go top/bottom, x to toggle hex, J/K or PgDn/PgUp to scroll.\ni to toggle image, c/b to cycle channel/bit plane, v to invert, +/- to zoom, HJKL to pan.",
+    )
+    .centered()
+    .render(area, buf);
 }
 
 impl StatefulList<'_> {
-    fn with_items(items: &Vec<Chunk>) -> StatefulList {
+    fn with_items(items: &Vec<Chunk>) -> StatefulList<'_> {
         StatefulList {
             state: ListState::default(),
             items,
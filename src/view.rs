@@ -1,73 +1,166 @@
-use crate::png::scanline::Scanline;
-use crate::png::{ColorType, Pixel, IHDR};
-use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use crate::color::ColorSpace;
+use crate::png::{Bkgd, PixelBuffer, Png, Transparency};
+use image::{DynamicImage, Rgba, RgbaImage};
 use std::cmp;
+use std::error::Error;
 use viuer::{print, Config};
 
-pub fn view_image(scanlines: &[Scanline], ihdr: &IHDR) {
+/// What transparent pixels are composited over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Background {
+    /// Keep the alpha channel and let the terminal show through
+    #[default]
+    None,
+    /// The `bKGD` color, or a checkerboard if the file has none
+    File,
+    Checkerboard,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Convert samples to sRGB using the file's `gAMA`, `cHRM` and `sRGB` chunks
+    pub color_manage: bool,
+    pub background: Background,
+}
+
+/// Size of a checkerboard square in image pixels.
+const CHECKERBOARD_SIZE: u32 = 8;
+
+enum Fill {
+    Color([f64; 3]),
+    Checkerboard,
+}
+
+impl Fill {
+    fn at(&self, x: u32, y: u32) -> [f64; 3] {
+        match self {
+            Fill::Color(color) => *color,
+            Fill::Checkerboard
+                if (x / CHECKERBOARD_SIZE + y / CHECKERBOARD_SIZE).is_multiple_of(2) =>
+            {
+                [0.8; 3]
+            }
+            Fill::Checkerboard => [0.6; 3],
+        }
+    }
+}
+
+pub fn view_image(buffer: &PixelBuffer) {
     let config = Config {
-        transparent: ihdr.color_type.has_alpha(),
-        width: Some(cmp::min(ihdr.width, 80)),
+        transparent: matches!(buffer.channels, 2 | 4),
+        width: Some(cmp::min(buffer.width, 80)),
         ..Config::default()
     };
 
-    let image = create_dynamic_image(scanlines, ihdr);
+    let image = create_dynamic_image(buffer);
     print(&image, &config).unwrap();
 }
 
-fn create_dynamic_image(scanlines: &[Scanline], ihdr: &IHDR) -> DynamicImage {
-    match ihdr.color_type {
-        ColorType::TruecolorAlpha | ColorType::Indexed => {
-            let mut img = RgbaImage::new(ihdr.width, ihdr.height);
-            for (i, scanline) in scanlines.iter().enumerate() {
-                for (j, pixel) in scanline.pixels.iter().enumerate() {
-                    let (r, g, b, a) = match pixel {
-                        Pixel::TruecolorAlpha(r, g, b, a) => (*r, *g, *b, *a),
-                        _ => unreachable!(),
-                    };
-                    img.put_pixel(j as u32, i as u32, image::Rgba([r, g, b, a]));
-                }
-            }
-            DynamicImage::ImageRgba8(img)
-        }
-        ColorType::Truecolor => {
-            let mut img = RgbImage::new(ihdr.width, ihdr.height);
-            for (i, scanline) in scanlines.iter().enumerate() {
-                for (j, pixel) in scanline.pixels.iter().enumerate() {
-                    let (r, g, b) = match pixel {
-                        Pixel::Truecolor(r, g, b) => (*r, *g, *b),
-                        _ => unreachable!(),
-                    };
-                    img.put_pixel(j as u32, i as u32, image::Rgb([r, g, b]));
-                }
-            }
-            DynamicImage::ImageRgb8(img)
+pub fn view_png(png: &Png, options: &RenderOptions) -> Result<(), Box<dyn Error>> {
+    let image = render_image(png, options)?;
+    let config = Config {
+        transparent: image.color().has_alpha(),
+        width: Some(cmp::min(image.width(), 80)),
+        ..Config::default()
+    };
+
+    print(&image, &config)?;
+    Ok(())
+}
+
+pub fn create_dynamic_image(buffer: &PixelBuffer) -> DynamicImage {
+    buffer.to_dynamic_image()
+}
+
+/// Renders the image the way a browser shows it: samples are converted to sRGB and transparent
+/// pixels, including `tRNS` color keys, are composited over the background.
+pub fn render_image(png: &Png, options: &RenderOptions) -> Result<DynamicImage, Box<dyn Error>> {
+    let buffer = png.get_buffer()?;
+    if !options.color_manage && options.background == Background::None {
+        return Ok(create_dynamic_image(&buffer));
+    }
+
+    let color_space = if options.color_manage {
+        ColorSpace::from_png(png)
+    } else {
+        ColorSpace::srgb()
+    };
+    let max = match buffer.bit_depth {
+        16 => 65535.0,
+        bit_depth => ((1u32 << bit_depth) - 1) as f64,
+    };
+    let color_key = match png.trns() {
+        Some(Transparency::Grey(gray)) => Some(vec![gray]),
+        Some(Transparency::Rgb(r, g, b)) => Some(vec![r, g, b]),
+        _ => None,
+    };
+    let fill = match options.background {
+        Background::None => None,
+        Background::File => Some(
+            background_color(png, max)
+                .map(|color| Fill::Color(color_space.to_srgb(color)))
+                .unwrap_or(Fill::Checkerboard),
+        ),
+        Background::Checkerboard => Some(Fill::Checkerboard),
+    };
+
+    let channels = buffer.channels as usize;
+    let mut samples = [0u16; 4];
+    let mut image = RgbaImage::new(buffer.width, buffer.height);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        for (channel, sample) in samples.iter_mut().take(channels).enumerate() {
+            *sample = buffer.sample(x, y, channel as u8);
         }
-        ColorType::Grayscale => {
-            let mut img = GrayImage::new(ihdr.width, ihdr.height);
-            for (i, scanline) in scanlines.iter().enumerate() {
-                for (j, pixel) in scanline.pixels.iter().enumerate() {
-                    let l = match pixel {
-                        Pixel::Grayscale(l) => *l,
-                        _ => unreachable!(),
-                    };
-                    img.put_pixel(j as u32, i as u32, image::Luma([l]));
-                }
+        let samples = &samples[..channels];
+
+        let (color, alpha) = match *samples {
+            [gray] => ([gray; 3], None),
+            [gray, alpha] => ([gray; 3], Some(alpha)),
+            [r, g, b] => ([r, g, b], None),
+            [r, g, b, alpha, ..] => ([r, g, b], Some(alpha)),
+            [] => ([0; 3], None),
+        };
+        let alpha = match alpha {
+            Some(alpha) => alpha as f64 / max,
+            None if color_key.as_deref() == Some(samples) => 0.0,
+            None => 1.0,
+        };
+
+        let mut color = color_space.to_srgb(color.map(|value| value as f64 / max));
+        let mut alpha = alpha;
+        if let Some(fill) = &fill {
+            let background = fill.at(x, y);
+            for (value, background) in color.iter_mut().zip(background) {
+                *value = *value * alpha + background * (1.0 - alpha);
             }
-            DynamicImage::ImageLuma8(img)
+            alpha = 1.0;
         }
-        ColorType::GrayscaleAlpha => {
-            let mut img = GrayAlphaImage::new(ihdr.width, ihdr.height);
-            for (i, scanline) in scanlines.iter().enumerate() {
-                for (j, pixel) in scanline.pixels.iter().enumerate() {
-                    let (l, a) = match pixel {
-                        Pixel::GrayscaleAlpha(l, a) => (*l, *a),
-                        _ => unreachable!(),
-                    };
-                    img.put_pixel(j as u32, i as u32, image::LumaA([l, a]));
-                }
-            }
-            DynamicImage::ImageLumaA8(img)
+
+        let to_u8 = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        *pixel = Rgba([
+            to_u8(color[0]),
+            to_u8(color[1]),
+            to_u8(color[2]),
+            to_u8(alpha),
+        ]);
+    }
+
+    let image = DynamicImage::ImageRgba8(image);
+    if fill.is_some() {
+        return Ok(DynamicImage::ImageRgb8(image.to_rgb8()));
+    }
+
+    Ok(image)
+}
+
+/// The `bKGD` color as normalized samples of the file's color space.
+fn background_color(png: &Png, max: f64) -> Option<[f64; 3]> {
+    match png.bkgd()? {
+        Bkgd::Grayscale(gray) => Some([gray as f64 / max; 3]),
+        Bkgd::Rgb(r, g, b) => Some([r as f64 / max, g as f64 / max, b as f64 / max]),
+        Bkgd::Indexed(index) => {
+            let (r, g, b) = *png.plte()?.entries.get(index as usize)?;
+            Some([r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0])
         }
     }
 }
@@ -0,0 +1,210 @@
+//! Conformance tests against PngSuite, the standard PNG test images. Every image listed in
+//! `pngsuite/expected.txt` either decodes to the listed pixel hash, which is also what the
+//! `image` crate decodes it to, or is rejected with the listed error category.
+
+use pngcheck::parse_bytes;
+use pngcheck::png::{ColorType, ParsedChunk, Png, ValidateOptions};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// How a corrupt file is rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ErrorCategory {
+    Signature,
+    Checksum,
+    Header,
+    MissingImageData,
+    ImageData,
+}
+
+impl ErrorCategory {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "signature" => Self::Signature,
+            "checksum" => Self::Checksum,
+            "header" => Self::Header,
+            "missing-image-data" => Self::MissingImageData,
+            "image-data" => Self::ImageData,
+            _ => panic!("Unknown error category {}", name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Expected {
+    Hash(u64),
+    Error(ErrorCategory),
+}
+
+fn suite_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/pngsuite")
+}
+
+fn expected() -> BTreeMap<String, Expected> {
+    let table = fs::read_to_string(suite_dir().join("expected.txt")).unwrap();
+    table
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let expected = match fields[1..] {
+                ["error", category] => Expected::Error(ErrorCategory::from_name(category)),
+                [hash] => Expected::Hash(u64::from_str_radix(hash, 16).unwrap()),
+                _ => panic!("Malformed line {:?}", line),
+            };
+            (fields[0].to_string(), expected)
+        })
+        .collect()
+}
+
+fn read(name: &str) -> Vec<u8> {
+    fs::read(suite_dir().join(name)).unwrap()
+}
+
+/// FNV-1a over the dimensions and the pixels as big-endian RGBA16.
+fn hash(width: u32, height: u32, pixels: impl Iterator<Item = [u16; 4]>) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let bytes = width
+        .to_be_bytes()
+        .into_iter()
+        .chain(height.to_be_bytes())
+        .chain(pixels.flat_map(|pixel| pixel.into_iter().flat_map(u16::to_be_bytes)));
+    for byte in bytes {
+        hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn image_crate_hash(data: &[u8]) -> u64 {
+    let image = image::load_from_memory_with_format(data, image::ImageFormat::Png)
+        .unwrap()
+        .to_rgba16();
+    hash(image.width(), image.height(), image.pixels().map(|p| p.0))
+}
+
+/// Decodes like the `image` crate does: samples scaled to 16 bits, and the tRNS color of
+/// grayscale and truecolor images made fully transparent.
+fn pngcheck_hash(png: &Png) -> u64 {
+    let raw = png.get_raw_buffer().unwrap();
+    let buffer = png.get_buffer().unwrap();
+    let max = (1u32 << buffer.bit_depth) - 1;
+    let key: Option<Vec<u16>> = match png.ihdr().unwrap().color_type {
+        ColorType::Grayscale | ColorType::Truecolor => png
+            .chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type == "tRNS")
+            .and_then(|chunk| chunk.data.as_deref())
+            .map(|data| {
+                data.chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    .collect()
+            }),
+        _ => None,
+    };
+
+    let pixels = (0..buffer.height).flat_map(|y| (0..buffer.width).map(move |x| (x, y)));
+    let pixels = pixels.map(|(x, y)| {
+        let sample = |c: u8| (buffer.sample(x, y, c) as u32 * 65535 / max) as u16;
+        let mut pixel = match buffer.channels {
+            1 => [sample(0), sample(0), sample(0), 65535],
+            2 => [sample(0), sample(0), sample(0), sample(1)],
+            3 => [sample(0), sample(1), sample(2), 65535],
+            _ => [sample(0), sample(1), sample(2), sample(3)],
+        };
+        let stored: Vec<u16> = (0..raw.channels).map(|c| raw.sample(x, y, c)).collect();
+        if key.as_deref() == Some(&stored[..]) {
+            pixel[3] = 0;
+        }
+        pixel
+    });
+    hash(buffer.width, buffer.height, pixels)
+}
+
+/// The first problem found in a file, or None if it decodes.
+fn error_category(data: &[u8]) -> Option<ErrorCategory> {
+    let png = match parse_bytes(data) {
+        Ok(png) => png,
+        Err(e) if e.to_string().contains("signature") => return Some(ErrorCategory::Signature),
+        Err(e) => panic!("Unexpected error {}", e),
+    };
+
+    if !png.chunks.iter().all(|chunk| chunk.validate_checksum()) {
+        return Some(ErrorCategory::Checksum);
+    }
+    match png
+        .chunks
+        .iter()
+        .find(|c| c.chunk_type == "IHDR")
+        .map(|c| c.parse())
+    {
+        Some(ParsedChunk::IHDR(_)) => {}
+        _ => return Some(ErrorCategory::Header),
+    }
+    if !png.chunks.iter().any(|chunk| chunk.chunk_type == "IDAT") {
+        return Some(ErrorCategory::MissingImageData);
+    }
+    if png.get_buffer().is_err() {
+        return Some(ErrorCategory::ImageData);
+    }
+    None
+}
+
+#[test]
+fn test_every_image_is_listed() {
+    let expected = expected();
+    let mut files: Vec<_> = fs::read_dir(suite_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".png"))
+        .collect();
+    files.sort();
+
+    assert_eq!(files, expected.keys().cloned().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_valid_images_decode_like_image_crate() {
+    for (name, expected) in expected() {
+        let Expected::Hash(expected) = expected else {
+            continue;
+        };
+        let data = read(&name);
+        let png = parse_bytes(&data).unwrap();
+
+        assert_eq!(error_category(&data), None, "{}", name);
+        assert_eq!(
+            png.validate(&ValidateOptions::default()).findings,
+            [],
+            "{}",
+            name
+        );
+        assert_eq!(image_crate_hash(&data), expected, "image crate, {}", name);
+        assert_eq!(pngcheck_hash(&png), expected, "{}", name);
+    }
+}
+
+#[test]
+fn test_corrupt_images_are_rejected() {
+    for (name, expected) in expected() {
+        let Expected::Error(category) = expected else {
+            continue;
+        };
+
+        let data = read(&name);
+
+        assert_eq!(error_category(&data), Some(category), "{}", name);
+        if let Ok(png) = parse_bytes(&data) {
+            assert!(
+                !png.validate(&ValidateOptions::default()).is_valid(),
+                "{}",
+                name
+            );
+        }
+        assert!(
+            image::load_from_memory(&data).is_err(),
+            "image crate, {}",
+            name
+        );
+    }
+}